@@ -0,0 +1,16 @@
+-- Known SSH host keys (per connection, or per host/port when connection_id is NULL)
+CREATE TABLE known_hosts (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    connection_id UUID REFERENCES connections(id) ON DELETE CASCADE,
+    host VARCHAR(255) NOT NULL,
+    port INTEGER NOT NULL,
+    key_type VARCHAR(64) NOT NULL,
+    public_key TEXT NOT NULL,
+    fingerprint VARCHAR(128) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    UNIQUE NULLS NOT DISTINCT (user_id, connection_id, host, port, public_key)
+);
+
+CREATE INDEX idx_known_hosts_lookup ON known_hosts(user_id, host, port);
+CREATE INDEX idx_known_hosts_connection ON known_hosts(connection_id);
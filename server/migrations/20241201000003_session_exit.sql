@@ -0,0 +1,3 @@
+-- How the remote shell ended, recorded when the SSH channel closes on its own
+ALTER TABLE sessions ADD COLUMN exit_code INTEGER;
+ALTER TABLE sessions ADD COLUMN exit_signal VARCHAR(32);
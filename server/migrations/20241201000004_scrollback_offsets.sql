@@ -0,0 +1,16 @@
+-- Absolute stream offset of each scrollback chunk, so chunks are append-only
+ALTER TABLE scrollback_chunks ADD COLUMN start_offset BIGINT;
+
+UPDATE scrollback_chunks c
+SET start_offset = o.start_offset
+FROM (
+    SELECT id,
+           COALESCE(SUM(LENGTH(data)) OVER (
+               PARTITION BY session_id ORDER BY chunk_index
+               ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING
+           ), 0) AS start_offset
+    FROM scrollback_chunks
+) o
+WHERE c.id = o.id;
+
+ALTER TABLE scrollback_chunks ALTER COLUMN start_offset SET NOT NULL;
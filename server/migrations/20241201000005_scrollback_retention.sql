@@ -0,0 +1,9 @@
+-- Uncompressed length of each chunk, and whether `data` holds zstd-compressed bytes
+ALTER TABLE scrollback_chunks ADD COLUMN size INTEGER;
+ALTER TABLE scrollback_chunks ADD COLUMN compressed BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE scrollback_chunks SET size = LENGTH(data);
+
+ALTER TABLE scrollback_chunks ALTER COLUMN size SET NOT NULL;
+
+CREATE INDEX idx_scrollback_created ON scrollback_chunks(created_at);
@@ -0,0 +1,4 @@
+-- Free-form labels for grouping connections, e.g. for fleet runs
+ALTER TABLE connections ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_connections_tags ON connections USING GIN (tags);
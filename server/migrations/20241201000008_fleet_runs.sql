@@ -0,0 +1,31 @@
+-- One command run across many connections, kept for later review
+CREATE TABLE fleet_runs (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    command TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'running',  -- running, complete
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_fleet_runs_user ON fleet_runs(user_id, created_at);
+
+-- Result of a fleet run on one connection; output is capped
+CREATE TABLE fleet_run_hosts (
+    id UUID PRIMARY KEY,
+    run_id UUID NOT NULL REFERENCES fleet_runs(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    connection_id UUID REFERENCES connections(id) ON DELETE SET NULL,
+    connection_name VARCHAR(255) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',  -- pending, running, succeeded, failed, timed_out, error
+    exit_code INTEGER,
+    exit_signal VARCHAR(32),
+    error TEXT,
+    stdout BYTEA NOT NULL DEFAULT '',
+    stderr BYTEA NOT NULL DEFAULT '',
+    output_truncated BOOLEAN NOT NULL DEFAULT FALSE,
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_fleet_run_hosts_run ON fleet_run_hosts(run_id, position);
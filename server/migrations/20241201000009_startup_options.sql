@@ -0,0 +1,13 @@
+-- How the startup command runs: typed into the shell, exec'd instead of a shell,
+-- or typed once output matches startup_prompt (a regular expression)
+ALTER TABLE connections ADD COLUMN startup_mode VARCHAR(16) NOT NULL DEFAULT 'typed'
+    CHECK (startup_mode IN ('typed', 'exec', 'prompt'));
+ALTER TABLE connections ADD COLUMN startup_prompt TEXT;
+
+-- Values substituted for ${name} in the startup command
+CREATE TABLE connection_variables (
+    connection_id UUID NOT NULL REFERENCES connections(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (connection_id, name)
+);
@@ -0,0 +1,18 @@
+-- TERM requested with the PTY; NULL means the server default (xterm-256color)
+ALTER TABLE connections ADD COLUMN term_type VARCHAR(64);
+
+-- Environment variables sent before the shell starts (e.g. LANG, LC_ALL)
+CREATE TABLE connection_env (
+    connection_id UUID NOT NULL REFERENCES connections(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (connection_id, name)
+);
+
+-- Terminal modes sent with the PTY request, by RFC 4254 name (e.g. ECHO, VERASE)
+CREATE TABLE connection_pty_modes (
+    connection_id UUID NOT NULL REFERENCES connections(id) ON DELETE CASCADE,
+    mode VARCHAR(16) NOT NULL,
+    value BIGINT NOT NULL CHECK (value >= 0 AND value <= 4294967295),
+    PRIMARY KEY (connection_id, mode)
+);
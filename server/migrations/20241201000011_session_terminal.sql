@@ -0,0 +1,7 @@
+-- Terminal the session was created with
+ALTER TABLE sessions ADD COLUMN name VARCHAR(255);
+ALTER TABLE sessions ADD COLUMN cols INTEGER NOT NULL DEFAULT 80;
+ALTER TABLE sessions ADD COLUMN rows INTEGER NOT NULL DEFAULT 24;
+ALTER TABLE sessions ADD COLUMN pixel_width INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN pixel_height INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN term_type VARCHAR(64);
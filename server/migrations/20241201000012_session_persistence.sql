@@ -0,0 +1,3 @@
+-- Run the shell inside tmux or screen so it outlives the SSH connection
+ALTER TABLE connections ADD COLUMN persistence VARCHAR(16) NOT NULL DEFAULT 'none'
+    CHECK (persistence IN ('none', 'tmux', 'screen'));
@@ -0,0 +1,10 @@
+-- Offset just past the last output ever stored, so offsets survive retention deleting every chunk
+ALTER TABLE sessions ADD COLUMN scrollback_end BIGINT NOT NULL DEFAULT 0;
+
+UPDATE sessions s SET scrollback_end = c.end_offset
+FROM (
+    SELECT session_id, MAX(start_offset + size) AS end_offset
+    FROM scrollback_chunks
+    GROUP BY session_id
+) c
+WHERE c.session_id = s.id;
@@ -1,11 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
 use sqlx::PgPool;
+use tokio::sync::RwLock;
+use tonic::body::BoxBody;
 use tonic::{Request, Response, Status};
-use tracing::info;
+use tower::{Layer, Service};
+use tracing::{debug, info};
+use uuid::Uuid;
 
 use crate::db::ApiKey;
 use crate::proto::auth_server::Auth;
 use crate::proto::{ApiKeyRequest, AuthResponse};
 
+/// How long a validated API key is trusted before hitting the database again
+pub const DEFAULT_AUTH_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// gRPC path prefix of the Auth service, which must be reachable without a key
+const AUTH_SERVICE_PREFIX: &str = "/hive.Auth/";
+
+/// User resolved from the bearer API key, stored in request extensions
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// Read the authenticated user id placed on the request by [`ApiKeyAuthLayer`]
+#[allow(clippy::result_large_err)]
+pub fn authenticated_user_id<T>(request: &Request<T>) -> Result<Uuid, Status> {
+    request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.user_id)
+        .ok_or_else(|| Status::unauthenticated("Missing or invalid API key"))
+}
+
+struct CachedUser {
+    user: AuthenticatedUser,
+    validated_at: Instant,
+}
+
+/// Validates bearer API keys against the database with a short-lived cache
+pub struct ApiKeyAuthenticator {
+    pool: PgPool,
+    ttl: Duration,
+    cache: RwLock<HashMap<String, CachedUser>>,
+}
+
+impl ApiKeyAuthenticator {
+    pub fn new(pool: PgPool, ttl: Duration) -> Self {
+        Self {
+            pool,
+            ttl,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn extract_bearer(headers: &http::HeaderMap) -> Option<&str> {
+        headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+    }
+
+    pub async fn authenticate(&self, headers: &http::HeaderMap) -> Result<AuthenticatedUser, Status> {
+        let key = Self::extract_bearer(headers)
+            .ok_or_else(|| Status::unauthenticated("Missing API key"))?;
+        let key_hash = ApiKey::hash_key(key);
+
+        {
+            let cache = self.cache.read().await;
+            if let Some(cached) = cache.get(&key_hash) {
+                if cached.validated_at.elapsed() < self.ttl {
+                    return Ok(cached.user.clone());
+                }
+            }
+        }
+
+        match ApiKey::validate(&self.pool, key).await {
+            Ok(Some((_, user))) => {
+                debug!("API key validated for user: {}", user.username);
+                let user = AuthenticatedUser {
+                    user_id: user.id,
+                    username: user.username,
+                };
+
+                let mut cache = self.cache.write().await;
+                cache.retain(|_, cached| cached.validated_at.elapsed() < self.ttl);
+                cache.insert(
+                    key_hash,
+                    CachedUser {
+                        user: user.clone(),
+                        validated_at: Instant::now(),
+                    },
+                );
+
+                Ok(user)
+            }
+            Ok(None) => {
+                self.cache.write().await.remove(&key_hash);
+                info!("Rejected request with invalid API key");
+                Err(Status::unauthenticated("Invalid or revoked API key"))
+            }
+            Err(e) => {
+                tracing::error!("Database error during API key validation: {}", e);
+                Err(Status::internal("Internal error"))
+            }
+        }
+    }
+}
+
+/// Tower layer that authenticates every gRPC call except the Auth service
+#[derive(Clone)]
+pub struct ApiKeyAuthLayer {
+    authenticator: Arc<ApiKeyAuthenticator>,
+}
+
+impl ApiKeyAuthLayer {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_ttl(pool, DEFAULT_AUTH_CACHE_TTL)
+    }
+
+    pub fn with_ttl(pool: PgPool, ttl: Duration) -> Self {
+        Self {
+            authenticator: Arc::new(ApiKeyAuthenticator::new(pool, ttl)),
+        }
+    }
+}
+
+impl<S> Layer<S> for ApiKeyAuthLayer {
+    type Service = ApiKeyAuthMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiKeyAuthMiddleware {
+            inner,
+            authenticator: self.authenticator.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiKeyAuthMiddleware<S> {
+    inner: S,
+    authenticator: Arc<ApiKeyAuthenticator>,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        // The clone may not be ready, so keep the polled service and leave the clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let authenticator = self.authenticator.clone();
+
+        Box::pin(async move {
+            if req.uri().path().starts_with(AUTH_SERVICE_PREFIX) {
+                return inner.call(req).await;
+            }
+
+            match authenticator.authenticate(req.headers()).await {
+                Ok(user) => {
+                    req.extensions_mut().insert(user);
+                    inner.call(req).await
+                }
+                Err(status) => Ok(status.into_http()),
+            }
+        })
+    }
+}
+
 pub struct AuthService {
     pool: PgPool,
 }
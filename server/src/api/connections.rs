@@ -1,14 +1,101 @@
+use std::collections::HashMap;
+
 use sqlx::PgPool;
 use tonic::{Request, Response, Status};
 use tracing::info;
 use uuid::Uuid;
 
-use crate::db::Connection;
+use super::authenticated_user_id;
+use crate::db::{Connection, ConnectionEnv, ConnectionPtyMode, ConnectionVariable, SshKey};
 use crate::proto::connections_server::Connections;
 use crate::proto::{
     Connection as ProtoConnection, ConnectionListResponse, CreateConnectionRequest,
     DeleteConnectionRequest, Empty, UpdateConnectionRequest,
 };
+use crate::ssh::pty_mode;
+use crate::terminal::{prompt_regex, Persistence, StartupMode};
+
+/// Longest variable, environment variable or TERM name the database accepts
+const MAX_NAME_LEN: usize = 64;
+
+/// Startup, variable, terminal and persistence settings from a create or update request, validated
+struct ConnectionSettings {
+    startup_mode: StartupMode,
+    startup_prompt: Option<String>,
+    variables: Vec<(String, String)>,
+    term_type: Option<String>,
+    env: Vec<(String, String)>,
+    pty_modes: Vec<(String, u32)>,
+    persistence: Option<Persistence>,
+}
+
+impl ConnectionSettings {
+    #[allow(clippy::result_large_err)]
+    fn validate(
+        startup_mode: &str,
+        startup_prompt: &str,
+        variables: HashMap<String, String>,
+        term_type: &str,
+        env: HashMap<String, String>,
+        pty_modes: HashMap<String, u32>,
+        persistence: &str,
+    ) -> Result<Self, Status> {
+        // Prompt mode needs a valid pattern; other modes do not keep one
+        let startup_mode = StartupMode::parse(startup_mode)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let startup_prompt = if startup_mode == StartupMode::Prompt {
+            prompt_regex(startup_prompt).map_err(|e| Status::invalid_argument(e.to_string()))?;
+            Some(startup_prompt.to_string())
+        } else {
+            None
+        };
+
+        let term_type = term_type.trim();
+        if term_type.len() > MAX_NAME_LEN || !term_type.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(Status::invalid_argument(format!("Invalid TERM: {}", term_type)));
+        }
+
+        let mut pty_modes: Vec<_> = pty_modes.into_iter().collect();
+        for (mode, _) in &pty_modes {
+            if pty_mode(mode).is_none() {
+                return Err(Status::invalid_argument(format!("Unknown terminal mode: {}", mode)));
+            }
+        }
+        pty_modes.sort();
+
+        let persistence =
+            Persistence::parse(persistence).map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        Ok(Self {
+            startup_mode,
+            startup_prompt,
+            variables: Self::identifiers(variables, "variable")?,
+            term_type: Some(term_type.to_string()).filter(|t| !t.is_empty()),
+            env: Self::identifiers(env, "environment variable")?,
+            pty_modes,
+            persistence,
+        })
+    }
+
+    /// Names are shell-style identifiers, sorted for storage
+    #[allow(clippy::result_large_err)]
+    fn identifiers(
+        values: HashMap<String, String>,
+        what: &str,
+    ) -> Result<Vec<(String, String)>, Status> {
+        let mut values: Vec<_> = values.into_iter().collect();
+        for (name, _) in &values {
+            let valid = name.len() <= MAX_NAME_LEN
+                && name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(Status::invalid_argument(format!("Invalid {} name: {}", what, name)));
+            }
+        }
+        values.sort();
+        Ok(values)
+    }
+}
 
 pub struct ConnectionsService {
     pool: PgPool,
@@ -19,16 +106,12 @@ impl ConnectionsService {
         Self { pool }
     }
 
-    fn extract_user_id(request: &Request<impl std::fmt::Debug>) -> Result<Uuid, Status> {
-        request
-            .metadata()
-            .get("x-user-id")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| Uuid::parse_str(s).ok())
-            .ok_or_else(|| Status::unauthenticated("Missing or invalid user ID"))
-    }
-
-    fn connection_to_proto(conn: Connection) -> ProtoConnection {
+    fn connection_to_proto(
+        conn: Connection,
+        variables: Vec<(String, String)>,
+        env: Vec<(String, String)>,
+        pty_modes: Vec<(String, u32)>,
+    ) -> ProtoConnection {
         ProtoConnection {
             id: conn.id.to_string(),
             name: conn.name,
@@ -38,23 +121,123 @@ impl ConnectionsService {
             ssh_key_id: conn.ssh_key_id.map(|id| id.to_string()).unwrap_or_default(),
             startup_command: conn.startup_command.unwrap_or_default(),
             created_at: conn.created_at.to_rfc3339(),
+            tags: conn.tags,
+            startup_mode: conn.startup_mode,
+            startup_prompt: conn.startup_prompt.unwrap_or_default(),
+            variables: variables.into_iter().collect(),
+            term_type: conn.term_type.unwrap_or_default(),
+            env: env.into_iter().collect(),
+            pty_modes: pty_modes.into_iter().collect(),
+            persistence: conn.persistence,
+        }
+    }
+
+    /// The connection as the API shows it, with its variables, environment and terminal modes
+    async fn load_proto(&self, conn: Connection) -> Result<ProtoConnection, Status> {
+        let db_error = |e: crate::HiveError| Status::internal(format!("Database error: {}", e));
+
+        let variables = ConnectionVariable::list_for_connection(&self.pool, conn.id)
+            .await
+            .map_err(db_error)?;
+        let env = ConnectionEnv::list_for_connection(&self.pool, conn.id)
+            .await
+            .map_err(db_error)?;
+        let pty_modes = ConnectionPtyMode::list_for_connection(&self.pool, conn.id)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Self::connection_to_proto(
+            conn,
+            variables.into_iter().map(|v| (v.name, v.value)).collect(),
+            env.into_iter().map(|e| (e.name, e.value)).collect(),
+            pty_modes.into_iter().map(|m| (m.mode, m.value as u32)).collect(),
+        ))
+    }
+
+    /// Store everything beyond the basic fields, returning the connection as the API shows it
+    async fn save_settings(
+        &self,
+        id: Uuid,
+        settings: ConnectionSettings,
+    ) -> Result<ProtoConnection, Status> {
+        let db_error = |e: crate::HiveError| Status::internal(format!("Database error: {}", e));
+
+        let mode = settings.startup_mode.as_str();
+        Connection::set_startup(&self.pool, id, mode, settings.startup_prompt.as_deref())
+            .await
+            .map_err(db_error)?;
+        Connection::set_term_type(&self.pool, id, settings.term_type.as_deref())
+            .await
+            .map_err(db_error)?;
+        let persistence = settings.persistence.as_ref().map_or("none", Persistence::as_str);
+        let connection = Connection::set_persistence(&self.pool, id, persistence)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| Status::not_found("Connection not found"))?;
+        ConnectionVariable::replace_for_connection(&self.pool, id, &settings.variables)
+            .await
+            .map_err(db_error)?;
+        ConnectionEnv::replace_for_connection(&self.pool, id, &settings.env)
+            .await
+            .map_err(db_error)?;
+        ConnectionPtyMode::replace_for_connection(&self.pool, id, &settings.pty_modes)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Self::connection_to_proto(
+            connection,
+            settings.variables,
+            settings.env,
+            settings.pty_modes,
+        ))
+    }
+
+    /// Trimmed, non-empty and unique, in the order given
+    fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+        let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+                normalized.push(tag.to_string());
+            }
         }
+        normalized
+    }
+
+    async fn owned_ssh_key_id(&self, user_id: Uuid, ssh_key_id: &str) -> Result<Option<Uuid>, Status> {
+        if ssh_key_id.is_empty() {
+            return Ok(None);
+        }
+
+        let id = Uuid::parse_str(ssh_key_id)
+            .map_err(|_| Status::invalid_argument("Invalid SSH key ID"))?;
+
+        let key = SshKey::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("SSH key not found"))?;
+
+        if key.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to use this SSH key"));
+        }
+
+        Ok(Some(id))
     }
 }
 
 #[tonic::async_trait]
 impl Connections for ConnectionsService {
     async fn list(&self, request: Request<Empty>) -> Result<Response<ConnectionListResponse>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
 
         let connections = Connection::list_for_user(&self.pool, user_id)
             .await
             .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
 
-        let proto_connections: Vec<ProtoConnection> = connections
-            .into_iter()
-            .map(Self::connection_to_proto)
-            .collect();
+        let mut proto_connections = Vec::with_capacity(connections.len());
+        for connection in connections {
+            proto_connections.push(self.load_proto(connection).await?);
+        }
 
         info!("Listed {} connections for user {}", proto_connections.len(), user_id);
 
@@ -67,17 +250,19 @@ impl Connections for ConnectionsService {
         &self,
         request: Request<CreateConnectionRequest>,
     ) -> Result<Response<ProtoConnection>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let req = request.into_inner();
 
-        let ssh_key_id = if req.ssh_key_id.is_empty() {
-            None
-        } else {
-            Some(
-                Uuid::parse_str(&req.ssh_key_id)
-                    .map_err(|_| Status::invalid_argument("Invalid SSH key ID"))?,
-            )
-        };
+        let ssh_key_id = self.owned_ssh_key_id(user_id, &req.ssh_key_id).await?;
+        let settings = ConnectionSettings::validate(
+            &req.startup_mode,
+            &req.startup_prompt,
+            req.variables,
+            &req.term_type,
+            req.env,
+            req.pty_modes,
+            &req.persistence,
+        )?;
 
         let startup_command = if req.startup_command.is_empty() {
             None
@@ -98,16 +283,24 @@ impl Connections for ConnectionsService {
         .await
         .map_err(|e| Status::internal(format!("Failed to create connection: {}", e)))?;
 
+        let tags = Self::normalize_tags(req.tags);
+        if !tags.is_empty() {
+            Connection::set_tags(&self.pool, connection.id, &tags)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to create connection: {}", e)))?;
+        }
+        let connection = self.save_settings(connection.id, settings).await?;
+
         info!("Created connection {} for user {}", connection.id, user_id);
 
-        Ok(Response::new(Self::connection_to_proto(connection)))
+        Ok(Response::new(connection))
     }
 
     async fn update(
         &self,
         request: Request<UpdateConnectionRequest>,
     ) -> Result<Response<ProtoConnection>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let req = request.into_inner();
 
         let id = Uuid::parse_str(&req.id)
@@ -123,14 +316,16 @@ impl Connections for ConnectionsService {
             return Err(Status::permission_denied("Not authorized to update this connection"));
         }
 
-        let ssh_key_id = if req.ssh_key_id.is_empty() {
-            None
-        } else {
-            Some(
-                Uuid::parse_str(&req.ssh_key_id)
-                    .map_err(|_| Status::invalid_argument("Invalid SSH key ID"))?,
-            )
-        };
+        let ssh_key_id = self.owned_ssh_key_id(user_id, &req.ssh_key_id).await?;
+        let settings = ConnectionSettings::validate(
+            &req.startup_mode,
+            &req.startup_prompt,
+            req.variables,
+            &req.term_type,
+            req.env,
+            req.pty_modes,
+            &req.persistence,
+        )?;
 
         let startup_command = if req.startup_command.is_empty() {
             None
@@ -138,7 +333,7 @@ impl Connections for ConnectionsService {
             Some(req.startup_command.as_str())
         };
 
-        let connection = Connection::update(
+        Connection::update(
             &self.pool,
             id,
             &req.name,
@@ -152,13 +347,18 @@ impl Connections for ConnectionsService {
         .map_err(|e| Status::internal(format!("Failed to update connection: {}", e)))?
         .ok_or_else(|| Status::not_found("Connection not found"))?;
 
+        Connection::set_tags(&self.pool, id, &Self::normalize_tags(req.tags))
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update connection: {}", e)))?;
+        let connection = self.save_settings(id, settings).await?;
+
         info!("Updated connection {} for user {}", id, user_id);
 
-        Ok(Response::new(Self::connection_to_proto(connection)))
+        Ok(Response::new(connection))
     }
 
     async fn delete(&self, request: Request<DeleteConnectionRequest>) -> Result<Response<Empty>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let req = request.into_inner();
 
         let id = Uuid::parse_str(&req.id)
@@ -0,0 +1,764 @@
+use std::io::SeekFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::DateTime;
+use futures::Stream;
+use russh_sftp::client::fs::{File, Metadata};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::authenticated_user_id;
+use crate::db::{Upload, UploadTransfer};
+use crate::proto::files_server::Files;
+use crate::proto::{
+    upload_event, upload_request, ChmodRequest, DownloadChunk, DownloadRequest, Empty, FileContent,
+    FileInfo, ListDirRequest, ListDirResponse, MkdirRequest, ReadFileRequest, RemoveRequest,
+    RenameRequest, UploadCompleted, UploadEvent, UploadProgress, UploadRequest, UploadStart,
+    UploadStarted, WriteFileRequest, WriteFileResponse,
+};
+use crate::ssh::{
+    create_dir_all, join_path, replace_file, sanitize_filename, shell_quote, unused_path, Sftp,
+};
+use crate::terminal::SessionManager;
+use crate::HiveError;
+
+/// Largest chunk accepted in one UploadChunk
+const MAX_CHUNK_BYTES: usize = 1024 * 1024;
+/// Read size when hashing the finished upload
+const HASH_READ_BYTES: usize = 256 * 1024;
+/// Largest frame sent by Download
+const DOWNLOAD_FRAME_SIZE: usize = 64 * 1024;
+/// Largest file Read and Write handle; bigger files go through Download and Upload
+const MAX_EDIT_BYTES: u64 = 2 * 1024 * 1024;
+
+pub struct FilesService {
+    pool: PgPool,
+    session_manager: Arc<SessionManager>,
+}
+
+impl FilesService {
+    pub fn new(pool: PgPool, session_manager: Arc<SessionManager>) -> Self {
+        Self { pool, session_manager }
+    }
+
+    fn error_to_status(e: HiveError) -> Status {
+        match e {
+            HiveError::Auth(msg) => Status::permission_denied(msg),
+            HiveError::Session(msg) => Status::not_found(msg),
+            e => Status::internal(format!("File operation failed: {}", e)),
+        }
+    }
+
+    /// Map an SFTP status from the remote host onto the matching gRPC code
+    fn sftp_status(e: russh_sftp::client::error::Error, path: &str) -> Status {
+        let message = format!("{}: {}", path, e);
+        match e {
+            russh_sftp::client::error::Error::Status(status) => match status.status_code {
+                StatusCode::NoSuchFile => Status::not_found(message),
+                StatusCode::PermissionDenied => Status::permission_denied(message),
+                StatusCode::OpUnsupported => Status::unimplemented(message),
+                _ => Status::failed_precondition(message),
+            },
+            _ => Status::internal(format!("SFTP error: {}", message)),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn parse_session_id(session_id: &str) -> Result<Uuid, Status> {
+        Uuid::parse_str(session_id).map_err(|_| Status::invalid_argument("Invalid session ID"))
+    }
+
+    /// SFTP session for a live session owned by `user_id`
+    async fn sftp(&self, user_id: Uuid, session_id: Uuid) -> Result<Arc<Sftp>, Status> {
+        self.session_manager
+            .sftp(session_id, user_id)
+            .await
+            .map_err(Self::error_to_status)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn required_path(path: &str) -> Result<&str, Status> {
+        match path.trim() {
+            "" => Err(Status::invalid_argument("Path is required")),
+            _ => Ok(path),
+        }
+    }
+
+    fn file_info(name: String, path: String, metadata: &Metadata) -> FileInfo {
+        FileInfo {
+            name,
+            path,
+            size: metadata.len(),
+            mode: metadata.permissions.unwrap_or_default() & 0o7777,
+            is_dir: metadata.is_dir(),
+            is_symlink: metadata.is_symlink(),
+            modified: metadata
+                .mtime
+                .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0))
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Current content, version and attributes of a file, or None if it does not exist
+    async fn read_versioned(
+        sftp: &SftpSession,
+        path: &str,
+    ) -> Result<Option<(FileContent, Metadata)>, Status> {
+        let metadata = match sftp.metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(russh_sftp::client::error::Error::Status(status))
+                if status.status_code == StatusCode::NoSuchFile =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(Self::sftp_status(e, path)),
+        };
+        if metadata.is_dir() {
+            return Err(Status::failed_precondition(format!("{} is a directory", path)));
+        }
+        if metadata.len() > MAX_EDIT_BYTES {
+            return Err(Status::failed_precondition(format!(
+                "{} is larger than {} bytes, use Download",
+                path, MAX_EDIT_BYTES
+            )));
+        }
+
+        let content = sftp.read(path).await.map_err(|e| Self::sftp_status(e, path))?;
+        let content = FileContent {
+            path: path.to_string(),
+            version: file_version(metadata.mtime, &content),
+            content,
+        };
+        Ok(Some((content, metadata)))
+    }
+
+    /// Write `content` to a temporary file beside `path`, then move it into place
+    async fn write_atomic(
+        sftp: &Sftp,
+        path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+    ) -> Result<(), Status> {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let temp_path = join_path(dir, &format!(".{}.hive-{}.tmp", name, Uuid::new_v4()));
+        let temp = temp_path.as_str();
+
+        let result = async {
+            let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+            let mut file = sftp
+                .open_with_flags(temp, flags)
+                .await
+                .map_err(|e| Self::sftp_status(e, temp))?;
+            file.write_all(content).await.map_err(write_error)?;
+            file.shutdown().await.map_err(write_error)?;
+
+            if let Some(mode) = mode {
+                let attrs = FileAttributes {
+                    permissions: Some(mode & 0o7777),
+                    ..FileAttributes::empty()
+                };
+                sftp.set_metadata(temp, attrs)
+                    .await
+                    .map_err(|e| Self::sftp_status(e, temp))?;
+            }
+
+            replace_file(sftp, temp, path).await.map_err(Self::error_to_status)
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = sftp.remove_file(temp).await;
+        }
+        result
+    }
+
+    /// Register a new transfer and create its partial file next to the destination
+    async fn start_upload(
+        &self,
+        user_id: Uuid,
+        start: UploadStart,
+    ) -> Result<(UploadTransfer, Arc<Sftp>), Status> {
+        let session_id = Self::parse_session_id(&start.session_id)?;
+        let sha256 = start.sha256.to_ascii_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Status::invalid_argument("sha256 must be 64 hex characters"));
+        }
+
+        let size = i64::try_from(start.size)
+            .map_err(|_| Status::invalid_argument("File is too large"))?;
+
+        let sftp = self.sftp(user_id, session_id).await?;
+
+        let filename = sanitize_filename(&start.filename);
+        let directory = match start.directory.trim() {
+            "" => self.session_manager.upload_dir().to_string(),
+            dir => dir.to_string(),
+        };
+        create_dir_all(&sftp, &directory).await.map_err(Self::error_to_status)?;
+
+        let id = Uuid::new_v4();
+        let partial_path = join_path(&directory, &format!(".hive-upload-{}.part", id));
+        let flags = OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE;
+        sftp.open_with_flags(partial_path.as_str(), flags)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create {}: {}", partial_path, e)))?
+            .shutdown()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create {}: {}", partial_path, e)))?;
+
+        let transfer = UploadTransfer::create(
+            &self.pool,
+            id,
+            session_id,
+            user_id,
+            &filename,
+            &directory,
+            &partial_path,
+            size,
+            &sha256,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        info!(
+            "Upload {} started: {} ({} bytes) for session {}",
+            id, filename, size, session_id
+        );
+        Ok((transfer, sftp))
+    }
+
+    /// Pick up an interrupted transfer where the remote partial file ends
+    async fn resume_upload(
+        &self,
+        user_id: Uuid,
+        upload_id: &str,
+    ) -> Result<(UploadTransfer, Arc<Sftp>), Status> {
+        let id = Uuid::parse_str(upload_id)
+            .map_err(|_| Status::invalid_argument("Invalid upload ID"))?;
+
+        let mut transfer = UploadTransfer::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Upload not found"))?;
+
+        if transfer.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to access this upload"));
+        }
+        if transfer.status != "in_progress" {
+            return Err(Status::failed_precondition(format!(
+                "Upload is already {}",
+                transfer.status
+            )));
+        }
+
+        let sftp = self.sftp(user_id, transfer.session_id).await?;
+
+        // Only trust bytes that reached both the database and the remote file
+        let on_disk = sftp
+            .metadata(transfer.partial_path.as_str())
+            .await
+            .map_err(|e| Status::not_found(format!("Partial upload is gone: {}", e)))?
+            .len() as i64;
+        let received = transfer.received_bytes.min(on_disk);
+        if received != transfer.received_bytes {
+            UploadTransfer::set_received(&self.pool, id, received)
+                .await
+                .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+            transfer.received_bytes = received;
+        }
+
+        info!("Upload {} resumed at {} of {} bytes", id, received, transfer.size_bytes);
+        Ok((transfer, sftp))
+    }
+}
+
+/// Receives chunks for one transfer and places the file once it is complete
+struct UploadTask {
+    pool: PgPool,
+    session_manager: Arc<SessionManager>,
+    sftp: Arc<Sftp>,
+    transfer: UploadTransfer,
+    paste_path: bool,
+    tx: mpsc::Sender<Result<UploadEvent, Status>>,
+}
+
+impl UploadTask {
+    async fn send(&self, event: upload_event::Event) -> bool {
+        self.tx
+            .send(Ok(UploadEvent { event: Some(event) }))
+            .await
+            .is_ok()
+    }
+
+    async fn run(self, mut stream: Streaming<UploadRequest>) {
+        if let Err(status) = self.receive(&mut stream).await {
+            warn!("Upload {} stopped: {}", self.transfer.id, status.message());
+            let _ = self.tx.send(Err(status)).await;
+        }
+    }
+
+    async fn receive(&self, stream: &mut Streaming<UploadRequest>) -> Result<(), Status> {
+        let id = self.transfer.id;
+        let size = self.transfer.size_bytes as u64;
+        let mut received = self.transfer.received_bytes as u64;
+
+        let started = upload_event::Event::Started(UploadStarted {
+            upload_id: id.to_string(),
+            offset: received,
+        });
+        if !self.send(started).await {
+            return Ok(());
+        }
+
+        let mut file = self
+            .sftp
+            .open_with_flags(self.transfer.partial_path.as_str(), OpenFlags::WRITE)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to open partial upload: {}", e)))?;
+
+        while received < size {
+            let request = match stream.next().await {
+                Some(Ok(request)) => request,
+                Some(Err(e)) => {
+                    info!("Upload {} interrupted at {} bytes: {}", id, received, e);
+                    return Ok(());
+                }
+                None => {
+                    info!("Upload {} paused at {} of {} bytes", id, received, size);
+                    return Ok(());
+                }
+            };
+
+            let chunk = match request.payload {
+                Some(upload_request::Payload::Chunk(chunk)) => chunk,
+                _ => return Err(Status::invalid_argument("Expected an upload chunk")),
+            };
+            if chunk.offset != received {
+                return Err(Status::failed_precondition(format!(
+                    "Chunk offset {} does not match received bytes {}",
+                    chunk.offset, received
+                )));
+            }
+            if chunk.data.len() > MAX_CHUNK_BYTES {
+                return Err(Status::invalid_argument(format!(
+                    "Chunks are limited to {} bytes",
+                    MAX_CHUNK_BYTES
+                )));
+            }
+            if received + chunk.data.len() as u64 > size {
+                return Err(Status::out_of_range("Chunk extends past the declared size"));
+            }
+
+            file.seek(SeekFrom::Start(received)).await.map_err(write_error)?;
+            file.write_all(&chunk.data).await.map_err(write_error)?;
+            file.flush().await.map_err(write_error)?;
+            received += chunk.data.len() as u64;
+
+            let in_progress = UploadTransfer::set_received(&self.pool, id, received as i64)
+                .await
+                .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+            if !in_progress {
+                return Err(Status::failed_precondition("Upload expired"));
+            }
+
+            if !self.send(upload_event::Event::Progress(UploadProgress { received, size })).await {
+                return Ok(());
+            }
+        }
+
+        file.shutdown().await.map_err(write_error)?;
+        self.complete().await
+    }
+
+    /// Check the checksum of the partial file, then move it to its final name
+    async fn complete(&self) -> Result<(), Status> {
+        let transfer = &self.transfer;
+        let sftp = &self.sftp;
+
+        let mut file = sftp
+            .open(transfer.partial_path.as_str())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read partial upload: {}", e)))?;
+        let sha256 = sha256_hex(&mut file).await.map_err(write_error)?;
+        drop(file);
+
+        if sha256 != transfer.sha256 {
+            warn!(
+                "Upload {} checksum mismatch: expected {}, got {}",
+                transfer.id, transfer.sha256, sha256
+            );
+            let _ = sftp.remove_file(transfer.partial_path.as_str()).await;
+            self.finish("failed").await?;
+            return Err(Status::data_loss(format!(
+                "SHA-256 mismatch: expected {}, got {}",
+                transfer.sha256, sha256
+            )));
+        }
+
+        let path = unused_path(sftp, &transfer.directory, &transfer.filename)
+            .await
+            .map_err(FilesService::error_to_status)?;
+        sftp.rename(transfer.partial_path.as_str(), path.as_str())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to move upload into place: {}", e)))?;
+        let path = sftp
+            .canonicalize(path.as_str())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to resolve upload path: {}", e)))?;
+
+        let upload = Upload::create(
+            &self.pool,
+            transfer.session_id,
+            &transfer.filename,
+            &path,
+            transfer.size_bytes,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        self.finish("complete").await?;
+
+        if self.paste_path {
+            let quoted = shell_quote(&upload.path);
+            if let Err(e) = self
+                .session_manager
+                .send_input(transfer.session_id, transfer.user_id, quoted.as_bytes())
+                .await
+            {
+                warn!("Failed to paste upload path: {}", e);
+            }
+        }
+
+        info!(
+            "Upload {} complete: {} ({} bytes)",
+            transfer.id, upload.path, transfer.size_bytes
+        );
+        self.send(upload_event::Event::Completed(UploadCompleted {
+            path: upload.path,
+            filename: upload.filename,
+            size: transfer.size_bytes as u64,
+            sha256,
+        }))
+        .await;
+        Ok(())
+    }
+
+    async fn finish(&self, status: &str) -> Result<(), Status> {
+        UploadTransfer::finish(&self.pool, self.transfer.id, status)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))
+    }
+}
+
+fn write_error(e: std::io::Error) -> Status {
+    Status::internal(format!("SFTP write failed: {}", e))
+}
+
+/// Version token for a file's content; changes whenever its mtime, size or bytes do
+fn file_version(mtime: Option<u32>, content: &[u8]) -> String {
+    let hash = hex::encode(Sha256::digest(content));
+    format!("{}-{}-{}", mtime.unwrap_or_default(), content.len(), hash)
+}
+
+fn read_error(e: std::io::Error) -> Status {
+    Status::internal(format!("SFTP read failed: {}", e))
+}
+
+async fn sha256_hex(file: &mut File) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_READ_BYTES];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[tonic::async_trait]
+impl Files for FilesService {
+    type UploadStream = Pin<Box<dyn Stream<Item = Result<UploadEvent, Status>> + Send>>;
+    type DownloadStream = Pin<Box<dyn Stream<Item = Result<DownloadChunk, Status>> + Send>>;
+
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<Self::UploadStream>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let mut stream = request.into_inner();
+
+        let first = stream
+            .next()
+            .await
+            .ok_or_else(|| Status::invalid_argument("No initial message received"))?
+            .map_err(|e| Status::internal(format!("Stream error: {}", e)))?;
+        let start = match first.payload {
+            Some(upload_request::Payload::Start(start)) => start,
+            _ => return Err(Status::invalid_argument("First message must be UploadStart")),
+        };
+
+        let paste_path = start.paste_path;
+        let (transfer, sftp) = if start.upload_id.is_empty() {
+            self.start_upload(user_id, start).await?
+        } else {
+            self.resume_upload(user_id, &start.upload_id).await?
+        };
+
+        let (tx, rx) = mpsc::channel(64);
+        let task = UploadTask {
+            pool: self.pool.clone(),
+            session_manager: self.session_manager.clone(),
+            sftp,
+            transfer,
+            paste_path,
+            tx,
+        };
+        tokio::spawn(task.run(stream));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn list_dir(
+        &self,
+        request: Request<ListDirRequest>,
+    ) -> Result<Response<ListDirResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        let path = match req.path.trim() {
+            "" => ".",
+            path => path,
+        };
+        let path = sftp
+            .canonicalize(path)
+            .await
+            .map_err(|e| Self::sftp_status(e, path))?;
+
+        let mut entries: Vec<FileInfo> = sftp
+            .read_dir(path.as_str())
+            .await
+            .map_err(|e| Self::sftp_status(e, &path))?
+            .map(|entry| {
+                let name = entry.file_name();
+                let entry_path = join_path(&path, &name);
+                Self::file_info(name, entry_path, &entry.metadata())
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Response::new(ListDirResponse { path, entries }))
+    }
+
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        let mut file = sftp.open(path).await.map_err(|e| Self::sftp_status(e, path))?;
+        let metadata = file.metadata().await.map_err(|e| Self::sftp_status(e, path))?;
+        if metadata.is_dir() {
+            return Err(Status::failed_precondition(format!("{} is a directory", path)));
+        }
+
+        let file_size = metadata.len();
+        if req.offset > file_size {
+            return Err(Status::out_of_range(format!(
+                "Offset {} is past the end of {} ({} bytes)",
+                req.offset, path, file_size
+            )));
+        }
+        let end = match req.length {
+            0 => file_size,
+            length => req.offset.saturating_add(length).min(file_size),
+        };
+        file.seek(SeekFrom::Start(req.offset)).await.map_err(read_error)?;
+
+        info!("Downloading {} bytes {}..{} of {}", path, req.offset, end, file_size);
+
+        let (tx, rx) = mpsc::channel(16);
+        let mut offset = req.offset;
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; DOWNLOAD_FRAME_SIZE];
+            loop {
+                let want = ((end - offset) as usize).min(DOWNLOAD_FRAME_SIZE);
+                let n = match file.read(&mut buf[..want]).await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        let _ = tx.send(Err(read_error(e))).await;
+                        return;
+                    }
+                };
+
+                // Always send one chunk so an empty range still reports the size
+                let done = n == 0 || offset + n as u64 >= end;
+                if n > 0 || offset == req.offset {
+                    let chunk = DownloadChunk {
+                        offset,
+                        data: buf[..n].to_vec(),
+                        file_size,
+                    };
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        return;
+                    }
+                }
+                offset += n as u64;
+                if done {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn mkdir(&self, request: Request<MkdirRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        if req.parents {
+            create_dir_all(&sftp, path).await.map_err(Self::error_to_status)?;
+        } else {
+            sftp.create_dir(path).await.map_err(|e| Self::sftp_status(e, path))?;
+        }
+
+        info!("Created directory {} in session {}", path, req.session_id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn rename(&self, request: Request<RenameRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let from = Self::required_path(&req.from)?;
+        let to = Self::required_path(&req.to)?;
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        sftp.rename(from, to).await.map_err(|e| Self::sftp_status(e, from))?;
+
+        info!("Renamed {} to {} in session {}", from, to, req.session_id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn remove(&self, request: Request<RemoveRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        // lstat, so a symlink to a directory is removed rather than followed
+        let metadata = sftp
+            .symlink_metadata(path)
+            .await
+            .map_err(|e| Self::sftp_status(e, path))?;
+        let result = if metadata.is_dir() {
+            sftp.remove_dir(path).await
+        } else {
+            sftp.remove_file(path).await
+        };
+        result.map_err(|e| Self::sftp_status(e, path))?;
+
+        info!("Removed {} in session {}", path, req.session_id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn chmod(&self, request: Request<ChmodRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        if req.mode > 0o7777 {
+            return Err(Status::invalid_argument("Mode may only contain permission bits"));
+        }
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        let attrs = FileAttributes {
+            permissions: Some(req.mode),
+            ..FileAttributes::empty()
+        };
+        sftp.set_metadata(path, attrs)
+            .await
+            .map_err(|e| Self::sftp_status(e, path))?;
+
+        info!("Changed mode of {} to {:o} in session {}", path, req.mode, req.session_id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn read(
+        &self,
+        request: Request<ReadFileRequest>,
+    ) -> Result<Response<FileContent>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        let (content, _) = Self::read_versioned(&sftp, path)
+            .await?
+            .ok_or_else(|| Status::not_found(format!("{} does not exist", path)))?;
+
+        Ok(Response::new(content))
+    }
+
+    async fn write(
+        &self,
+        request: Request<WriteFileRequest>,
+    ) -> Result<Response<WriteFileResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+        let path = Self::required_path(&req.path)?;
+        if req.content.len() as u64 > MAX_EDIT_BYTES {
+            return Err(Status::invalid_argument(format!(
+                "Content is larger than {} bytes, use Upload",
+                MAX_EDIT_BYTES
+            )));
+        }
+        let sftp = self.sftp(user_id, Self::parse_session_id(&req.session_id)?).await?;
+
+        let current = Self::read_versioned(&sftp, path).await?;
+        let current_version = current.as_ref().map(|(c, _)| c.version.as_str()).unwrap_or_default();
+        if current_version != req.version {
+            info!("Write to {} refused: file changed since it was read", path);
+            let current = match current {
+                Some((content, _)) => content,
+                None => FileContent {
+                    path: path.to_string(),
+                    ..Default::default()
+                },
+            };
+            return Ok(Response::new(WriteFileResponse {
+                path: path.to_string(),
+                version: String::new(),
+                conflict: Some(current),
+            }));
+        }
+
+        // Keep the permissions of the file being replaced
+        let mode = current.and_then(|(_, metadata)| metadata.permissions);
+        Self::write_atomic(&sftp, path, &req.content, mode).await?;
+
+        let metadata = sftp.metadata(path).await.map_err(|e| Self::sftp_status(e, path))?;
+        info!("Wrote {} bytes to {} in session {}", req.content.len(), path, req.session_id);
+        Ok(Response::new(WriteFileResponse {
+            path: path.to_string(),
+            version: file_version(metadata.mtime, &req.content),
+            conflict: None,
+        }))
+    }
+}
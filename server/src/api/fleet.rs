@@ -0,0 +1,354 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::authenticated_user_id;
+use crate::db::{Connection, FleetRun, FleetRunHost};
+use crate::proto::fleet_server::Fleet;
+use crate::proto::{
+    fleet_event, Empty, FleetEvent, FleetHostResult, FleetRun as ProtoFleetRun,
+    FleetRunListResponse, FleetRunRequest, GetFleetRunRequest,
+};
+use crate::terminal::{ExecEvent, SessionManager};
+use crate::HiveError;
+
+/// Hosts run at once when the request does not say
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 32;
+/// Per-host timeout when the request does not set one
+const DEFAULT_HOST_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_HOST_TIMEOUT: Duration = Duration::from_secs(3600);
+/// Output stored per host and stream; everything is still streamed live
+const MAX_STORED_OUTPUT: usize = 64 * 1024;
+/// Runs returned by ListRuns
+const RECENT_RUNS: i64 = 50;
+
+pub struct FleetService {
+    pool: PgPool,
+    session_manager: Arc<SessionManager>,
+}
+
+impl FleetService {
+    pub fn new(pool: PgPool, session_manager: Arc<SessionManager>) -> Self {
+        Self { pool, session_manager }
+    }
+
+    fn host_to_proto(host: FleetRunHost, with_output: bool) -> FleetHostResult {
+        FleetHostResult {
+            connection_id: host.connection_id.map(|id| id.to_string()).unwrap_or_default(),
+            connection_name: host.connection_name,
+            status: host.status,
+            exit_code: host.exit_code,
+            exit_signal: host.exit_signal.unwrap_or_default(),
+            error: host.error.unwrap_or_default(),
+            stdout: if with_output { host.stdout } else { Vec::new() },
+            stderr: if with_output { host.stderr } else { Vec::new() },
+            output_truncated: host.output_truncated,
+            started_at: host.started_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            finished_at: host.finished_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        }
+    }
+
+    fn run_to_proto(run: FleetRun, hosts: Vec<FleetRunHost>, with_output: bool) -> ProtoFleetRun {
+        ProtoFleetRun {
+            id: run.id.to_string(),
+            command: run.command,
+            status: run.status,
+            created_at: run.created_at.to_rfc3339(),
+            finished_at: run.finished_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            hosts: hosts
+                .into_iter()
+                .map(|host| Self::host_to_proto(host, with_output))
+                .collect(),
+        }
+    }
+
+    /// Connections named by id or tag, all owned by `user_id`
+    async fn resolve_targets(
+        &self,
+        user_id: Uuid,
+        req: &FleetRunRequest,
+    ) -> Result<Vec<Connection>, Status> {
+        let tag = req.tag.trim();
+        match (tag.is_empty(), req.connection_ids.is_empty()) {
+            (true, true) => Err(Status::invalid_argument("Give connection_ids or a tag")),
+            (false, false) => {
+                Err(Status::invalid_argument("Give connection_ids or a tag, not both"))
+            }
+            (false, true) => {
+                let connections = Connection::list_by_tag(&self.pool, user_id, tag)
+                    .await
+                    .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+                if connections.is_empty() {
+                    return Err(Status::not_found(format!("No connections tagged {}", tag)));
+                }
+                Ok(connections)
+            }
+            (true, false) => {
+                let mut connections: Vec<Connection> = Vec::new();
+                for id in &req.connection_ids {
+                    let id = Uuid::parse_str(id)
+                        .map_err(|_| Status::invalid_argument("Invalid connection ID"))?;
+                    if connections.iter().any(|c| c.id == id) {
+                        continue;
+                    }
+
+                    let connection = Connection::find_by_id(&self.pool, id)
+                        .await
+                        .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+                        .ok_or_else(|| Status::not_found(format!("Connection {} not found", id)))?;
+                    if connection.user_id != user_id {
+                        return Err(Status::permission_denied(
+                            "Not authorized to use this connection",
+                        ));
+                    }
+                    connections.push(connection);
+                }
+                Ok(connections)
+            }
+        }
+    }
+}
+
+/// Shared by the per-host tasks of one run
+struct FleetContext {
+    pool: PgPool,
+    session_manager: Arc<SessionManager>,
+    user_id: Uuid,
+    run_id: Uuid,
+    command: String,
+    password: String,
+    timeout: Duration,
+    tx: mpsc::Sender<Result<FleetEvent, Status>>,
+}
+
+impl FleetContext {
+    /// Send an event if the client is still listening; the run goes on either way
+    async fn send(&self, connection_id: Option<Uuid>, event: fleet_event::Event) {
+        let _ = self
+            .tx
+            .send(Ok(FleetEvent {
+                run_id: self.run_id.to_string(),
+                connection_id: connection_id.map(|id| id.to_string()).unwrap_or_default(),
+                event: Some(event),
+            }))
+            .await;
+    }
+
+    async fn save(&self, host: &FleetRunHost) {
+        if let Err(e) = host.save(&self.pool).await {
+            warn!("Failed to record fleet run {} host {}: {}", self.run_id, host.id, e);
+        }
+    }
+
+    async fn run_host(&self, mut host: FleetRunHost) -> FleetRunHost {
+        let connection_id = host.connection_id;
+        host.status = "running".to_string();
+        host.started_at = Some(Utc::now());
+        self.save(&host).await;
+        self.send(connection_id, fleet_event::Event::HostStarted(Empty {})).await;
+
+        let process = match connection_id {
+            Some(id) => {
+                self.session_manager
+                    .exec(self.user_id, id, &self.password, &self.command)
+                    .await
+            }
+            None => Err(HiveError::Session("Connection was deleted".into())),
+        };
+
+        match process {
+            Err(e) => {
+                host.status = "error".to_string();
+                host.error = Some(e.to_string());
+            }
+            Ok(mut process) => {
+                let deadline = tokio::time::sleep(self.timeout);
+                tokio::pin!(deadline);
+                loop {
+                    tokio::select! {
+                        event = process.next() => match event {
+                            Some(ExecEvent::Stdout(data)) => {
+                                host.output_truncated |= append_capped(&mut host.stdout, &data);
+                                let event = fleet_event::Event::Stdout(data);
+                                self.send(connection_id, event).await;
+                            }
+                            Some(ExecEvent::Stderr(data)) => {
+                                host.output_truncated |= append_capped(&mut host.stderr, &data);
+                                let event = fleet_event::Event::Stderr(data);
+                                self.send(connection_id, event).await;
+                            }
+                            Some(ExecEvent::Exit(exit)) => {
+                                host.status = match exit.exit_code {
+                                    Some(0) => "succeeded",
+                                    _ => "failed",
+                                }
+                                .to_string();
+                                host.exit_code = exit.exit_code;
+                                host.exit_signal = exit.exit_signal;
+                                break;
+                            }
+                            None => break,
+                        },
+                        _ = &mut deadline => {
+                            process.kill().await;
+                            host.status = "timed_out".to_string();
+                            break;
+                        }
+                    }
+                }
+                process.close().await;
+            }
+        }
+
+        host.finished_at = Some(Utc::now());
+        self.save(&host).await;
+
+        let result = FleetService::host_to_proto(host.clone(), false);
+        self.send(connection_id, fleet_event::Event::HostFinished(result)).await;
+        host
+    }
+}
+
+/// Append up to the storage cap, returning whether anything was cut
+fn append_capped(buffer: &mut Vec<u8>, data: &[u8]) -> bool {
+    let room = MAX_STORED_OUTPUT.saturating_sub(buffer.len());
+    buffer.extend_from_slice(&data[..data.len().min(room)]);
+    data.len() > room
+}
+
+#[tonic::async_trait]
+impl Fleet for FleetService {
+    type RunStream = Pin<Box<dyn Stream<Item = Result<FleetEvent, Status>> + Send>>;
+
+    async fn run(
+        &self,
+        request: Request<FleetRunRequest>,
+    ) -> Result<Response<Self::RunStream>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        if req.command.trim().is_empty() {
+            return Err(Status::invalid_argument("Command is required"));
+        }
+        let connections = self.resolve_targets(user_id, &req).await?;
+        let concurrency = match req.concurrency {
+            0 => DEFAULT_CONCURRENCY,
+            n => (n as usize).min(MAX_CONCURRENCY),
+        };
+        let timeout = match req.timeout_seconds {
+            0 => DEFAULT_HOST_TIMEOUT,
+            seconds => Duration::from_secs(seconds.into()).min(MAX_HOST_TIMEOUT),
+        };
+
+        let (run, hosts) = FleetRun::create(&self.pool, user_id, &req.command, &connections)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        info!(
+            "Fleet run {} started on {} connections for user {}",
+            run.id,
+            hosts.len(),
+            user_id
+        );
+
+        let (tx, rx) = mpsc::channel(256);
+        let context = FleetContext {
+            pool: self.pool.clone(),
+            session_manager: self.session_manager.clone(),
+            user_id,
+            run_id: run.id,
+            command: req.command,
+            password: req.password,
+            timeout,
+            tx,
+        };
+        tokio::spawn(async move {
+            let started = FleetService::run_to_proto(run, hosts.clone(), false);
+            context.send(None, fleet_event::Event::Started(started)).await;
+
+            let mut hosts: Vec<FleetRunHost> = futures::stream::iter(hosts)
+                .map(|host| context.run_host(host))
+                .buffer_unordered(concurrency)
+                .collect()
+                .await;
+            hosts.sort_by_key(|host| host.position);
+
+            let run = match FleetRun::finish(&context.pool, context.run_id).await {
+                Ok(run) => run,
+                Err(e) => {
+                    warn!("Failed to finish fleet run {}: {}", context.run_id, e);
+                    return;
+                }
+            };
+
+            let succeeded = hosts.iter().filter(|h| h.status == "succeeded").count();
+            info!(
+                "Fleet run {} finished: {} of {} hosts succeeded",
+                run.id,
+                succeeded,
+                hosts.len()
+            );
+
+            let finished = FleetService::run_to_proto(run, hosts, false);
+            context.send(None, fleet_event::Event::Finished(finished)).await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn list_runs(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<FleetRunListResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+
+        let runs = FleetRun::list_for_user(&self.pool, user_id, RECENT_RUNS)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        let mut proto_runs = Vec::with_capacity(runs.len());
+        for run in runs {
+            let hosts = FleetRunHost::list_for_run(&self.pool, run.id)
+                .await
+                .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+            proto_runs.push(Self::run_to_proto(run, hosts, false));
+        }
+
+        Ok(Response::new(FleetRunListResponse { runs: proto_runs }))
+    }
+
+    async fn get_run(
+        &self,
+        request: Request<GetFleetRunRequest>,
+    ) -> Result<Response<ProtoFleetRun>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id).map_err(|_| Status::invalid_argument("Invalid run ID"))?;
+
+        let run = FleetRun::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Fleet run not found"))?;
+
+        if run.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to view this fleet run"));
+        }
+
+        let hosts = FleetRunHost::list_for_run(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(Self::run_to_proto(run, hosts, true)))
+    }
+}
@@ -0,0 +1,120 @@
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use uuid::Uuid;
+
+use super::authenticated_user_id;
+use crate::crypto::MasterKey;
+use crate::db::SshKey;
+use crate::proto::keys_server::Keys;
+use crate::proto::{CreateKeyRequest, DeleteKeyRequest, Empty, Key, KeyListResponse};
+use crate::ssh::{openssh_public_key, parse_private_key, parse_public_key};
+
+pub struct KeysService {
+    pool: PgPool,
+    master_key: Option<MasterKey>,
+}
+
+impl KeysService {
+    pub fn new(pool: PgPool, master_key: Option<MasterKey>) -> Self {
+        Self { pool, master_key }
+    }
+
+    fn key_to_proto(key: SshKey) -> Key {
+        Key {
+            id: key.id.to_string(),
+            name: key.name,
+            public_key: key.public_key,
+            created_at: key.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Keys for KeysService {
+    async fn list(&self, request: Request<Empty>) -> Result<Response<KeyListResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+
+        let keys = SshKey::list_for_user(&self.pool, user_id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(KeyListResponse {
+            keys: keys.into_iter().map(Self::key_to_proto).collect(),
+        }))
+    }
+
+    async fn create(&self, request: Request<CreateKeyRequest>) -> Result<Response<Key>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let master_key = self
+            .master_key
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("Server master key is not configured"))?;
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("Key name is required"));
+        }
+
+        let key_pair =
+            parse_private_key(&req.private_key).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let public_key = key_pair
+            .clone_public_key()
+            .map_err(|e| Status::invalid_argument(format!("Unsupported private key: {}", e)))?;
+
+        // A supplied public key is only a cross-check; the stored one is always derived
+        if !req.public_key.trim().is_empty() {
+            let supplied =
+                parse_public_key(&req.public_key).map_err(|e| Status::invalid_argument(e.to_string()))?;
+            if supplied != public_key {
+                return Err(Status::invalid_argument(
+                    "Public key does not match the private key",
+                ));
+            }
+        }
+
+        let private_key_encrypted = master_key
+            .seal(req.private_key.as_bytes())
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let key = SshKey::create(
+            &self.pool,
+            user_id,
+            &req.name,
+            &private_key_encrypted,
+            &openssh_public_key(&public_key),
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to create key: {}", e)))?;
+
+        info!("Created SSH key {} for user {}", key.id, user_id);
+
+        Ok(Response::new(Self::key_to_proto(key)))
+    }
+
+    async fn delete(&self, request: Request<DeleteKeyRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id).map_err(|_| Status::invalid_argument("Invalid key ID"))?;
+
+        // Verify ownership
+        let existing = SshKey::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Key not found"))?;
+
+        if existing.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to delete this key"));
+        }
+
+        SshKey::delete(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to delete key: {}", e)))?;
+
+        info!("Deleted SSH key {} for user {}", id, user_id);
+
+        Ok(Response::new(Empty {}))
+    }
+}
@@ -0,0 +1,185 @@
+use russh::keys::PublicKeyBase64;
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use uuid::Uuid;
+
+use super::authenticated_user_id;
+use crate::db::{Connection, KnownHost};
+use crate::proto::known_hosts_server::KnownHosts;
+use crate::proto::{
+    AcceptHostKeyRequest, Empty, ForgetHostKeyRequest, ImportKnownHostsRequest,
+    ImportKnownHostsResponse, KnownHost as ProtoKnownHost, KnownHostListResponse,
+    ListKnownHostsRequest,
+};
+use crate::ssh::{fetch_host_key, fingerprint, import_known_hosts, key_type};
+
+pub struct KnownHostsService {
+    pool: PgPool,
+}
+
+impl KnownHostsService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    fn known_host_to_proto(known_host: KnownHost) -> ProtoKnownHost {
+        ProtoKnownHost {
+            id: known_host.id.to_string(),
+            connection_id: known_host
+                .connection_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            host: known_host.host,
+            port: known_host.port,
+            key_type: known_host.key_type,
+            fingerprint: known_host.fingerprint,
+            public_key: known_host.public_key,
+            created_at: known_host.created_at.to_rfc3339(),
+        }
+    }
+
+    async fn owned_connection(&self, user_id: Uuid, connection_id: &str) -> Result<Connection, Status> {
+        let connection_id = Uuid::parse_str(connection_id)
+            .map_err(|_| Status::invalid_argument("Invalid connection ID"))?;
+
+        let connection = Connection::find_by_id(&self.pool, connection_id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Connection not found"))?;
+
+        if connection.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to use this connection"));
+        }
+
+        Ok(connection)
+    }
+}
+
+#[tonic::async_trait]
+impl KnownHosts for KnownHostsService {
+    async fn list(
+        &self,
+        request: Request<ListKnownHostsRequest>,
+    ) -> Result<Response<KnownHostListResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let known_hosts = if req.connection_id.is_empty() {
+            KnownHost::list_for_user(&self.pool, user_id).await
+        } else {
+            let connection = self.owned_connection(user_id, &req.connection_id).await?;
+            KnownHost::find_for_host(
+                &self.pool,
+                user_id,
+                Some(connection.id),
+                &connection.host,
+                connection.port,
+            )
+            .await
+        }
+        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(KnownHostListResponse {
+            known_hosts: known_hosts
+                .into_iter()
+                .map(Self::known_host_to_proto)
+                .collect(),
+        }))
+    }
+
+    async fn accept(
+        &self,
+        request: Request<AcceptHostKeyRequest>,
+    ) -> Result<Response<ProtoKnownHost>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        if req.fingerprint.is_empty() {
+            return Err(Status::invalid_argument("Fingerprint is required"));
+        }
+
+        let connection = self.owned_connection(user_id, &req.connection_id).await?;
+
+        let key = fetch_host_key(&connection.host, connection.port as u16)
+            .await
+            .map_err(|e| Status::unavailable(format!("Failed to fetch host key: {}", e)))?;
+
+        let presented = fingerprint(&key);
+        if presented != req.fingerprint {
+            return Err(Status::failed_precondition(format!(
+                "HostKeyMismatch: {}:{} now presents {}, not {}",
+                connection.host, connection.port, presented, req.fingerprint
+            )));
+        }
+
+        let known_host = KnownHost::replace_for_connection(
+            &self.pool,
+            user_id,
+            connection.id,
+            &connection.host,
+            connection.port,
+            key_type(&key),
+            &key.public_key_base64(),
+            &presented,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to store host key: {}", e)))?;
+
+        info!(
+            "Accepted host key {} for connection {} (user {})",
+            presented, connection.id, user_id
+        );
+
+        Ok(Response::new(Self::known_host_to_proto(known_host)))
+    }
+
+    async fn forget(&self, request: Request<ForgetHostKeyRequest>) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id)
+            .map_err(|_| Status::invalid_argument("Invalid known host ID"))?;
+
+        // Verify ownership
+        let existing = KnownHost::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Known host not found"))?;
+
+        if existing.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to forget this host key"));
+        }
+
+        KnownHost::delete(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to forget host key: {}", e)))?;
+
+        info!("Forgot host key {} for user {}", id, user_id);
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn import(
+        &self,
+        request: Request<ImportKnownHostsRequest>,
+    ) -> Result<Response<ImportKnownHostsResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let connection = if req.connection_id.is_empty() {
+            None
+        } else {
+            Some(self.owned_connection(user_id, &req.connection_id).await?)
+        };
+
+        let summary = import_known_hosts(&self.pool, user_id, connection.as_ref(), &req.content)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to import known hosts: {}", e)))?;
+
+        Ok(Response::new(ImportKnownHostsResponse {
+            imported: summary.imported as u32,
+            skipped: summary.skipped as u32,
+        }))
+    }
+}
@@ -1,7 +1,18 @@
 mod auth;
 mod connections;
+mod files;
+mod fleet;
+mod keys;
+mod known_hosts;
 mod sessions;
 
-pub use auth::AuthService;
+pub use auth::{
+    authenticated_user_id, ApiKeyAuthLayer, ApiKeyAuthMiddleware, ApiKeyAuthenticator,
+    AuthService, AuthenticatedUser, DEFAULT_AUTH_CACHE_TTL,
+};
 pub use connections::ConnectionsService;
+pub use files::FilesService;
+pub use fleet::FleetService;
+pub use keys::KeysService;
+pub use known_hosts::KnownHostsService;
 pub use sessions::SessionsService;
@@ -1,17 +1,40 @@
+use std::pin::Pin;
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+use futures::Stream;
 use sqlx::PgPool;
-use tonic::{Request, Response, Status};
-use tracing::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request, Response, Status};
+use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::db::{Connection, Session};
+use super::authenticated_user_id;
+use crate::db::{Connection, ScrollbackChunk, ScrollbackRange, Session};
 use crate::proto::sessions_server::Sessions;
 use crate::proto::{
-    CloseSessionRequest, CreateSessionRequest, Empty, Session as ProtoSession,
-    SessionListResponse,
+    get_scrollback_request, BroadcastRequest, BroadcastResponse,
+    BroadcastResult as ProtoBroadcastResult, CloseSessionRequest, CreateSessionGroupRequest,
+    CreateSessionRequest, DeleteSessionGroupRequest, Empty, GetScrollbackRequest,
+    ResumeSessionRequest, ScrollbackInfo, ScrollbackPage, Session as ProtoSession,
+    SessionGroup as ProtoSessionGroup, SessionGroupListResponse, SessionListResponse,
+    SuspendSessionRequest, UpdateSessionGroupRequest,
 };
-use crate::terminal::SessionManager;
+use crate::terminal::{
+    SessionEnd, SessionGroup, SessionManager, SessionOptions, MAX_TERMINAL_CELLS,
+};
+use crate::HiveError;
+
+/// Chunks fetched from the database per query while streaming scrollback
+const SCROLLBACK_PAGE_CHUNKS: i64 = 16;
+/// Terminal size when the client does not send one
+const DEFAULT_COLS: u32 = 80;
+const DEFAULT_ROWS: u32 = 24;
+/// Column widths in the sessions table
+const MAX_TERM_TYPE_LEN: usize = 64;
+const MAX_NAME_LEN: usize = 255;
 
 pub struct SessionsService {
     pool: PgPool,
@@ -23,13 +46,140 @@ impl SessionsService {
         Self { pool, session_manager }
     }
 
-    fn extract_user_id(request: &Request<impl std::fmt::Debug>) -> Result<Uuid, Status> {
-        request
-            .metadata()
-            .get("x-user-id")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| Uuid::parse_str(s).ok())
-            .ok_or_else(|| Status::unauthenticated("Missing or invalid user ID"))
+    fn create_error_to_status(e: HiveError) -> Status {
+        match e {
+            HiveError::HostKeyMismatch { ref actual, .. } => {
+                Self::host_key_status(format!("HostKeyMismatch: {}", e), actual)
+            }
+            HiveError::HostKeyUnknown { ref fingerprint, .. } => {
+                Self::host_key_status(format!("HostKeyUnknown: {}", e), fingerprint)
+            }
+            e => Status::internal(format!("Failed to create session: {}", e)),
+        }
+    }
+
+    /// Host key failures carry the presented fingerprint so the client can offer Accept
+    fn host_key_status(message: String, fingerprint: &str) -> Status {
+        let mut metadata = MetadataMap::new();
+        if let Ok(value) = fingerprint.parse() {
+            metadata.insert("x-host-key-fingerprint", value);
+        }
+        Status::with_metadata(Code::FailedPrecondition, message, metadata)
+    }
+
+    fn group_error_to_status(e: HiveError) -> Status {
+        match e {
+            HiveError::Auth(msg) => Status::permission_denied(msg),
+            HiveError::Session(msg) => Status::not_found(msg),
+            e => Status::internal(format!("Session group error: {}", e)),
+        }
+    }
+
+    /// Sessions in the wrong state to suspend or resume are a failed precondition
+    fn state_error_to_status(action: &str, e: HiveError) -> Status {
+        match e {
+            HiveError::Session(msg) => Status::failed_precondition(msg),
+            e => Status::internal(format!("Failed to {} session: {}", action, e)),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn parse_session_ids(ids: &[String]) -> Result<Vec<Uuid>, Status> {
+        ids.iter()
+            .map(|id| Uuid::parse_str(id).map_err(|_| Status::invalid_argument("Invalid session ID")))
+            .collect()
+    }
+
+    fn group_to_proto(group: SessionGroup) -> ProtoSessionGroup {
+        ProtoSessionGroup {
+            id: group.id.to_string(),
+            name: group.name,
+            session_ids: group.session_ids.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn session_options(req: &CreateSessionRequest) -> Result<SessionOptions, Status> {
+        let cols = if req.cols == 0 { DEFAULT_COLS } else { req.cols };
+        let rows = if req.rows == 0 { DEFAULT_ROWS } else { req.rows };
+        if cols > MAX_TERMINAL_CELLS || rows > MAX_TERMINAL_CELLS {
+            return Err(Status::invalid_argument(format!(
+                "Terminal size {}x{} is too large",
+                cols, rows
+            )));
+        }
+
+        let mut options =
+            SessionOptions::new(cols, rows).with_pixel_size(req.pixel_width, req.pixel_height);
+
+        let term_type = req.term_type.trim();
+        if !term_type.is_empty() {
+            let printable = term_type.chars().all(|c| c.is_ascii_graphic());
+            if term_type.len() > MAX_TERM_TYPE_LEN || !printable {
+                return Err(Status::invalid_argument(format!("Invalid TERM: {}", term_type)));
+            }
+            options = options.with_term_type(term_type);
+        }
+
+        let name = req.name.trim();
+        if name.len() > MAX_NAME_LEN {
+            return Err(Status::invalid_argument("Session name is too long"));
+        }
+        if !name.is_empty() {
+            options = options.with_name(name);
+        }
+
+        Ok(options)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn scrollback_range(
+        range: Option<get_scrollback_request::Range>,
+    ) -> Result<ScrollbackRange, Status> {
+        let mut scrollback_range = ScrollbackRange::default();
+
+        match range {
+            Some(get_scrollback_request::Range::Bytes(bytes)) => {
+                scrollback_range.from = bytes.start;
+                if bytes.end != 0 {
+                    if bytes.end < bytes.start {
+                        return Err(Status::invalid_argument("Byte range ends before it starts"));
+                    }
+                    scrollback_range.to = bytes.end;
+                }
+            }
+            Some(get_scrollback_request::Range::Time(time)) => {
+                scrollback_range.since = Self::parse_time(&time.start)?;
+                scrollback_range.until = Self::parse_time(&time.end)?;
+            }
+            None => {}
+        }
+
+        Ok(scrollback_range)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn parse_time(value: &str) -> Result<Option<DateTime<Utc>>, Status> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| Some(time.with_timezone(&Utc)))
+            .map_err(|_| Status::invalid_argument(format!("Invalid RFC 3339 time: {}", value)))
+    }
+
+    async fn scrollback_info(&self, session_id: Uuid) -> crate::Result<ScrollbackInfo> {
+        let end_offset = ScrollbackChunk::end_offset(&self.pool, session_id).await?;
+        let start_offset = ScrollbackChunk::first_offset(&self.pool, session_id)
+            .await?
+            .unwrap_or(end_offset);
+        let total_size = ScrollbackChunk::total_size(&self.pool, session_id).await? as u64;
+
+        Ok(ScrollbackInfo {
+            total_size,
+            start_offset,
+            end_offset,
+        })
     }
 
     async fn session_to_proto(&self, session: Session) -> Result<ProtoSession, Status> {
@@ -46,14 +196,24 @@ impl SessionsService {
             status: session.status,
             created_at: session.created_at.to_rfc3339(),
             last_activity: session.last_activity.to_rfc3339(),
+            exit_code: session.exit_code,
+            exit_signal: session.exit_signal.unwrap_or_default(),
+            name: session.name.unwrap_or_default(),
+            cols: session.cols as u32,
+            rows: session.rows as u32,
+            pixel_width: session.pixel_width as u32,
+            pixel_height: session.pixel_height as u32,
+            term_type: session.term_type.unwrap_or_default(),
         })
     }
 }
 
 #[tonic::async_trait]
 impl Sessions for SessionsService {
+    type GetScrollbackStream = Pin<Box<dyn Stream<Item = Result<ScrollbackPage, Status>> + Send>>;
+
     async fn list(&self, request: Request<Empty>) -> Result<Response<SessionListResponse>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
 
         let sessions = Session::list_for_user(&self.pool, user_id)
             .await
@@ -75,11 +235,12 @@ impl Sessions for SessionsService {
         &self,
         request: Request<CreateSessionRequest>,
     ) -> Result<Response<ProtoSession>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let req = request.into_inner();
 
         let connection_id = Uuid::parse_str(&req.connection_id)
             .map_err(|_| Status::invalid_argument("Invalid connection ID"))?;
+        let options = Self::session_options(&req)?;
 
         // Verify connection exists and belongs to user
         let connection = Connection::find_by_id(&self.pool, connection_id)
@@ -93,9 +254,9 @@ impl Sessions for SessionsService {
 
         // Create SSH session via SessionManager (establishes connection)
         let (session_id, _output_rx) = self.session_manager
-            .create_session(user_id, connection_id, 80, 24, &req.password)
+            .create_session(user_id, connection_id, options, &req.password)
             .await
-            .map_err(|e| Status::internal(format!("Failed to create session: {}", e)))?;
+            .map_err(Self::create_error_to_status)?;
 
         // Get session from DB for response
         let session = Session::find_by_id(&self.pool, session_id)
@@ -108,18 +269,11 @@ impl Sessions for SessionsService {
             session.id, connection_id, user_id
         );
 
-        Ok(Response::new(ProtoSession {
-            id: session.id.to_string(),
-            connection_id: session.connection_id.to_string(),
-            connection_name: connection.name,
-            status: session.status,
-            created_at: session.created_at.to_rfc3339(),
-            last_activity: session.last_activity.to_rfc3339(),
-        }))
+        Ok(Response::new(self.session_to_proto(session).await?))
     }
 
     async fn close(&self, request: Request<CloseSessionRequest>) -> Result<Response<Empty>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let req = request.into_inner();
 
         let id = Uuid::parse_str(&req.id)
@@ -135,7 +289,8 @@ impl Sessions for SessionsService {
             return Err(Status::permission_denied("Not authorized to close this session"));
         }
 
-        Session::close(&self.pool, id)
+        self.session_manager
+            .end_session(id, SessionEnd::new("Session closed by user"))
             .await
             .map_err(|e| Status::internal(format!("Failed to close session: {}", e)))?;
 
@@ -143,4 +298,284 @@ impl Sessions for SessionsService {
 
         Ok(Response::new(Empty {}))
     }
+
+    async fn suspend(
+        &self,
+        request: Request<SuspendSessionRequest>,
+    ) -> Result<Response<ProtoSession>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id)
+            .map_err(|_| Status::invalid_argument("Invalid session ID"))?;
+
+        // Verify ownership
+        let session = Session::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Session not found"))?;
+
+        if session.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to suspend this session"));
+        }
+
+        let session = self.session_manager
+            .suspend_session(id)
+            .await
+            .map_err(|e| Self::state_error_to_status("suspend", e))?;
+
+        info!("Suspended session {} for user {}", id, user_id);
+
+        Ok(Response::new(self.session_to_proto(session).await?))
+    }
+
+    async fn resume(
+        &self,
+        request: Request<ResumeSessionRequest>,
+    ) -> Result<Response<ProtoSession>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id)
+            .map_err(|_| Status::invalid_argument("Invalid session ID"))?;
+
+        // Verify ownership
+        let session = Session::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Session not found"))?;
+
+        if session.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to resume this session"));
+        }
+
+        let session = self.session_manager
+            .resume_session(id, &req.password)
+            .await
+            .map_err(|e| Self::state_error_to_status("resume", e))?;
+
+        info!("Resumed session {} for user {}", id, user_id);
+
+        Ok(Response::new(self.session_to_proto(session).await?))
+    }
+
+    async fn get_scrollback(
+        &self,
+        request: Request<GetScrollbackRequest>,
+    ) -> Result<Response<Self::GetScrollbackStream>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.session_id)
+            .map_err(|_| Status::invalid_argument("Invalid session ID"))?;
+        let range = Self::scrollback_range(req.range)?;
+
+        // Verify ownership
+        let session = Session::find_by_id(&self.pool, id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Session not found"))?;
+
+        if session.user_id != user_id {
+            return Err(Status::permission_denied("Not authorized to access this session"));
+        }
+
+        // Include output still buffered by a live session
+        self.session_manager.flush_session_scrollback(id).await;
+
+        let info = self
+            .scrollback_info(id)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        let (tx, rx) = mpsc::channel(4);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            // Where the next page should start; time ranges may begin anywhere
+            let mut next_offset = (range.since.is_none() && range.until.is_none())
+                .then_some(range.from.max(info.start_offset));
+            let mut info = Some(info);
+            let mut after_index = None;
+
+            loop {
+                let chunks = match ScrollbackChunk::list_page(
+                    &pool,
+                    id,
+                    &range,
+                    after_index,
+                    SCROLLBACK_PAGE_CHUNKS,
+                )
+                .await
+                {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        warn!("Failed to read scrollback for session {}: {}", id, e);
+                        let status = Status::internal(format!("Database error: {}", e));
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                };
+                let Some(last) = chunks.last() else {
+                    break;
+                };
+                after_index = Some(last.chunk_index);
+
+                for chunk in chunks {
+                    let contents = match chunk.contents() {
+                        Ok(contents) => contents,
+                        Err(e) => {
+                            let _ = tx
+                                .send(Err(Status::internal(format!("Corrupt scrollback: {}", e))))
+                                .await;
+                            return;
+                        }
+                    };
+
+                    // Slice to the requested byte range
+                    let chunk_start = chunk.start_offset as u64;
+                    let offset = chunk_start.max(range.from);
+                    let skip = (offset - chunk_start) as usize;
+                    let take = (range.to.saturating_sub(chunk_start) as usize).min(contents.len());
+                    let lost = next_offset.map_or(0, |next| offset.saturating_sub(next));
+                    next_offset = Some(chunk_start + take as u64);
+
+                    let page = ScrollbackPage {
+                        offset,
+                        data: contents[skip..take].to_vec(),
+                        created_at: chunk.created_at.to_rfc3339(),
+                        info: info.take(),
+                        lost,
+                    };
+                    if tx.send(Ok(page)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            // Empty ranges still report the stored size
+            if let Some(info) = info {
+                let page = ScrollbackPage {
+                    offset: range.from,
+                    data: Vec::new(),
+                    created_at: String::new(),
+                    info: Some(info),
+                    lost: 0,
+                };
+                let _ = tx.send(Ok(page)).await;
+            }
+        });
+
+        info!("Streaming scrollback of session {} for user {}", id, user_id);
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn create_group(
+        &self,
+        request: Request<CreateSessionGroupRequest>,
+    ) -> Result<Response<ProtoSessionGroup>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let name = req.name.trim();
+        if name.is_empty() {
+            return Err(Status::invalid_argument("Group name is required"));
+        }
+        let session_ids = Self::parse_session_ids(&req.session_ids)?;
+
+        let group = self
+            .session_manager
+            .create_group(user_id, name, &session_ids)
+            .await
+            .map_err(Self::group_error_to_status)?;
+
+        Ok(Response::new(Self::group_to_proto(group)))
+    }
+
+    async fn list_groups(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<SessionGroupListResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+
+        let groups = self.session_manager.list_groups(user_id).await;
+
+        Ok(Response::new(SessionGroupListResponse {
+            groups: groups.into_iter().map(Self::group_to_proto).collect(),
+        }))
+    }
+
+    async fn update_group(
+        &self,
+        request: Request<UpdateSessionGroupRequest>,
+    ) -> Result<Response<ProtoSessionGroup>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id)
+            .map_err(|_| Status::invalid_argument("Invalid group ID"))?;
+        let add = Self::parse_session_ids(&req.add_session_ids)?;
+        let remove = Self::parse_session_ids(&req.remove_session_ids)?;
+
+        let group = self
+            .session_manager
+            .update_group(id, user_id, &add, &remove)
+            .await
+            .map_err(Self::group_error_to_status)?;
+
+        info!(
+            "Updated session group {}: +{} -{} sessions",
+            id,
+            add.len(),
+            remove.len()
+        );
+
+        Ok(Response::new(Self::group_to_proto(group)))
+    }
+
+    async fn delete_group(
+        &self,
+        request: Request<DeleteSessionGroupRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.id)
+            .map_err(|_| Status::invalid_argument("Invalid group ID"))?;
+
+        self.session_manager
+            .delete_group(id, user_id)
+            .await
+            .map_err(Self::group_error_to_status)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn broadcast(
+        &self,
+        request: Request<BroadcastRequest>,
+    ) -> Result<Response<BroadcastResponse>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let id = Uuid::parse_str(&req.group_id)
+            .map_err(|_| Status::invalid_argument("Invalid group ID"))?;
+
+        let results = self
+            .session_manager
+            .broadcast(id, user_id, &req.data)
+            .await
+            .map_err(Self::group_error_to_status)?;
+
+        let results = results
+            .into_iter()
+            .map(|r| ProtoBroadcastResult {
+                session_id: r.session_id.to_string(),
+                ok: r.result.is_ok(),
+                error: r.result.err().map(|e| e.to_string()).unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(BroadcastResponse { results }))
+    }
 }
@@ -1,9 +1,16 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
 use sqlx::PgPool;
 use tracing::info;
+use uuid::Uuid;
 
-use crate::db::{ApiKey, User};
-use crate::Result;
+use crate::db::{ApiKey, Connection, KnownHost, User};
+use crate::ssh::{import_known_hosts, HostKeyPolicy};
+use crate::terminal::{ReaperPolicy, ScrollbackRetention};
+use crate::tls::TlsOptions;
+use crate::{HiveError, Result};
 
 #[derive(Parser)]
 #[command(name = "hive-server")]
@@ -19,6 +26,116 @@ pub struct Cli {
     /// gRPC listen address
     #[arg(long, default_value = "[::1]:50051")]
     pub listen: String,
+
+    /// How to treat SSH hosts whose key has not been seen before
+    #[arg(long, value_enum, default_value = "tofu")]
+    pub host_key_policy: HostKeyPolicy,
+
+    /// PEM certificate chain to serve gRPC over TLS
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle; when set, clients must present a certificate it signed (mutual TLS)
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Seconds between checks for renewed TLS certificate files
+    #[arg(long, default_value_t = 60)]
+    pub tls_reload_interval: u64,
+
+    /// 32-byte key (hex or base64) that encrypts stored SSH private keys
+    #[arg(long, env = "HIVE_MASTER_KEY", hide_env_values = true)]
+    pub master_key: Option<String>,
+
+    /// Most scrollback bytes kept per session; older output is removed first
+    #[arg(long, env = "HIVE_SCROLLBACK_MAX_SESSION_BYTES")]
+    pub scrollback_max_session_bytes: Option<u64>,
+
+    /// Most scrollback bytes kept per user across all of their sessions
+    #[arg(long, env = "HIVE_SCROLLBACK_MAX_USER_BYTES")]
+    pub scrollback_max_user_bytes: Option<u64>,
+
+    /// Hours after which scrollback is removed, in every session and for every user
+    #[arg(long, env = "HIVE_SCROLLBACK_MAX_AGE_HOURS")]
+    pub scrollback_max_age_hours: Option<u64>,
+
+    /// Directory on remote hosts that uploaded files are written to
+    #[arg(long, env = "HIVE_UPLOAD_DIR", default_value = crate::terminal::DEFAULT_UPLOAD_DIR)]
+    pub upload_dir: String,
+
+    /// Seconds between scrollback retention passes while serving
+    #[arg(long, default_value_t = 600)]
+    pub scrollback_gc_interval: u64,
+
+    /// Attempts to re-establish a session's dropped SSH connection before ending it
+    #[arg(long, env = "HIVE_RECONNECT_ATTEMPTS", default_value_t = 8)]
+    pub reconnect_attempts: u32,
+
+    /// Close sessions no client has been attached to for this many hours
+    #[arg(long, env = "HIVE_CLOSE_UNATTENDED_HOURS")]
+    pub close_unattended_hours: Option<u64>,
+
+    /// Close sessions without input or output for this many minutes
+    #[arg(long, env = "HIVE_CLOSE_IDLE_MINUTES")]
+    pub close_idle_minutes: Option<u64>,
+
+    /// Suspend sessions without input or output for this many minutes
+    #[arg(long, env = "HIVE_SUSPEND_IDLE_MINUTES")]
+    pub suspend_idle_minutes: Option<u64>,
+
+    /// Fail uploads that have received nothing for this many hours; 0 keeps them
+    #[arg(long, env = "HIVE_UPLOAD_EXPIRY_HOURS", default_value_t = 24)]
+    pub upload_expiry_hours: u64,
+
+    /// Seconds between idle session checks while serving
+    #[arg(long, default_value_t = 60)]
+    pub reaper_interval: u64,
+}
+
+impl Cli {
+    /// TLS settings, if a certificate was configured
+    pub fn tls_options(&self) -> Option<TlsOptions> {
+        let (cert, key) = (self.tls_cert.as_ref()?, self.tls_key.as_ref()?);
+        let mut options = TlsOptions::new(cert, key)
+            .with_reload_interval(Duration::from_secs(self.tls_reload_interval.max(1)));
+        if let Some(client_ca) = &self.tls_client_ca {
+            options = options.with_client_ca(client_ca);
+        }
+        Some(options)
+    }
+
+    /// Scrollback limits from the command line
+    pub fn scrollback_retention(&self) -> ScrollbackRetention {
+        ScrollbackRetention {
+            max_session_bytes: self.scrollback_max_session_bytes,
+            max_user_bytes: self.scrollback_max_user_bytes,
+            max_age: self
+                .scrollback_max_age_hours
+                .map(|hours| Duration::from_secs(hours.saturating_mul(3600))),
+        }
+    }
+
+    /// Idle session and upload limits from the command line
+    pub fn reaper_policy(&self) -> ReaperPolicy {
+        ReaperPolicy {
+            close_unattended_after: self
+                .close_unattended_hours
+                .map(|hours| Duration::from_secs(hours.saturating_mul(3600))),
+            close_idle_after: self
+                .close_idle_minutes
+                .map(|minutes| Duration::from_secs(minutes.saturating_mul(60))),
+            suspend_idle_after: self
+                .suspend_idle_minutes
+                .map(|minutes| Duration::from_secs(minutes.saturating_mul(60))),
+            expire_uploads_after: Some(self.upload_expiry_hours)
+                .filter(|&hours| hours > 0)
+                .map(|hours| Duration::from_secs(hours.saturating_mul(3600))),
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -33,6 +150,16 @@ pub enum Commands {
         #[command(subcommand)]
         action: KeyCommands,
     },
+    /// Known SSH host key management
+    KnownHost {
+        #[command(subcommand)]
+        action: KnownHostCommands,
+    },
+    /// Stored scrollback maintenance
+    Scrollback {
+        #[command(subcommand)]
+        action: ScrollbackCommands,
+    },
     /// Run migrations
     Migrate,
     /// Start the server
@@ -74,6 +201,34 @@ pub enum KeyCommands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum KnownHostCommands {
+    /// Import an OpenSSH known_hosts file for a user
+    Import {
+        /// Username
+        #[arg(long)]
+        user: String,
+        /// Path to the known_hosts file
+        #[arg(long)]
+        file: PathBuf,
+        /// Only import keys for this connection (matches hashed entries too)
+        #[arg(long)]
+        connection: Option<Uuid>,
+    },
+    /// List known host keys for a user
+    List {
+        /// Username
+        #[arg(long)]
+        user: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ScrollbackCommands {
+    /// Remove scrollback beyond the configured --scrollback-* limits
+    Gc,
+}
+
 pub async fn handle_user_command(pool: &PgPool, action: UserCommands) -> Result<()> {
     match action {
         UserCommands::Create { username } => {
@@ -86,7 +241,7 @@ pub async fn handle_user_command(pool: &PgPool, action: UserCommands) -> Result<
             if users.is_empty() {
                 println!("No users found");
             } else {
-                println!("{:<36} {:<20} {}", "ID", "Username", "Created");
+                println!("{:<36} {:<20} Created", "ID", "Username");
                 println!("{}", "-".repeat(70));
                 for user in users {
                     println!(
@@ -131,7 +286,7 @@ pub async fn handle_key_command(pool: &PgPool, action: KeyCommands) -> Result<()
                 println!("No API keys found for user {}", user);
             } else {
                 println!("API keys for user {}:", user);
-                println!("{:<36} {:<20} {:<20} {}", "ID", "Name", "Created", "Last Used");
+                println!("{:<36} {:<20} {:<20} Last Used", "ID", "Name", "Created");
                 println!("{}", "-".repeat(90));
                 for key in keys {
                     let last_used = key
@@ -160,3 +315,92 @@ pub async fn handle_key_command(pool: &PgPool, action: KeyCommands) -> Result<()
     }
     Ok(())
 }
+
+pub async fn handle_known_host_command(pool: &PgPool, action: KnownHostCommands) -> Result<()> {
+    match action {
+        KnownHostCommands::Import {
+            user,
+            file,
+            connection,
+        } => {
+            let user_record = User::find_by_username(pool, &user)
+                .await?
+                .ok_or_else(|| HiveError::Auth(format!("User not found: {}", user)))?;
+
+            let connection = match connection {
+                Some(id) => {
+                    let connection = Connection::find_by_id(pool, id)
+                        .await?
+                        .filter(|c| c.user_id == user_record.id)
+                        .ok_or_else(|| {
+                            HiveError::Config(format!("Connection {} not found for {}", id, user))
+                        })?;
+                    Some(connection)
+                }
+                None => None,
+            };
+
+            let content = std::fs::read_to_string(&file)?;
+            let summary =
+                import_known_hosts(pool, user_record.id, connection.as_ref(), &content).await?;
+
+            println!(
+                "Imported {} host keys ({} lines skipped)",
+                summary.imported, summary.skipped
+            );
+        }
+        KnownHostCommands::List { user } => {
+            let user_record = User::find_by_username(pool, &user)
+                .await?
+                .ok_or_else(|| HiveError::Auth(format!("User not found: {}", user)))?;
+
+            let known_hosts = KnownHost::list_for_user(pool, user_record.id).await?;
+
+            if known_hosts.is_empty() {
+                println!("No known hosts for user {}", user);
+            } else {
+                println!("{:<36} {:<30} {:<12} Fingerprint", "ID", "Host", "Type");
+                println!("{}", "-".repeat(130));
+                for known_host in known_hosts {
+                    println!(
+                        "{:<36} {:<30} {:<12} {}",
+                        known_host.id,
+                        format!("{}:{}", known_host.host, known_host.port),
+                        known_host.key_type,
+                        known_host.fingerprint
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn handle_scrollback_command(
+    pool: &PgPool,
+    retention: &ScrollbackRetention,
+    action: ScrollbackCommands,
+) -> Result<()> {
+    match action {
+        ScrollbackCommands::Gc => {
+            if retention.is_unlimited() {
+                return Err(HiveError::Config(
+                    "No scrollback limits configured, set --scrollback-max-session-bytes, \
+                     --scrollback-max-user-bytes or --scrollback-max-age-hours"
+                        .into(),
+                ));
+            }
+
+            let summary = retention.apply(pool).await?;
+            info!("Scrollback GC removed {} chunks", summary.total());
+            println!(
+                "Removed {} scrollback chunks ({} expired, {} over session cap, {} over user cap)",
+                summary.total(),
+                summary.expired,
+                summary.over_session_cap,
+                summary.over_user_cap
+            );
+        }
+    }
+    Ok(())
+}
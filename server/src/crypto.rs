@@ -0,0 +1,79 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::{HiveError, Result};
+
+/// Version tag prepended to sealed values so the scheme can change later
+const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+/// Server-wide AES-256-GCM key used to encrypt secrets stored in the database
+#[derive(Clone)]
+pub struct MasterKey {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+impl MasterKey {
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(bytes)),
+        }
+    }
+
+    /// Parse a 32-byte key given as 64 hex characters or base64
+    pub fn parse(encoded: &str) -> Result<Self> {
+        let encoded = encoded.trim();
+        let bytes = if encoded.len() == 64 {
+            hex::decode(encoded).ok()
+        } else {
+            BASE64.decode(encoded).ok()
+        };
+
+        let bytes: [u8; 32] = bytes
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| {
+                HiveError::Config("Master key must be 32 bytes, hex or base64 encoded".into())
+            })?;
+
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Encrypt `plaintext` into a `v1:<base64(nonce || ciphertext)>` string
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| HiveError::Config("Failed to encrypt secret".into()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", SEALED_PREFIX, BASE64.encode(sealed)))
+    }
+
+    /// Decrypt a value produced by [`MasterKey::seal`]
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>> {
+        let invalid = || HiveError::Config("Encrypted secret is malformed".into());
+
+        let encoded = sealed.strip_prefix(SEALED_PREFIX).ok_or_else(invalid)?;
+        let bytes = BASE64.decode(encoded).map_err(|_| invalid())?;
+        if bytes.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                HiveError::Config("Failed to decrypt secret (wrong master key?)".into())
+            })
+    }
+}
@@ -16,6 +16,39 @@ pub struct Connection {
     pub ssh_key_id: Option<Uuid>,
     pub startup_command: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub tags: Vec<String>,
+    /// typed, exec or prompt
+    pub startup_mode: String,
+    /// Pattern the output must match before the startup command is typed in prompt mode
+    pub startup_prompt: Option<String>,
+    /// TERM requested with the PTY, when not the default
+    pub term_type: Option<String>,
+    /// none, tmux or screen
+    pub persistence: String,
+}
+
+/// Environment variable sent to the remote host before the shell starts
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ConnectionEnv {
+    pub connection_id: Uuid,
+    pub name: String,
+    pub value: String,
+}
+
+/// Terminal mode sent with the PTY request
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ConnectionPtyMode {
+    pub connection_id: Uuid,
+    pub mode: String,
+    pub value: i64,
+}
+
+/// Value substituted for `${name}` in a connection's startup command
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ConnectionVariable {
+    pub connection_id: Uuid,
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -26,6 +59,39 @@ pub struct Session {
     pub status: String,
     pub created_at: DateTime<Utc>,
     pub last_activity: DateTime<Utc>,
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<String>,
+    pub name: Option<String>,
+    /// Terminal size the session was created with
+    pub cols: i32,
+    pub rows: i32,
+    pub pixel_width: i32,
+    pub pixel_height: i32,
+    /// TERM requested with the PTY
+    pub term_type: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct KnownHost {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub connection_id: Option<Uuid>,
+    pub host: String,
+    pub port: i32,
+    pub key_type: String,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SshKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub private_key_encrypted: String,
+    pub public_key: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -200,6 +266,7 @@ impl ApiKey {
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
@@ -215,7 +282,8 @@ impl Connection {
             r#"
             INSERT INTO connections (id, user_id, name, host, port, username, ssh_key_id, startup_command)
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
             "#,
         )
         .bind(id)
@@ -235,7 +303,8 @@ impl Connection {
     pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
         let conn = sqlx::query_as::<_, Connection>(
             r#"
-            SELECT id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at
+            SELECT id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
             FROM connections WHERE id = $1
             "#,
         )
@@ -249,7 +318,8 @@ impl Connection {
     pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>> {
         let conns = sqlx::query_as::<_, Connection>(
             r#"
-            SELECT id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at
+            SELECT id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
             FROM connections WHERE user_id = $1
             ORDER BY created_at
             "#,
@@ -261,6 +331,7 @@ impl Connection {
         Ok(conns)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update(
         pool: &PgPool,
         id: Uuid,
@@ -276,7 +347,8 @@ impl Connection {
             UPDATE connections
             SET name = $2, host = $3, port = $4, username = $5, ssh_key_id = $6, startup_command = $7
             WHERE id = $1
-            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
             "#,
         )
         .bind(id)
@@ -292,6 +364,109 @@ impl Connection {
         Ok(conn)
     }
 
+    /// Replace the connection's tags
+    pub async fn set_tags(pool: &PgPool, id: Uuid, tags: &[String]) -> Result<Option<Self>> {
+        let conn = sqlx::query_as::<_, Connection>(
+            r#"
+            UPDATE connections SET tags = $2
+            WHERE id = $1
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
+            "#,
+        )
+        .bind(id)
+        .bind(tags)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(conn)
+    }
+
+    /// Set how the startup command is run
+    pub async fn set_startup(
+        pool: &PgPool,
+        id: Uuid,
+        mode: &str,
+        prompt: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let conn = sqlx::query_as::<_, Connection>(
+            r#"
+            UPDATE connections SET startup_mode = $2, startup_prompt = $3
+            WHERE id = $1
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
+            "#,
+        )
+        .bind(id)
+        .bind(mode)
+        .bind(prompt)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(conn)
+    }
+
+    /// Set the TERM requested with the PTY; None restores the default
+    pub async fn set_term_type(
+        pool: &PgPool,
+        id: Uuid,
+        term_type: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let conn = sqlx::query_as::<_, Connection>(
+            r#"
+            UPDATE connections SET term_type = $2
+            WHERE id = $1
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
+            "#,
+        )
+        .bind(id)
+        .bind(term_type)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(conn)
+    }
+
+    /// Set whether sessions run their shell inside tmux or screen
+    pub async fn set_persistence(
+        pool: &PgPool,
+        id: Uuid,
+        persistence: &str,
+    ) -> Result<Option<Self>> {
+        let conn = sqlx::query_as::<_, Connection>(
+            r#"
+            UPDATE connections SET persistence = $2
+            WHERE id = $1
+            RETURNING id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
+            "#,
+        )
+        .bind(id)
+        .bind(persistence)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(conn)
+    }
+
+    pub async fn list_by_tag(pool: &PgPool, user_id: Uuid, tag: &str) -> Result<Vec<Self>> {
+        let conns = sqlx::query_as::<_, Connection>(
+            r#"
+            SELECT id, user_id, name, host, port, username, ssh_key_id, startup_command, created_at, tags,
+                startup_mode, startup_prompt, term_type, persistence
+            FROM connections WHERE user_id = $1 AND tags @> ARRAY[$2]::TEXT[]
+            ORDER BY name
+            "#,
+        )
+        .bind(user_id)
+        .bind(tag)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(conns)
+    }
+
     pub async fn delete(pool: &PgPool, id: Uuid) -> Result<bool> {
         let result = sqlx::query("DELETE FROM connections WHERE id = $1")
             .bind(id)
@@ -302,6 +477,144 @@ impl Connection {
     }
 }
 
+impl ConnectionEnv {
+    pub async fn list_for_connection(pool: &PgPool, connection_id: Uuid) -> Result<Vec<Self>> {
+        let env = sqlx::query_as::<_, ConnectionEnv>(
+            r#"
+            SELECT connection_id, name, value
+            FROM connection_env WHERE connection_id = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(connection_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(env)
+    }
+
+    /// Replace all of a connection's environment variables
+    pub async fn replace_for_connection(
+        pool: &PgPool,
+        connection_id: Uuid,
+        env: &[(String, String)],
+    ) -> Result<Vec<Self>> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM connection_env WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (name, value) in env {
+            sqlx::query(
+                "INSERT INTO connection_env (connection_id, name, value) VALUES ($1, $2, $3)",
+            )
+            .bind(connection_id)
+            .bind(name)
+            .bind(value)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Self::list_for_connection(pool, connection_id).await
+    }
+}
+
+impl ConnectionPtyMode {
+    pub async fn list_for_connection(pool: &PgPool, connection_id: Uuid) -> Result<Vec<Self>> {
+        let modes = sqlx::query_as::<_, ConnectionPtyMode>(
+            r#"
+            SELECT connection_id, mode, value
+            FROM connection_pty_modes WHERE connection_id = $1
+            ORDER BY mode
+            "#,
+        )
+        .bind(connection_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(modes)
+    }
+
+    /// Replace all of a connection's terminal modes
+    pub async fn replace_for_connection(
+        pool: &PgPool,
+        connection_id: Uuid,
+        modes: &[(String, u32)],
+    ) -> Result<Vec<Self>> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM connection_pty_modes WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (mode, value) in modes {
+            sqlx::query(
+                "INSERT INTO connection_pty_modes (connection_id, mode, value) VALUES ($1, $2, $3)",
+            )
+            .bind(connection_id)
+            .bind(mode)
+            .bind(i64::from(*value))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Self::list_for_connection(pool, connection_id).await
+    }
+}
+
+impl ConnectionVariable {
+    pub async fn list_for_connection(pool: &PgPool, connection_id: Uuid) -> Result<Vec<Self>> {
+        let variables = sqlx::query_as::<_, ConnectionVariable>(
+            r#"
+            SELECT connection_id, name, value
+            FROM connection_variables WHERE connection_id = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(connection_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(variables)
+    }
+
+    /// Replace all of a connection's variables
+    pub async fn replace_for_connection(
+        pool: &PgPool,
+        connection_id: Uuid,
+        variables: &[(String, String)],
+    ) -> Result<Vec<Self>> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM connection_variables WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (name, value) in variables {
+            sqlx::query(
+                "INSERT INTO connection_variables (connection_id, name, value) VALUES ($1, $2, $3)",
+            )
+            .bind(connection_id)
+            .bind(name)
+            .bind(value)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Self::list_for_connection(pool, connection_id).await
+    }
+}
+
 impl Session {
     pub async fn create(pool: &PgPool, user_id: Uuid, connection_id: Uuid) -> Result<Self> {
         let id = Uuid::new_v4();
@@ -309,7 +622,8 @@ impl Session {
             r#"
             INSERT INTO sessions (id, user_id, connection_id, status)
             VALUES ($1, $2, $3, 'active')
-            RETURNING id, user_id, connection_id, status, created_at, last_activity
+            RETURNING id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
             "#,
         )
         .bind(id)
@@ -321,10 +635,43 @@ impl Session {
         Ok(session)
     }
 
+    /// Record the terminal a session was created with
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_terminal(
+        pool: &PgPool,
+        id: Uuid,
+        name: Option<&str>,
+        cols: u32,
+        rows: u32,
+        pixel_width: u32,
+        pixel_height: u32,
+        term_type: &str,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE sessions
+            SET name = $2, cols = $3, rows = $4, pixel_width = $5, pixel_height = $6, term_type = $7
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(cols as i32)
+        .bind(rows as i32)
+        .bind(pixel_width as i32)
+        .bind(pixel_height as i32)
+        .bind(term_type)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
         let session = sqlx::query_as::<_, Session>(
             r#"
-            SELECT id, user_id, connection_id, status, created_at, last_activity
+            SELECT id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
             FROM sessions WHERE id = $1
             "#,
         )
@@ -338,7 +685,8 @@ impl Session {
     pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>> {
         let sessions = sqlx::query_as::<_, Session>(
             r#"
-            SELECT id, user_id, connection_id, status, created_at, last_activity
+            SELECT id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
             FROM sessions WHERE user_id = $1
             ORDER BY last_activity DESC
             "#,
@@ -353,7 +701,8 @@ impl Session {
     pub async fn list_active_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>> {
         let sessions = sqlx::query_as::<_, Session>(
             r#"
-            SELECT id, user_id, connection_id, status, created_at, last_activity
+            SELECT id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
             FROM sessions WHERE user_id = $1 AND status = 'active'
             ORDER BY last_activity DESC
             "#,
@@ -365,14 +714,63 @@ impl Session {
         Ok(sessions)
     }
 
-    pub async fn update_status(pool: &PgPool, id: Uuid, status: &str) -> Result<bool> {
-        let result = sqlx::query("UPDATE sessions SET status = $2 WHERE id = $1")
-            .bind(id)
-            .bind(status)
-            .execute(pool)
-            .await?;
+    /// Sessions of every user in `status`, oldest first
+    pub async fn list_with_status(pool: &PgPool, status: &str) -> Result<Vec<Self>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            r#"
+            SELECT id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
+            FROM sessions WHERE status = $1
+            ORDER BY created_at
+            "#,
+        )
+        .bind(status)
+        .fetch_all(pool)
+        .await?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(sessions)
+    }
+
+    /// Statuses a session may reach `status` from: active and suspended sessions switch between
+    /// each other or end, a lost session can only be closed, and closed is final
+    fn statuses_before(status: &str) -> &'static [&'static str] {
+        match status {
+            "active" => &["suspended"],
+            "suspended" => &["active"],
+            "lost" => &["active", "suspended"],
+            "closed" => &["active", "suspended", "lost"],
+            _ => &[],
+        }
+    }
+
+    /// Move a session to `status`, failing if its current status cannot change to it
+    pub async fn transition(pool: &PgPool, id: Uuid, status: &str) -> Result<Self> {
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions SET status = $2
+            WHERE id = $1 AND status = ANY($3)
+            RETURNING id, user_id, connection_id, status, created_at, last_activity, exit_code, exit_signal,
+                name, cols, rows, pixel_width, pixel_height, term_type
+            "#,
+        )
+        .bind(id)
+        .bind(status)
+        .bind(Self::statuses_before(status))
+        .fetch_optional(pool)
+        .await?;
+
+        match session {
+            Some(session) => Ok(session),
+            None => {
+                let current = Self::find_by_id(pool, id)
+                    .await?
+                    .ok_or_else(|| crate::HiveError::Session("Session not found".into()))?;
+                Err(crate::HiveError::Session(format!(
+                    "Cannot change session from {} to {}",
+                    current.status, status
+                )))
+            }
+        }
     }
 
     pub async fn update_activity(pool: &PgPool, id: Uuid) -> Result<bool> {
@@ -384,155 +782,493 @@ impl Session {
         Ok(result.rows_affected() > 0)
     }
 
+    /// Close a session unless it already is, returning whether it changed
     pub async fn close(pool: &PgPool, id: Uuid) -> Result<bool> {
-        Self::update_status(pool, id, "closed").await
+        let result = sqlx::query(
+            "UPDATE sessions SET status = 'closed' WHERE id = $1 AND status = ANY($2)",
+        )
+        .bind(id)
+        .bind(Self::statuses_before("closed"))
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
     }
-}
 
-const SCROLLBACK_CHUNK_SIZE: usize = 65536; // 64KB chunks
+    /// Close a session whose remote shell ended, keeping how it ended
+    pub async fn record_exit(
+        pool: &PgPool,
+        id: Uuid,
+        exit_code: Option<i32>,
+        exit_signal: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE sessions
+            SET status = 'closed', exit_code = $2, exit_signal = $3
+            WHERE id = $1 AND status = ANY($4)
+            "#,
+        )
+        .bind(id)
+        .bind(exit_code)
+        .bind(exit_signal)
+        .bind(Self::statuses_before("closed"))
+        .execute(pool)
+        .await?;
 
-#[derive(Debug, Clone, sqlx::FromRow)]
-pub struct ScrollbackChunk {
-    pub id: i64,
-    pub session_id: Uuid,
-    pub chunk_index: i32,
-    pub data: Vec<u8>,
-    pub created_at: DateTime<Utc>,
+        Ok(result.rows_affected() > 0)
+    }
 }
 
-impl ScrollbackChunk {
-    pub async fn append(pool: &PgPool, session_id: Uuid, data: &[u8]) -> Result<()> {
-        // Get current max chunk index
-        let current_max: Option<i32> = sqlx::query_scalar(
-            "SELECT MAX(chunk_index) FROM scrollback_chunks WHERE session_id = $1",
+impl KnownHost {
+    /// Add a trusted key, keeping the existing row if it is already known
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        connection_id: Option<Uuid>,
+        host: &str,
+        port: i32,
+        key_type: &str,
+        public_key: &str,
+        fingerprint: &str,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4();
+        let known_host = sqlx::query_as::<_, KnownHost>(
+            r#"
+            INSERT INTO known_hosts (id, user_id, connection_id, host, port, key_type, public_key, fingerprint)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (user_id, connection_id, host, port, public_key)
+            DO UPDATE SET key_type = EXCLUDED.key_type
+            RETURNING id, user_id, connection_id, host, port, key_type, public_key, fingerprint, created_at
+            "#,
         )
-        .bind(session_id)
+        .bind(id)
+        .bind(user_id)
+        .bind(connection_id)
+        .bind(host)
+        .bind(port)
+        .bind(key_type)
+        .bind(public_key)
+        .bind(fingerprint)
         .fetch_one(pool)
         .await?;
 
-        let mut chunk_index = current_max.unwrap_or(-1);
-        let mut remaining = data;
+        Ok(known_host)
+    }
 
-        // Get last chunk to see if we can append to it
-        if chunk_index >= 0 {
-            let last_chunk: Option<ScrollbackChunk> = sqlx::query_as(
-                r#"
-                SELECT id, session_id, chunk_index, data, created_at
-                FROM scrollback_chunks
-                WHERE session_id = $1 AND chunk_index = $2
-                "#,
-            )
-            .bind(session_id)
-            .bind(chunk_index)
-            .fetch_optional(pool)
-            .await?;
+    /// Replace every key trusted for a connection with a single new key
+    #[allow(clippy::too_many_arguments)]
+    pub async fn replace_for_connection(
+        pool: &PgPool,
+        user_id: Uuid,
+        connection_id: Uuid,
+        host: &str,
+        port: i32,
+        key_type: &str,
+        public_key: &str,
+        fingerprint: &str,
+    ) -> Result<Self> {
+        let mut tx = pool.begin().await?;
 
-            if let Some(last) = last_chunk {
-                let space_left = SCROLLBACK_CHUNK_SIZE - last.data.len();
-                if space_left > 0 {
-                    // Append to existing chunk
-                    let to_append = std::cmp::min(space_left, remaining.len());
-                    let mut new_data = last.data;
-                    new_data.extend_from_slice(&remaining[..to_append]);
-
-                    sqlx::query(
-                        "UPDATE scrollback_chunks SET data = $1 WHERE session_id = $2 AND chunk_index = $3",
-                    )
-                    .bind(&new_data)
-                    .bind(session_id)
-                    .bind(chunk_index)
-                    .execute(pool)
-                    .await?;
+        sqlx::query(
+            "DELETE FROM known_hosts WHERE user_id = $1 AND connection_id = $2 AND host = $3 AND port = $4",
+        )
+        .bind(user_id)
+        .bind(connection_id)
+        .bind(host)
+        .bind(port)
+        .execute(&mut *tx)
+        .await?;
 
-                    remaining = &remaining[to_append..];
-                }
-            }
+        let known_host = sqlx::query_as::<_, KnownHost>(
+            r#"
+            INSERT INTO known_hosts (id, user_id, connection_id, host, port, key_type, public_key, fingerprint)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, user_id, connection_id, host, port, key_type, public_key, fingerprint, created_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(connection_id)
+        .bind(host)
+        .bind(port)
+        .bind(key_type)
+        .bind(public_key)
+        .bind(fingerprint)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(known_host)
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let known_host = sqlx::query_as::<_, KnownHost>(
+            r#"
+            SELECT id, user_id, connection_id, host, port, key_type, public_key, fingerprint, created_at
+            FROM known_hosts WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(known_host)
+    }
+
+    /// Keys trusted for a host: those pinned to the connection plus host-wide imports
+    pub async fn find_for_host(
+        pool: &PgPool,
+        user_id: Uuid,
+        connection_id: Option<Uuid>,
+        host: &str,
+        port: i32,
+    ) -> Result<Vec<Self>> {
+        let known_hosts = sqlx::query_as::<_, KnownHost>(
+            r#"
+            SELECT id, user_id, connection_id, host, port, key_type, public_key, fingerprint, created_at
+            FROM known_hosts
+            WHERE user_id = $1 AND host = $3 AND port = $4
+              AND (connection_id IS NULL OR connection_id = $2)
+            ORDER BY created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(connection_id)
+        .bind(host)
+        .bind(port)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(known_hosts)
+    }
+
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>> {
+        let known_hosts = sqlx::query_as::<_, KnownHost>(
+            r#"
+            SELECT id, user_id, connection_id, host, port, key_type, public_key, fingerprint, created_at
+            FROM known_hosts WHERE user_id = $1
+            ORDER BY host, port, created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(known_hosts)
+    }
+
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM known_hosts WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl SshKey {
+    /// `private_key_encrypted` must already be sealed with the server master key
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        name: &str,
+        private_key_encrypted: &str,
+        public_key: &str,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4();
+        let key = sqlx::query_as::<_, SshKey>(
+            r#"
+            INSERT INTO ssh_keys (id, user_id, name, private_key_encrypted, public_key)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, name, private_key_encrypted, public_key, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(private_key_encrypted)
+        .bind(public_key)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let key = sqlx::query_as::<_, SshKey>(
+            r#"
+            SELECT id, user_id, name, private_key_encrypted, public_key, created_at
+            FROM ssh_keys WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>> {
+        let keys = sqlx::query_as::<_, SshKey>(
+            r#"
+            SELECT id, user_id, name, private_key_encrypted, public_key, created_at
+            FROM ssh_keys WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM ssh_keys WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+const SCROLLBACK_CHUNK_SIZE: usize = 65536; // 64KB chunks
+/// Chunks smaller than this are stored as-is; compressing them rarely pays off
+const SCROLLBACK_COMPRESS_MIN: usize = 1024;
+const SCROLLBACK_ZSTD_LEVEL: i32 = 3;
+
+/// Part of a session's stored output: a byte range, narrowed to chunks stored in a time window
+#[derive(Debug, Clone)]
+pub struct ScrollbackRange {
+    /// Inclusive start offset
+    pub from: u64,
+    /// Exclusive end offset
+    pub to: u64,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Default for ScrollbackRange {
+    fn default() -> Self {
+        Self {
+            from: 0,
+            to: u64::MAX,
+            since: None,
+            until: None,
         }
+    }
+}
 
-        // Create new chunks for remaining data
-        while !remaining.is_empty() {
-            chunk_index += 1;
-            let chunk_size = std::cmp::min(SCROLLBACK_CHUNK_SIZE, remaining.len());
-            let chunk_data = &remaining[..chunk_size];
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ScrollbackChunk {
+    pub id: i64,
+    pub session_id: Uuid,
+    pub chunk_index: i32,
+    /// Absolute offset of the first byte of `data` in the session's output
+    pub start_offset: i64,
+    /// Uncompressed length of `data`
+    pub size: i32,
+    /// Whether `data` is zstd-compressed
+    pub compressed: bool,
+    pub data: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ScrollbackChunk {
+    /// The chunk's output bytes, decompressed if needed
+    pub fn contents(&self) -> Result<Vec<u8>> {
+        if !self.compressed {
+            return Ok(self.data.clone());
+        }
+        Ok(zstd::bulk::decompress(&self.data, self.size as usize)?)
+    }
+
+    /// Append data after the last stored chunk
+    pub async fn append(pool: &PgPool, session_id: Uuid, data: &[u8]) -> Result<()> {
+        let offset = Self::end_offset(pool, session_id).await?;
+        Self::append_at(pool, session_id, offset, data).await
+    }
+
+    /// Insert data starting at absolute stream offset `offset`, never rewriting existing chunks.
+    ///
+    /// Chunks are sealed as they are written, so each one is compressed up front when that
+    /// makes it smaller.
+    pub async fn append_at(pool: &PgPool, session_id: Uuid, offset: u64, data: &[u8]) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        let mut offset = offset;
+
+        for chunk_data in data.chunks(SCROLLBACK_CHUNK_SIZE) {
+            let compressed = if chunk_data.len() >= SCROLLBACK_COMPRESS_MIN {
+                zstd::bulk::compress(chunk_data, SCROLLBACK_ZSTD_LEVEL)?
+            } else {
+                Vec::new()
+            };
+            let is_compressed = !compressed.is_empty() && compressed.len() < chunk_data.len();
+            let stored = if is_compressed { compressed.as_slice() } else { chunk_data };
 
             sqlx::query(
                 r#"
-                INSERT INTO scrollback_chunks (session_id, chunk_index, data)
-                VALUES ($1, $2, $3)
+                INSERT INTO scrollback_chunks
+                    (session_id, chunk_index, start_offset, size, compressed, data)
+                SELECT $1, COALESCE(MAX(chunk_index) + 1, 0), $2, $3, $4, $5
+                FROM scrollback_chunks
+                WHERE session_id = $1
                 "#,
             )
             .bind(session_id)
-            .bind(chunk_index)
-            .bind(chunk_data)
-            .execute(pool)
+            .bind(offset as i64)
+            .bind(chunk_data.len() as i32)
+            .bind(is_compressed)
+            .bind(stored)
+            .execute(&mut *tx)
             .await?;
 
-            remaining = &remaining[chunk_size..];
+            offset += chunk_data.len() as u64;
         }
 
+        sqlx::query(
+            "UPDATE sessions SET scrollback_end = GREATEST(scrollback_end, $2) WHERE id = $1",
+        )
+        .bind(session_id)
+        .bind(offset as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
         Ok(())
     }
 
+    /// Offset just past the last byte ever stored, even if retention has since removed it
+    pub async fn end_offset(pool: &PgPool, session_id: Uuid) -> Result<u64> {
+        let end: i64 = sqlx::query_scalar(
+            r#"
+            SELECT GREATEST(
+                COALESCE((SELECT scrollback_end FROM sessions WHERE id = $1), 0),
+                COALESCE(
+                    (SELECT MAX(start_offset + size) FROM scrollback_chunks WHERE session_id = $1),
+                    0
+                )
+            )::BIGINT
+            "#,
+        )
+        .bind(session_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(end as u64)
+    }
+
+    /// Offset of the oldest byte still stored, if any; older output was removed by retention
+    pub async fn first_offset(pool: &PgPool, session_id: Uuid) -> Result<Option<u64>> {
+        let first: Option<i64> = sqlx::query_scalar(
+            "SELECT MIN(start_offset) FROM scrollback_chunks WHERE session_id = $1",
+        )
+        .bind(session_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(first.map(|first| first as u64))
+    }
+
     pub async fn get_all(pool: &PgPool, session_id: Uuid) -> Result<Vec<u8>> {
+        let (_, data) = Self::read_range(pool, session_id, 0, u64::MAX).await?;
+        Ok(data)
+    }
+
+    pub async fn get_from_offset(pool: &PgPool, session_id: Uuid, offset: usize) -> Result<Vec<u8>> {
+        let (_, data) = Self::read_range(pool, session_id, offset as u64, u64::MAX).await?;
+        Ok(data)
+    }
+
+    /// Stored output in `[from, to)`, along with the offset it actually starts at.
+    ///
+    /// The start is later than `from` when older output was removed by retention, or was lost
+    /// because it could not be stored in time. Output before such a gap is left out, so the
+    /// result is always one unbroken stretch ending at the newest stored byte before `to`.
+    pub async fn read_range(
+        pool: &PgPool,
+        session_id: Uuid,
+        from: u64,
+        to: u64,
+    ) -> Result<(u64, Vec<u8>)> {
         let chunks: Vec<ScrollbackChunk> = sqlx::query_as(
             r#"
-            SELECT id, session_id, chunk_index, data, created_at
+            SELECT id, session_id, chunk_index, start_offset, size, compressed, data, created_at
             FROM scrollback_chunks
-            WHERE session_id = $1
+            WHERE session_id = $1 AND start_offset + size > $2 AND start_offset < $3
             ORDER BY chunk_index
             "#,
         )
         .bind(session_id)
+        .bind(from.min(i64::MAX as u64) as i64)
+        .bind(to.min(i64::MAX as u64) as i64)
         .fetch_all(pool)
         .await?;
 
+        let mut start = to;
         let mut result = Vec::new();
         for chunk in chunks {
-            result.extend_from_slice(&chunk.data);
+            let contents = chunk.contents()?;
+            let chunk_start = chunk.start_offset as u64;
+            let offset = chunk_start.max(from);
+            if offset != start + result.len() as u64 {
+                start = offset;
+                result.clear();
+            }
+
+            let skip = (offset - chunk_start) as usize;
+            let take = (to.saturating_sub(chunk_start) as usize).min(contents.len());
+            result.extend_from_slice(&contents[skip..take]);
         }
 
-        Ok(result)
+        Ok((start, result))
     }
 
-    pub async fn get_from_offset(pool: &PgPool, session_id: Uuid, offset: usize) -> Result<Vec<u8>> {
-        let chunks: Vec<ScrollbackChunk> = sqlx::query_as(
+    /// Up to `limit` chunks overlapping `range`, in order, starting after chunk `after_index`.
+    ///
+    /// Chunks are returned whole; callers slice them to the byte range.
+    pub async fn list_page(
+        pool: &PgPool,
+        session_id: Uuid,
+        range: &ScrollbackRange,
+        after_index: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<ScrollbackChunk>> {
+        let chunks = sqlx::query_as(
             r#"
-            SELECT id, session_id, chunk_index, data, created_at
+            SELECT id, session_id, chunk_index, start_offset, size, compressed, data, created_at
             FROM scrollback_chunks
             WHERE session_id = $1
+              AND chunk_index > $2
+              AND start_offset + size > $3
+              AND start_offset < $4
+              AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+              AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
             ORDER BY chunk_index
+            LIMIT $7
             "#,
         )
         .bind(session_id)
+        .bind(after_index.unwrap_or(-1))
+        .bind(range.from.min(i64::MAX as u64) as i64)
+        .bind(range.to.min(i64::MAX as u64) as i64)
+        .bind(range.since)
+        .bind(range.until)
+        .bind(limit)
         .fetch_all(pool)
         .await?;
 
-        let mut result = Vec::new();
-        let mut current_offset = 0;
-
-        for chunk in chunks {
-            let chunk_len = chunk.data.len();
-            if current_offset + chunk_len <= offset {
-                current_offset += chunk_len;
-                continue;
-            }
-
-            let start_in_chunk = if current_offset < offset {
-                offset - current_offset
-            } else {
-                0
-            };
-
-            result.extend_from_slice(&chunk.data[start_in_chunk..]);
-            current_offset += chunk_len;
-        }
-
-        Ok(result)
+        Ok(chunks)
     }
 
     pub async fn total_size(pool: &PgPool, session_id: Uuid) -> Result<usize> {
         let size: Option<i64> = sqlx::query_scalar(
-            "SELECT COALESCE(SUM(LENGTH(data)), 0)::BIGINT FROM scrollback_chunks WHERE session_id = $1",
+            "SELECT COALESCE(SUM(size), 0)::BIGINT FROM scrollback_chunks WHERE session_id = $1",
         )
         .bind(session_id)
         .fetch_one(pool)
@@ -549,4 +1285,450 @@ impl ScrollbackChunk {
 
         Ok(result.rows_affected() > 0)
     }
+
+    /// Delete each session's oldest chunks beyond its newest `max_bytes`, returning rows deleted
+    pub async fn trim_sessions_to(pool: &PgPool, max_bytes: u64) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM scrollback_chunks
+            WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, SUM(size) OVER (
+                        PARTITION BY session_id ORDER BY chunk_index DESC
+                    ) AS newer_bytes
+                    FROM scrollback_chunks
+                ) ranked
+                WHERE newer_bytes > $1
+            )
+            "#,
+        )
+        .bind(max_bytes.min(i64::MAX as u64) as i64)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete each user's oldest chunks, across all their sessions, beyond their newest
+    /// `max_bytes`, returning rows deleted
+    pub async fn trim_users_to(pool: &PgPool, max_bytes: u64) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM scrollback_chunks
+            WHERE id IN (
+                SELECT id FROM (
+                    SELECT c.id, SUM(c.size) OVER (
+                        PARTITION BY s.user_id ORDER BY c.created_at DESC, c.id DESC
+                    ) AS newer_bytes
+                    FROM scrollback_chunks c
+                    JOIN sessions s ON s.id = c.session_id
+                ) ranked
+                WHERE newer_bytes > $1
+            )
+            "#,
+        )
+        .bind(max_bytes.min(i64::MAX as u64) as i64)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete chunks written before `cutoff`, returning rows deleted
+    pub async fn delete_older_than(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM scrollback_chunks WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// A file uploaded to a session's remote host
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Upload {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub filename: String,
+    /// Absolute path on the remote host
+    pub path: String,
+    pub size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Upload {
+    pub async fn create(
+        pool: &PgPool,
+        session_id: Uuid,
+        filename: &str,
+        path: &str,
+        size_bytes: i64,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4();
+        let upload = sqlx::query_as::<_, Upload>(
+            r#"
+            INSERT INTO uploads (id, session_id, filename, path, size_bytes)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, session_id, filename, path, size_bytes, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(filename)
+        .bind(path)
+        .bind(size_bytes)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(upload)
+    }
+
+    pub async fn list_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<Self>> {
+        let uploads = sqlx::query_as::<_, Upload>(
+            r#"
+            SELECT id, session_id, filename, path, size_bytes, created_at
+            FROM uploads WHERE session_id = $1
+            ORDER BY created_at
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(uploads)
+    }
+}
+
+/// A chunked upload, tracked until it completes so it can resume after a disconnect
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UploadTransfer {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    pub filename: String,
+    /// Remote directory the finished file is placed in
+    pub directory: String,
+    /// Remote file the chunks are written to until the checksum is verified
+    pub partial_path: String,
+    pub size_bytes: i64,
+    pub received_bytes: i64,
+    /// Expected hex SHA-256 of the whole file
+    pub sha256: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl UploadTransfer {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        pool: &PgPool,
+        id: Uuid,
+        session_id: Uuid,
+        user_id: Uuid,
+        filename: &str,
+        directory: &str,
+        partial_path: &str,
+        size_bytes: i64,
+        sha256: &str,
+    ) -> Result<Self> {
+        let transfer = sqlx::query_as::<_, UploadTransfer>(
+            r#"
+            INSERT INTO upload_transfers
+                (id, session_id, user_id, filename, directory, partial_path, size_bytes, sha256)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, session_id, user_id, filename, directory, partial_path, size_bytes,
+                      received_bytes, sha256, status, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(user_id)
+        .bind(filename)
+        .bind(directory)
+        .bind(partial_path)
+        .bind(size_bytes)
+        .bind(sha256)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(transfer)
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let transfer = sqlx::query_as::<_, UploadTransfer>(
+            r#"
+            SELECT id, session_id, user_id, filename, directory, partial_path, size_bytes,
+                   received_bytes, sha256, status, created_at, updated_at
+            FROM upload_transfers WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(transfer)
+    }
+
+    /// Record progress, returning false if the transfer is no longer in progress
+    pub async fn set_received(pool: &PgPool, id: Uuid, received_bytes: i64) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE upload_transfers SET received_bytes = $2, updated_at = NOW()
+            WHERE id = $1 AND status = 'in_progress'
+            "#,
+        )
+        .bind(id)
+        .bind(received_bytes)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Fail transfers that have received nothing since `cutoff`, returning them
+    pub async fn expire_idle(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<Vec<Self>> {
+        let transfers = sqlx::query_as::<_, UploadTransfer>(
+            r#"
+            UPDATE upload_transfers SET status = 'failed', updated_at = NOW()
+            WHERE status = 'in_progress' AND updated_at < $1
+            RETURNING id, session_id, user_id, filename, directory, partial_path, size_bytes,
+                      received_bytes, sha256, status, created_at, updated_at
+            "#,
+        )
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(transfers)
+    }
+
+    /// Fail every unfinished transfer of a session, returning them
+    pub async fn fail_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<Self>> {
+        let transfers = sqlx::query_as::<_, UploadTransfer>(
+            r#"
+            UPDATE upload_transfers SET status = 'failed', updated_at = NOW()
+            WHERE session_id = $1 AND status = 'in_progress'
+            RETURNING id, session_id, user_id, filename, directory, partial_path, size_bytes,
+                      received_bytes, sha256, status, created_at, updated_at
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(transfers)
+    }
+
+    /// Move to `complete` or `failed`
+    pub async fn finish(pool: &PgPool, id: Uuid, status: &str) -> Result<()> {
+        sqlx::query("UPDATE upload_transfers SET status = $2, updated_at = NOW() WHERE id = $1")
+            .bind(id)
+            .bind(status)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A command run across many connections
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FleetRun {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub command: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Outcome of a fleet run on one connection
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FleetRunHost {
+    pub id: Uuid,
+    pub run_id: Uuid,
+    pub position: i32,
+    pub connection_id: Option<Uuid>,
+    pub connection_name: String,
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<String>,
+    pub error: Option<String>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub output_truncated: bool,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl FleetRun {
+    /// Record a new run with a pending host row per connection, in order
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        command: &str,
+        connections: &[Connection],
+    ) -> Result<(Self, Vec<FleetRunHost>)> {
+        let mut tx = pool.begin().await?;
+
+        let run = sqlx::query_as::<_, FleetRun>(
+            r#"
+            INSERT INTO fleet_runs (id, user_id, command)
+            VALUES ($1, $2, $3)
+            RETURNING id, user_id, command, status, created_at, finished_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(command)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut hosts = Vec::with_capacity(connections.len());
+        for (position, connection) in connections.iter().enumerate() {
+            let host = sqlx::query_as::<_, FleetRunHost>(
+                r#"
+                INSERT INTO fleet_run_hosts (id, run_id, position, connection_id, connection_name)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING id, run_id, position, connection_id, connection_name, status, exit_code,
+                          exit_signal, error, stdout, stderr, output_truncated, started_at,
+                          finished_at
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(run.id)
+            .bind(position as i32)
+            .bind(connection.id)
+            .bind(&connection.name)
+            .fetch_one(&mut *tx)
+            .await?;
+            hosts.push(host);
+        }
+
+        tx.commit().await?;
+        Ok((run, hosts))
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let run = sqlx::query_as::<_, FleetRun>(
+            r#"
+            SELECT id, user_id, command, status, created_at, finished_at
+            FROM fleet_runs WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// Most recent runs first
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid, limit: i64) -> Result<Vec<Self>> {
+        let runs = sqlx::query_as::<_, FleetRun>(
+            r#"
+            SELECT id, user_id, command, status, created_at, finished_at
+            FROM fleet_runs WHERE user_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(runs)
+    }
+
+    /// Mark runs left unfinished by a previous server process `interrupted`, along with their
+    /// pending and running hosts, returning how many runs there were
+    pub async fn interrupt_unfinished(pool: &PgPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE fleet_run_hosts
+            SET status = 'interrupted', error = 'Server restarted before the command finished',
+                finished_at = NOW()
+            WHERE status IN ('pending', 'running')
+              AND run_id IN (SELECT id FROM fleet_runs WHERE status = 'running')
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let runs = sqlx::query(
+            r#"
+            UPDATE fleet_runs SET status = 'interrupted', finished_at = NOW()
+            WHERE status = 'running'
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(runs.rows_affected())
+    }
+
+    pub async fn finish(pool: &PgPool, id: Uuid) -> Result<Self> {
+        let run = sqlx::query_as::<_, FleetRun>(
+            r#"
+            UPDATE fleet_runs SET status = 'complete', finished_at = NOW()
+            WHERE id = $1
+            RETURNING id, user_id, command, status, created_at, finished_at
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(run)
+    }
+}
+
+impl FleetRunHost {
+    pub async fn list_for_run(pool: &PgPool, run_id: Uuid) -> Result<Vec<Self>> {
+        let hosts = sqlx::query_as::<_, FleetRunHost>(
+            r#"
+            SELECT id, run_id, position, connection_id, connection_name, status, exit_code,
+                   exit_signal, error, stdout, stderr, output_truncated, started_at, finished_at
+            FROM fleet_run_hosts WHERE run_id = $1
+            ORDER BY position
+            "#,
+        )
+        .bind(run_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(hosts)
+    }
+
+    /// Write the host's current status, result and output
+    pub async fn save(&self, pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE fleet_run_hosts
+            SET status = $2, exit_code = $3, exit_signal = $4, error = $5, stdout = $6,
+                stderr = $7, output_truncated = $8, started_at = $9, finished_at = $10
+            WHERE id = $1
+            "#,
+        )
+        .bind(self.id)
+        .bind(&self.status)
+        .bind(self.exit_code)
+        .bind(&self.exit_signal)
+        .bind(&self.error)
+        .bind(&self.stdout)
+        .bind(&self.stderr)
+        .bind(self.output_truncated)
+        .bind(self.started_at)
+        .bind(self.finished_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
 }
@@ -1,8 +1,10 @@
 pub mod api;
 pub mod cli;
+pub mod crypto;
 pub mod db;
 pub mod ssh;
 pub mod terminal;
+pub mod tls;
 
 use thiserror::Error;
 
@@ -20,6 +22,21 @@ pub enum HiveError {
     #[error("SSH error: {0}")]
     Ssh(String),
 
+    #[error("Host key mismatch for {host}:{port}: expected {expected}, got {actual}")]
+    HostKeyMismatch {
+        host: String,
+        port: u16,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Unknown host key for {host}:{port}: {fingerprint}")]
+    HostKeyUnknown {
+        host: String,
+        port: u16,
+        fingerprint: String,
+    },
+
     #[error("Session error: {0}")]
     Session(String),
 
@@ -30,6 +47,12 @@ pub enum HiveError {
     Io(#[from] std::io::Error),
 }
 
+impl From<russh::Error> for HiveError {
+    fn from(e: russh::Error) -> Self {
+        HiveError::Ssh(e.to_string())
+    }
+}
+
 pub type Result<T> = std::result::Result<T, HiveError>;
 
 pub mod proto {
@@ -1,18 +1,31 @@
 use clap::Parser;
+use tokio::net::TcpListener;
 use tonic::transport::Server;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use std::sync::Arc;
 
-use hive_server::api::{AuthService, ConnectionsService, SessionsService};
-use hive_server::cli::{handle_key_command, handle_user_command, Cli, Commands};
-use hive_server::db::{create_pool, run_migrations};
+use hive_server::api::{
+    ApiKeyAuthLayer, AuthService, ConnectionsService, FilesService, FleetService, KeysService,
+    KnownHostsService, SessionsService,
+};
+use hive_server::cli::{
+    handle_key_command, handle_known_host_command, handle_scrollback_command, handle_user_command,
+    Cli, Commands,
+};
+use hive_server::crypto::MasterKey;
+use hive_server::db::{create_pool, run_migrations, FleetRun};
 use hive_server::proto::auth_server::AuthServer;
 use hive_server::proto::connections_server::ConnectionsServer;
+use hive_server::proto::files_server::FilesServer;
+use hive_server::proto::fleet_server::FleetServer;
+use hive_server::proto::keys_server::KeysServer;
+use hive_server::proto::known_hosts_server::KnownHostsServer;
 use hive_server::proto::sessions_server::SessionsServer;
 use hive_server::proto::terminal_server::TerminalServer;
-use hive_server::terminal::{SessionManager, TerminalService};
+use hive_server::terminal::{ReconnectPolicy, SessionManager, TerminalService};
+use hive_server::tls::ReloadingTlsAcceptor;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -34,6 +47,7 @@ async fn main() -> anyhow::Result<()> {
         .ok_or_else(|| anyhow::anyhow!("DATABASE_URL not set"))?;
 
     let pool = create_pool(&database_url).await?;
+    let retention = cli.scrollback_retention();
 
     match cli.command {
         Some(Commands::Migrate) => {
@@ -47,6 +61,12 @@ async fn main() -> anyhow::Result<()> {
         Some(Commands::Key { action }) => {
             handle_key_command(&pool, action).await?;
         }
+        Some(Commands::KnownHost { action }) => {
+            handle_known_host_command(&pool, action).await?;
+        }
+        Some(Commands::Scrollback { action }) => {
+            handle_scrollback_command(&pool, &retention, action).await?;
+        }
         Some(Commands::Serve) | None => {
             // Run migrations before starting server
             run_migrations(&pool).await?;
@@ -54,22 +74,114 @@ async fn main() -> anyhow::Result<()> {
             let addr = cli.listen.parse()?;
             info!("Starting Hive Server on {}", addr);
 
-            let session_manager = Arc::new(SessionManager::new(pool.clone()));
+            let master_key = cli.master_key.as_deref().map(MasterKey::parse).transpose()?;
+            if master_key.is_none() {
+                warn!("No master key configured, SSH key authentication is disabled");
+            }
+
+            if !retention.is_unlimited() {
+                let interval = std::time::Duration::from_secs(cli.scrollback_gc_interval.max(1));
+                retention.spawn_gc(pool.clone(), interval);
+            }
+
+            let mut session_manager = SessionManager::new(pool.clone())
+                .with_host_key_policy(cli.host_key_policy)
+                .with_upload_dir(cli.upload_dir.clone())
+                .with_reconnect_policy(ReconnectPolicy {
+                    max_attempts: cli.reconnect_attempts,
+                    ..Default::default()
+                });
+            if let Some(master_key) = master_key.clone() {
+                session_manager = session_manager.with_master_key(master_key);
+            }
+            let session_manager = Arc::new(session_manager);
+
+            let restored = session_manager.restore_sessions().await?;
+            if restored.restored > 0 || restored.lost > 0 {
+                info!(
+                    "Restored {} sessions from the previous run, {} lost",
+                    restored.restored, restored.lost
+                );
+            }
+            let interrupted = FleetRun::interrupt_unfinished(&pool).await?;
+            if interrupted > 0 {
+                info!("Marked {} fleet runs from the previous run interrupted", interrupted);
+            }
+
+            let reaper = cli.reaper_policy();
+            if !reaper.is_unlimited() {
+                let interval = std::time::Duration::from_secs(cli.reaper_interval.max(1));
+                reaper.spawn(session_manager.clone(), interval);
+            }
 
             let auth_service = AuthService::new(pool.clone());
             let connections_service = ConnectionsService::new(pool.clone());
+            let keys_service = KeysService::new(pool.clone(), master_key);
+            let known_hosts_service = KnownHostsService::new(pool.clone());
             let sessions_service = SessionsService::new(pool.clone(), session_manager.clone());
-            let terminal_service = TerminalService::new(session_manager);
+            let terminal_service = TerminalService::new(session_manager.clone());
+            let files_service = FilesService::new(pool.clone(), session_manager.clone());
+            let fleet_service = FleetService::new(pool.clone(), session_manager.clone());
 
-            Server::builder()
+            let router = Server::builder()
+                .layer(ApiKeyAuthLayer::new(pool.clone()))
                 .add_service(AuthServer::new(auth_service))
                 .add_service(ConnectionsServer::new(connections_service))
+                .add_service(KeysServer::new(keys_service))
+                .add_service(KnownHostsServer::new(known_hosts_service))
                 .add_service(SessionsServer::new(sessions_service))
                 .add_service(TerminalServer::new(terminal_service))
-                .serve(addr)
-                .await?;
+                .add_service(FilesServer::new(files_service))
+                .add_service(FleetServer::new(fleet_service));
+
+            let shutdown = session_manager.disconnect_on(shutdown_signal());
+            match cli.tls_options() {
+                Some(tls_options) => {
+                    if tls_options.client_ca_path.is_some() {
+                        info!("Serving gRPC over TLS, client certificates required");
+                    } else {
+                        info!("Serving gRPC over TLS");
+                    }
+
+                    let acceptor = ReloadingTlsAcceptor::new(tls_options)?;
+                    acceptor.spawn_reloader();
+
+                    let listener = TcpListener::bind(addr).await?;
+                    router
+                        .serve_with_incoming_shutdown(acceptor.incoming(listener), shutdown)
+                        .await?;
+                }
+                None => {
+                    warn!("Serving gRPC without TLS, API keys and terminal data travel in plaintext");
+                    router.serve_with_shutdown(addr, shutdown).await?;
+                }
+            }
         }
     }
 
     Ok(())
 }
+
+/// Resolves on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
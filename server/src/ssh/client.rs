@@ -5,6 +5,7 @@ use russh::keys::key::PublicKey;
 use russh::{Channel, Disconnect};
 use tracing::{debug, info};
 
+use super::HostKeyVerifier;
 use crate::{HiveError, Result};
 
 pub struct SshClient {
@@ -12,28 +13,24 @@ pub struct SshClient {
 }
 
 struct ClientHandler {
-    host: String,
+    verifier: HostKeyVerifier,
 }
 
 #[async_trait::async_trait]
 impl client::Handler for ClientHandler {
-    type Error = russh::Error;
+    type Error = HiveError;
 
     async fn check_server_key(
         &mut self,
         server_public_key: &PublicKey,
     ) -> std::result::Result<bool, Self::Error> {
-        info!(
-            "Accepting server key for {}: {:?}",
-            self.host,
-            server_public_key.fingerprint()
-        );
+        self.verifier.verify(server_public_key).await?;
         Ok(true)
     }
 }
 
 impl SshClient {
-    pub async fn connect(host: &str, port: u16) -> Result<Self> {
+    pub async fn connect(host: &str, port: u16, verifier: HostKeyVerifier) -> Result<Self> {
         let config = Config {
             inactivity_timeout: Some(std::time::Duration::from_secs(3600)),
             keepalive_interval: Some(std::time::Duration::from_secs(30)),
@@ -42,16 +39,17 @@ impl SshClient {
         };
 
         let config = Arc::new(config);
-        let handler = ClientHandler {
-            host: host.to_string(),
-        };
+        let handler = ClientHandler { verifier };
 
         let addr = format!("{}:{}", host, port);
         debug!("Connecting to SSH server at {}", addr);
 
         let handle = client::connect(config, &addr, handler)
             .await
-            .map_err(|e| HiveError::Ssh(format!("Failed to connect to {}: {}", addr, e)))?;
+            .map_err(|e| match e {
+                HiveError::Ssh(msg) => HiveError::Ssh(format!("Failed to connect to {}: {}", addr, msg)),
+                e => e,
+            })?;
 
         info!("Connected to SSH server at {}", addr);
 
@@ -0,0 +1,32 @@
+use russh::keys::key::{KeyPair, PublicKey};
+use russh::keys::{decode_secret_key, parse_public_key_base64, PublicKeyBase64};
+
+use super::key_type;
+use crate::{HiveError, Result};
+
+/// Parse an unencrypted OpenSSH/PEM private key
+pub fn parse_private_key(pem: &str) -> Result<KeyPair> {
+    decode_secret_key(pem, None).map_err(|e| match e {
+        russh::keys::Error::KeyIsEncrypted => {
+            HiveError::Ssh("Passphrase-protected private keys are not supported".into())
+        }
+        e => HiveError::Ssh(format!("Invalid private key: {}", e)),
+    })
+}
+
+/// Parse an `authorized_keys`-style line (`<type> <base64> [comment]`)
+pub fn parse_public_key(line: &str) -> Result<PublicKey> {
+    let mut fields = line.split_whitespace();
+    let encoded = match (fields.next(), fields.next()) {
+        (Some(_key_type), Some(encoded)) => encoded,
+        (Some(encoded), None) => encoded,
+        _ => return Err(HiveError::Ssh("Public key is empty".into())),
+    };
+
+    parse_public_key_base64(encoded).map_err(|e| HiveError::Ssh(format!("Invalid public key: {}", e)))
+}
+
+/// Render a public key as an `authorized_keys` line without comment
+pub fn openssh_public_key(key: &PublicKey) -> String {
+    format!("{} {}", key_type(key), key.public_key_base64())
+}
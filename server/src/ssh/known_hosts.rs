@@ -0,0 +1,344 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use russh::client::{self, Config};
+use russh::keys::key::PublicKey;
+use russh::keys::{parse_public_key_base64, PublicKeyBase64};
+use sha1::Sha1;
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::db::{Connection, KnownHost};
+use crate::{HiveError, Result};
+
+const HOST_KEY_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to treat a host whose key has never been seen before
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HostKeyPolicy {
+    /// Record the first key seen for a host and reject any later change
+    #[default]
+    #[value(name = "tofu")]
+    TrustOnFirstUse,
+    /// Refuse unknown hosts until their key is explicitly accepted
+    Strict,
+}
+
+/// Checks server keys against the `known_hosts` table for one connection
+#[derive(Clone)]
+pub struct HostKeyVerifier {
+    pool: PgPool,
+    user_id: Uuid,
+    connection_id: Option<Uuid>,
+    host: String,
+    port: u16,
+    policy: HostKeyPolicy,
+}
+
+impl HostKeyVerifier {
+    pub fn new(
+        pool: PgPool,
+        user_id: Uuid,
+        connection_id: Option<Uuid>,
+        host: &str,
+        port: u16,
+        policy: HostKeyPolicy,
+    ) -> Self {
+        Self {
+            pool,
+            user_id,
+            connection_id,
+            host: host.to_string(),
+            port,
+            policy,
+        }
+    }
+
+    pub fn for_connection(pool: PgPool, connection: &Connection, policy: HostKeyPolicy) -> Self {
+        Self::new(
+            pool,
+            connection.user_id,
+            Some(connection.id),
+            &connection.host,
+            connection.port as u16,
+            policy,
+        )
+    }
+
+    pub async fn verify(&self, key: &PublicKey) -> Result<()> {
+        let public_key = key.public_key_base64();
+        let actual = fingerprint(key);
+
+        let known = KnownHost::find_for_host(
+            &self.pool,
+            self.user_id,
+            self.connection_id,
+            &self.host,
+            self.port as i32,
+        )
+        .await?;
+
+        if known.iter().any(|k| k.public_key == public_key) {
+            return Ok(());
+        }
+
+        if !known.is_empty() {
+            let expected = known
+                .iter()
+                .find(|k| k.key_type == key_type(key))
+                .unwrap_or(&known[0])
+                .fingerprint
+                .clone();
+
+            warn!(
+                "Host key mismatch for {}:{}: expected {}, got {}",
+                self.host, self.port, expected, actual
+            );
+
+            return Err(HiveError::HostKeyMismatch {
+                host: self.host.clone(),
+                port: self.port,
+                expected,
+                actual,
+            });
+        }
+
+        match self.policy {
+            HostKeyPolicy::TrustOnFirstUse => {
+                KnownHost::create(
+                    &self.pool,
+                    self.user_id,
+                    self.connection_id,
+                    &self.host,
+                    self.port as i32,
+                    key_type(key),
+                    &public_key,
+                    &actual,
+                )
+                .await?;
+
+                info!("Learned host key for {}:{}: {}", self.host, self.port, actual);
+                Ok(())
+            }
+            HostKeyPolicy::Strict => Err(HiveError::HostKeyUnknown {
+                host: self.host.clone(),
+                port: self.port,
+                fingerprint: actual,
+            }),
+        }
+    }
+}
+
+/// Key algorithm as written in known_hosts files (RSA signature variants share `ssh-rsa`)
+pub fn key_type(key: &PublicKey) -> &'static str {
+    match key {
+        PublicKey::RSA { .. } => "ssh-rsa",
+        _ => key.name(),
+    }
+}
+
+/// OpenSSH-style `SHA256:...` fingerprint
+pub fn fingerprint(key: &PublicKey) -> String {
+    format!("SHA256:{}", key.fingerprint())
+}
+
+struct ProbeHandler {
+    key: Arc<Mutex<Option<PublicKey>>>,
+}
+
+#[async_trait::async_trait]
+impl client::Handler for ProbeHandler {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> std::result::Result<bool, Self::Error> {
+        *self.key.lock().unwrap() = Some(server_public_key.clone());
+        // Abort the handshake, only the key was needed
+        Ok(false)
+    }
+}
+
+/// Connect just far enough to read the host key a server presents
+pub async fn fetch_host_key(host: &str, port: u16) -> Result<PublicKey> {
+    let key = Arc::new(Mutex::new(None));
+    let handler = ProbeHandler { key: key.clone() };
+    let addr = format!("{}:{}", host, port);
+
+    let probe = client::connect(Arc::new(Config::default()), &addr, handler);
+    match tokio::time::timeout(HOST_KEY_PROBE_TIMEOUT, probe).await {
+        Err(_) => return Err(HiveError::Ssh(format!("Timed out probing host key of {}", addr))),
+        Ok(Ok(_)) | Ok(Err(russh::Error::UnknownKey)) => {}
+        Ok(Err(e)) => return Err(HiveError::Ssh(format!("Failed to connect to {}: {}", addr, e))),
+    }
+
+    let key = key.lock().unwrap().take();
+    key.ok_or_else(|| HiveError::Ssh(format!("{} did not present a host key", addr)))
+}
+
+/// One usable line of an OpenSSH known_hosts file
+#[derive(Debug, Clone)]
+pub struct KnownHostsLine {
+    pub hostnames: Vec<String>,
+    pub key: PublicKey,
+}
+
+impl KnownHostsLine {
+    /// Whether any hostname (plain or hashed) on this line names `host:port`
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        self.hostnames.iter().any(|pattern| {
+            if let Some(hashed) = pattern.strip_prefix("|1|") {
+                return hashed_hostname_matches(hashed, host, port);
+            }
+            parse_host_port(pattern).is_some_and(|(h, p)| h == host && p == port)
+        })
+    }
+
+    /// Plain `host:port` pairs; hashed names and wildcard patterns are skipped
+    pub fn plain_hosts(&self) -> Vec<(String, u16)> {
+        self.hostnames
+            .iter()
+            .filter(|pattern| !pattern.starts_with('|'))
+            .filter_map(|pattern| parse_host_port(pattern))
+            .collect()
+    }
+}
+
+fn parse_host_port(pattern: &str) -> Option<(String, u16)> {
+    if pattern.starts_with('!') || pattern.contains(['*', '?']) {
+        return None;
+    }
+
+    match pattern.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest.split_once("]:")?;
+            Some((host.to_string(), port.parse().ok()?))
+        }
+        None => Some((pattern.to_string(), 22)),
+    }
+}
+
+fn hashed_hostname_matches(hashed: &str, host: &str, port: u16) -> bool {
+    let Some((salt, hash)) = hashed.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (BASE64.decode(salt), BASE64.decode(hash)) else {
+        return false;
+    };
+
+    let name = if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    };
+
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(name.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+/// Parse an OpenSSH known_hosts file, returning usable lines and the number skipped
+pub fn parse_known_hosts(content: &str) -> (Vec<KnownHostsLine>, usize) {
+    let mut lines = Vec::new();
+    let mut skipped = 0;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // @cert-authority and @revoked markers are not supported
+        if line.starts_with('@') {
+            skipped += 1;
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(hostnames), Some(_key_type), Some(key)) = (fields.next(), fields.next(), fields.next())
+        else {
+            skipped += 1;
+            continue;
+        };
+
+        match parse_public_key_base64(key) {
+            Ok(key) => lines.push(KnownHostsLine {
+                hostnames: hostnames.split(',').map(str::to_string).collect(),
+                key,
+            }),
+            Err(_) => skipped += 1,
+        }
+    }
+
+    (lines, skipped)
+}
+
+/// Outcome of importing a known_hosts file
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+/// Import an OpenSSH known_hosts file for a user.
+///
+/// With a connection, only lines naming its host (including hashed names) are
+/// imported and pinned to it; otherwise every plain hostname becomes a host-wide entry.
+pub async fn import_known_hosts(
+    pool: &PgPool,
+    user_id: Uuid,
+    connection: Option<&Connection>,
+    content: &str,
+) -> Result<ImportSummary> {
+    let (lines, mut skipped) = parse_known_hosts(content);
+    let mut imported = 0;
+
+    for line in lines {
+        let public_key = line.key.public_key_base64();
+        let fingerprint = fingerprint(&line.key);
+        let key_type = key_type(&line.key);
+
+        let targets: Vec<(Option<Uuid>, String, u16)> = match connection {
+            Some(conn) if line.matches(&conn.host, conn.port as u16) => {
+                vec![(Some(conn.id), conn.host.clone(), conn.port as u16)]
+            }
+            Some(_) => Vec::new(),
+            None => line
+                .plain_hosts()
+                .into_iter()
+                .map(|(host, port)| (None, host, port))
+                .collect(),
+        };
+
+        if targets.is_empty() {
+            skipped += 1;
+            continue;
+        }
+
+        for (connection_id, host, port) in targets {
+            KnownHost::create(
+                pool,
+                user_id,
+                connection_id,
+                &host,
+                port as i32,
+                key_type,
+                &public_key,
+                &fingerprint,
+            )
+            .await?;
+            imported += 1;
+        }
+    }
+
+    info!("Imported {} known host keys for user {} ({} skipped)", imported, user_id, skipped);
+
+    Ok(ImportSummary { imported, skipped })
+}
@@ -1,5 +1,19 @@
 mod client;
+mod keys;
+mod known_hosts;
+mod pty;
 mod session;
+mod sftp;
 
 pub use client::SshClient;
+pub use keys::{openssh_public_key, parse_private_key, parse_public_key};
+pub use known_hosts::{
+    fetch_host_key, fingerprint, import_known_hosts, key_type, parse_known_hosts, HostKeyPolicy,
+    HostKeyVerifier, ImportSummary, KnownHostsLine,
+};
+pub use pty::{pty_mode, DEFAULT_TERM};
 pub use session::SshSession;
+pub use sftp::{
+    create_dir_all, create_unique, join_path, replace_file, sanitize_filename, sftp_error,
+    shell_quote, unused_path, Sftp,
+};
@@ -0,0 +1,69 @@
+use russh::Pty;
+
+/// TERM requested when a connection does not set one
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Terminal modes by their RFC 4254 name
+const PTY_MODES: &[(&str, Pty)] = &[
+    ("VINTR", Pty::VINTR),
+    ("VQUIT", Pty::VQUIT),
+    ("VERASE", Pty::VERASE),
+    ("VKILL", Pty::VKILL),
+    ("VEOF", Pty::VEOF),
+    ("VEOL", Pty::VEOL),
+    ("VEOL2", Pty::VEOL2),
+    ("VSTART", Pty::VSTART),
+    ("VSTOP", Pty::VSTOP),
+    ("VSUSP", Pty::VSUSP),
+    ("VDSUSP", Pty::VDSUSP),
+    ("VREPRINT", Pty::VREPRINT),
+    ("VWERASE", Pty::VWERASE),
+    ("VLNEXT", Pty::VLNEXT),
+    ("VFLUSH", Pty::VFLUSH),
+    ("VSWTCH", Pty::VSWTCH),
+    ("VSTATUS", Pty::VSTATUS),
+    ("VDISCARD", Pty::VDISCARD),
+    ("IGNPAR", Pty::IGNPAR),
+    ("PARMRK", Pty::PARMRK),
+    ("INPCK", Pty::INPCK),
+    ("ISTRIP", Pty::ISTRIP),
+    ("INLCR", Pty::INLCR),
+    ("IGNCR", Pty::IGNCR),
+    ("ICRNL", Pty::ICRNL),
+    ("IUCLC", Pty::IUCLC),
+    ("IXON", Pty::IXON),
+    ("IXANY", Pty::IXANY),
+    ("IXOFF", Pty::IXOFF),
+    ("IMAXBEL", Pty::IMAXBEL),
+    ("IUTF8", Pty::IUTF8),
+    ("ISIG", Pty::ISIG),
+    ("ICANON", Pty::ICANON),
+    ("XCASE", Pty::XCASE),
+    ("ECHO", Pty::ECHO),
+    ("ECHOE", Pty::ECHOE),
+    ("ECHOK", Pty::ECHOK),
+    ("ECHONL", Pty::ECHONL),
+    ("NOFLSH", Pty::NOFLSH),
+    ("TOSTOP", Pty::TOSTOP),
+    ("IEXTEN", Pty::IEXTEN),
+    ("ECHOCTL", Pty::ECHOCTL),
+    ("ECHOKE", Pty::ECHOKE),
+    ("PENDIN", Pty::PENDIN),
+    ("OPOST", Pty::OPOST),
+    ("OLCUC", Pty::OLCUC),
+    ("ONLCR", Pty::ONLCR),
+    ("OCRNL", Pty::OCRNL),
+    ("ONOCR", Pty::ONOCR),
+    ("ONLRET", Pty::ONLRET),
+    ("CS7", Pty::CS7),
+    ("CS8", Pty::CS8),
+    ("PARENB", Pty::PARENB),
+    ("PARODD", Pty::PARODD),
+    ("TTY_OP_ISPEED", Pty::TTY_OP_ISPEED),
+    ("TTY_OP_OSPEED", Pty::TTY_OP_OSPEED),
+];
+
+/// Terminal mode opcode by its RFC 4254 name, e.g. `ECHO` or `VERASE`
+pub fn pty_mode(name: &str) -> Option<Pty> {
+    PTY_MODES.iter().find(|(mode, _)| *mode == name).map(|&(_, pty)| pty)
+}
@@ -5,6 +5,7 @@ use russh::keys::key::PublicKey;
 use russh::Channel;
 use tracing::{debug, info};
 
+use super::HostKeyVerifier;
 use crate::{HiveError, Result};
 
 pub struct SshSession {
@@ -12,22 +13,18 @@ pub struct SshSession {
 }
 
 struct SessionHandler {
-    host: String,
+    verifier: HostKeyVerifier,
 }
 
 #[async_trait::async_trait]
 impl client::Handler for SessionHandler {
-    type Error = russh::Error;
+    type Error = HiveError;
 
     async fn check_server_key(
         &mut self,
         server_public_key: &PublicKey,
     ) -> std::result::Result<bool, Self::Error> {
-        info!(
-            "Accepting server key for {}: {:?}",
-            self.host,
-            server_public_key.fingerprint()
-        );
+        self.verifier.verify(server_public_key).await?;
         Ok(true)
     }
 }
@@ -40,6 +37,7 @@ impl SshSession {
         password: &str,
         cols: u32,
         rows: u32,
+        verifier: HostKeyVerifier,
     ) -> Result<Self> {
         let config = Config {
             inactivity_timeout: Some(std::time::Duration::from_secs(3600)),
@@ -50,16 +48,17 @@ impl SshSession {
 
         let config = Arc::new(config);
 
-        let handler = SessionHandler {
-            host: host.to_string(),
-        };
+        let handler = SessionHandler { verifier };
 
         let addr = format!("{}:{}", host, port);
         debug!("Connecting to SSH server at {}", addr);
 
         let mut handle = client::connect(config, &addr, handler)
             .await
-            .map_err(|e| HiveError::Ssh(format!("Failed to connect to {}: {}", addr, e)))?;
+            .map_err(|e| match e {
+                HiveError::Ssh(msg) => HiveError::Ssh(format!("Failed to connect to {}: {}", addr, msg)),
+                e => e,
+            })?;
 
         info!("Connected to SSH server at {}", addr);
 
@@ -0,0 +1,216 @@
+use std::ops::Deref;
+
+use russh::client::{self, Msg};
+use russh::Channel;
+use russh_sftp::client::fs::File;
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use russh_sftp::protocol::{OpenFlags, Packet, StatusCode};
+
+use crate::{HiveError, Result};
+
+/// Give up on numbered names after this many collisions
+const MAX_NAME_ATTEMPTS: usize = 1000;
+/// Longest file name kept from the client, in bytes
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Rename that replaces an existing target in one step, as rename(2) does
+const POSIX_RENAME: &str = "posix-rename@openssh.com";
+
+/// SFTP on one SSH connection.
+///
+/// russh-sftp's `SftpSession` cannot send extension requests, so when the server offers
+/// `posix-rename@openssh.com` a second, raw SFTP channel is kept open to issue it.
+pub struct Sftp {
+    session: SftpSession,
+    posix_rename: Option<RawSftpSession>,
+}
+
+impl Deref for Sftp {
+    type Target = SftpSession;
+
+    fn deref(&self) -> &SftpSession {
+        &self.session
+    }
+}
+
+impl Sftp {
+    /// Start the SFTP subsystem on new channels of `handle`
+    pub async fn open<H: client::Handler>(handle: &client::Handle<H>) -> Result<Self> {
+        let session = SftpSession::new(start_subsystem(handle).await?.into_stream())
+            .await
+            .map_err(sftp_error)?;
+
+        let raw = RawSftpSession::new(start_subsystem(handle).await?.into_stream());
+        let version = raw.init().await.map_err(sftp_error)?;
+        let posix_rename = version
+            .extensions
+            .get(POSIX_RENAME)
+            .is_some_and(|v| v == "1")
+            .then_some(raw);
+
+        Ok(Self { session, posix_rename })
+    }
+
+    /// Rename `from` to `to` with `posix-rename@openssh.com`, replacing `to` if it exists.
+    ///
+    /// Returns `Ok(false)` if the server does not support the extension.
+    async fn rename_over(&self, from: &str, to: &str) -> Result<bool> {
+        let Some(raw) = &self.posix_rename else {
+            return Ok(false);
+        };
+
+        let mut data = Vec::with_capacity(8 + from.len() + to.len());
+        for path in [from, to] {
+            data.extend_from_slice(&(path.len() as u32).to_be_bytes());
+            data.extend_from_slice(path.as_bytes());
+        }
+
+        match raw.extended(POSIX_RENAME, data).await.map_err(sftp_error)? {
+            Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(true),
+            Packet::Status(status) => Err(HiveError::Ssh(format!(
+                "SFTP error: Failed to rename {} to {}: {}",
+                from, to, status.error_message
+            ))),
+            _ => Err(HiveError::Ssh(format!("SFTP error: Unexpected reply renaming {}", from))),
+        }
+    }
+}
+
+async fn start_subsystem<H: client::Handler>(handle: &client::Handle<H>) -> Result<Channel<Msg>> {
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| HiveError::Ssh(format!("Failed to open SFTP channel: {}", e)))?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(|e| HiveError::Ssh(format!("Failed to start SFTP: {}", e)))?;
+    Ok(channel)
+}
+
+pub fn sftp_error(e: russh_sftp::client::error::Error) -> HiveError {
+    HiveError::Ssh(format!("SFTP error: {}", e))
+}
+
+/// Reduce a client-supplied file name to a single, non-hidden path component
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+
+    let mut end = cleaned.len().min(MAX_FILENAME_BYTES);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match &cleaned[..end] {
+        "" => "upload".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// `name` for the first attempt, then `stem-1.ext`, `stem-2.ext`, ...
+fn numbered_name(name: &str, attempt: usize) -> String {
+    if attempt == 0 {
+        return name.to_string();
+    }
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..dot], attempt, &name[dot..]),
+        _ => format!("{}-{}", name, attempt),
+    }
+}
+
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), name)
+    }
+}
+
+/// Create `dir` and any missing parents
+pub async fn create_dir_all(sftp: &SftpSession, dir: &str) -> Result<()> {
+    let mut path = if dir.starts_with('/') { String::from("/") } else { String::new() };
+
+    for component in dir.split('/').filter(|c| !c.is_empty()) {
+        path = join_path(&path, component);
+        if !sftp.try_exists(path.as_str()).await.map_err(sftp_error)? {
+            if let Err(e) = sftp.create_dir(path.as_str()).await {
+                // Another upload may have created it in the meantime
+                if !sftp.try_exists(path.as_str()).await.unwrap_or(false) {
+                    return Err(sftp_error(e));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// First path in `dir` for `filename` that does not exist yet, numbering the name on collision
+pub async fn unused_path(sftp: &SftpSession, dir: &str, filename: &str) -> Result<String> {
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let path = join_path(dir, &numbered_name(filename, attempt));
+        if !sftp.try_exists(path.as_str()).await.map_err(sftp_error)? {
+            return Ok(path);
+        }
+    }
+
+    Err(HiveError::Ssh(format!("No free file name for {} in {}", filename, dir)))
+}
+
+/// Create a new file in `dir` without replacing anything, numbering the name on collision.
+///
+/// Returns the path that was created along with the open file.
+pub async fn create_unique(sftp: &SftpSession, dir: &str, filename: &str) -> Result<(String, File)> {
+    let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let path = join_path(dir, &numbered_name(filename, attempt));
+        match sftp.open_with_flags(path.as_str(), flags).await {
+            Ok(file) => return Ok((path, file)),
+            Err(e) => {
+                if !sftp.try_exists(path.as_str()).await.unwrap_or(false) {
+                    return Err(sftp_error(e));
+                }
+            }
+        }
+    }
+
+    Err(HiveError::Ssh(format!("No free file name for {} in {}", filename, dir)))
+}
+
+/// Move `from` over `to`, replacing any existing file.
+///
+/// With `posix-rename@openssh.com` the replacement is atomic. Servers without it refuse to
+/// overwrite with a plain rename, so an existing target is first moved aside: `to` is then
+/// briefly missing, and stays missing if moving it back after a failure fails too.
+pub async fn replace_file(sftp: &Sftp, from: &str, to: &str) -> Result<()> {
+    if sftp.rename_over(from, to).await? {
+        return Ok(());
+    }
+    if sftp.rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    let backup = format!("{}.hive-{}.bak", to, uuid::Uuid::new_v4());
+    sftp.rename(to, backup.as_str()).await.map_err(sftp_error)?;
+    if let Err(e) = sftp.rename(from, to).await {
+        let _ = sftp.rename(backup.as_str(), to).await;
+        return Err(sftp_error(e));
+    }
+
+    let _ = sftp.remove_file(backup).await;
+    Ok(())
+}
+
+/// Quote a path for a POSIX shell, leaving common safe paths untouched
+pub fn shell_quote(path: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "/._-+:@%,=".contains(c);
+    if !path.is_empty() && path.chars().all(is_safe) {
+        return path.to_string();
+    }
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
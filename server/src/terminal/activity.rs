@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tokio::time::Instant;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::db::Session as DbSession;
+
+/// Shortest time between writes of a session's `last_activity`
+const WRITE_INTERVAL: Duration = Duration::from_secs(30);
+
+struct ActivityState {
+    last_activity: Instant,
+    last_written: Option<Instant>,
+    clients: usize,
+    /// When the last client detached, or the session started if none has attached yet
+    unattended_since: Option<Instant>,
+}
+
+/// Input, output and attached clients of a live session.
+///
+/// Every byte of input or output counts as activity, but the database copy is only written
+/// once per `WRITE_INTERVAL`, so a busy session does not turn into a stream of updates.
+pub(super) struct Activity {
+    pool: PgPool,
+    session_id: Uuid,
+    state: Mutex<ActivityState>,
+}
+
+impl Activity {
+    pub fn new(pool: PgPool, session_id: Uuid) -> Self {
+        let now = Instant::now();
+        Self {
+            pool,
+            session_id,
+            state: Mutex::new(ActivityState {
+                last_activity: now,
+                last_written: None,
+                clients: 0,
+                unattended_since: Some(now),
+            }),
+        }
+    }
+
+    /// Record input or output, writing it to the database if the last write is old enough
+    pub fn touch(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.last_activity = now;
+        if state.last_written.is_some_and(|written| now - written < WRITE_INTERVAL) {
+            return;
+        }
+        state.last_written = Some(now);
+
+        let (pool, session_id) = (self.pool.clone(), self.session_id);
+        tokio::spawn(async move {
+            if let Err(e) = DbSession::update_activity(&pool, session_id).await {
+                warn!("Failed to record activity of session {}: {}", session_id, e);
+            }
+        });
+    }
+
+    /// Time since the last input or output
+    pub fn idle_for(&self) -> Duration {
+        self.state.lock().unwrap().last_activity.elapsed()
+    }
+
+    /// Time since a client was last attached, or None while one is
+    pub fn unattended_for(&self) -> Option<Duration> {
+        self.state.lock().unwrap().unattended_since.map(|since| since.elapsed())
+    }
+
+    /// Start the idle and unattended clocks over, e.g. when a suspended session resumes
+    pub fn reset(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.last_activity = now;
+        if state.clients == 0 {
+            state.unattended_since = Some(now);
+        }
+    }
+
+    pub fn attach(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.clients += 1;
+        state.unattended_since = None;
+    }
+
+    pub fn detach(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.clients = state.clients.saturating_sub(1);
+        if state.clients == 0 {
+            state.unattended_since = Some(Instant::now());
+        }
+    }
+}
+
+/// Counts as a client attached to the session until dropped
+pub struct AttachedClient(Arc<Activity>);
+
+impl AttachedClient {
+    pub(super) fn new(activity: Arc<Activity>) -> Self {
+        activity.attach();
+        Self(activity)
+    }
+}
+
+impl Drop for AttachedClient {
+    fn drop(&mut self) {
+        self.0.detach();
+    }
+}
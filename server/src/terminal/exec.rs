@@ -0,0 +1,88 @@
+use russh::client::{self, Msg};
+use russh::keys::key::PublicKey;
+use russh::{Channel, ChannelMsg, Disconnect, Sig};
+use tracing::debug;
+
+use super::manager::signal_name;
+use crate::ssh::HostKeyVerifier;
+use crate::HiveError;
+
+/// Only checks the host key; command output is read from the exec channel itself
+pub(super) struct ExecHandler {
+    pub(super) verifier: HostKeyVerifier,
+}
+
+#[async_trait::async_trait]
+impl client::Handler for ExecHandler {
+    type Error = HiveError;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> std::result::Result<bool, Self::Error> {
+        self.verifier.verify(server_public_key).await?;
+        Ok(true)
+    }
+}
+
+/// How a remote command ended
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecExit {
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecEvent {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// Always the last event
+    Exit(ExecExit),
+}
+
+/// A command running on its own SSH connection, without a PTY
+pub struct ExecProcess {
+    pub(super) handle: client::Handle<ExecHandler>,
+    pub(super) channel: Channel<Msg>,
+    pub(super) exit: Option<ExecExit>,
+}
+
+impl ExecProcess {
+    /// Next piece of output, or the exit once the channel closes; None after that
+    pub async fn next(&mut self) -> Option<ExecEvent> {
+        loop {
+            let exit = self.exit.as_mut()?;
+            match self.channel.wait().await {
+                Some(ChannelMsg::Data { data }) => return Some(ExecEvent::Stdout(data.to_vec())),
+                // Extended data type 1 is stderr, the only one defined
+                Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                    return Some(ExecEvent::Stderr(data.to_vec()))
+                }
+                Some(ChannelMsg::ExitStatus { exit_status }) => {
+                    exit.exit_code = Some(exit_status as i32);
+                }
+                Some(ChannelMsg::ExitSignal { signal_name: signal, .. }) => {
+                    exit.exit_signal = Some(signal_name(&signal));
+                }
+                Some(ChannelMsg::Close) | None => return self.exit.take().map(ExecEvent::Exit),
+                Some(msg) => debug!("Ignoring exec channel message {:?}", msg),
+            }
+        }
+    }
+
+    /// Ask the remote side to terminate the command
+    pub async fn kill(&self) {
+        if let Err(e) = self.channel.signal(Sig::TERM).await {
+            debug!("Failed to signal remote command: {}", e);
+        }
+    }
+
+    /// Close the channel and the connection
+    pub async fn close(self) {
+        let _ = self.channel.close().await;
+        let _ = self
+            .handle
+            .disconnect(Disconnect::ByApplication, "Command finished", "en")
+            .await;
+    }
+}
@@ -0,0 +1,32 @@
+use uuid::Uuid;
+
+use crate::HiveError;
+
+/// Live sessions that receive the same input, e.g. to type into several servers at once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionGroup {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// In the order they were added, without duplicates
+    pub session_ids: Vec<Uuid>,
+}
+
+impl SessionGroup {
+    pub fn add(&mut self, session_id: Uuid) {
+        if !self.session_ids.contains(&session_id) {
+            self.session_ids.push(session_id);
+        }
+    }
+
+    pub fn remove(&mut self, session_id: Uuid) {
+        self.session_ids.retain(|&id| id != session_id);
+    }
+}
+
+/// Outcome of sending broadcast input to one member of a group
+#[derive(Debug)]
+pub struct BroadcastResult {
+    pub session_id: Uuid,
+    pub result: Result<(), HiveError>,
+}
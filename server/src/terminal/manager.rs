@@ -1,72 +1,345 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
 
 use russh::client::{self, Config, Msg};
 use russh::keys::key::PublicKey;
-use russh::Channel;
+use russh::{Channel, ChannelId, ChannelMsg, Disconnect, Pty, Sig};
 use sqlx::PgPool;
-use tokio::sync::{broadcast, Mutex, RwLock};
-use tracing::{debug, error, info};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, oneshot, watch, Mutex, RwLock};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::db::{Connection as DbConnection, ScrollbackChunk, Session as DbSession};
+use crate::crypto::MasterKey;
+use crate::db::{
+    Connection as DbConnection, ConnectionEnv, ConnectionPtyMode, ScrollbackChunk,
+    Session as DbSession, SshKey, Upload, UploadTransfer,
+};
+use crate::ssh::{
+    create_dir_all, create_unique, parse_private_key, pty_mode, sanitize_filename, sftp_error,
+    HostKeyPolicy, HostKeyVerifier, Sftp, DEFAULT_TERM,
+};
 use crate::{HiveError, Result};
 
+use super::activity::{Activity, AttachedClient};
+use super::exec::{ExecExit, ExecHandler, ExecProcess};
+use super::group::{BroadcastResult, SessionGroup};
+use super::output::{OutputChunk, OutputLog, OutputSubscription};
+use super::persistence::Persistence;
+use super::reconnect::{LinkState, ReconnectPolicy};
+use super::screen::ScreenSnapshot;
+use super::scrollback::ScrollbackWriter;
+use super::startup::{StartupCommand, StartupMode};
+
+type SessionMap = Arc<RwLock<HashMap<Uuid, Arc<Mutex<ActiveSession>>>>>;
+type GroupMap = Arc<RwLock<HashMap<Uuid, SessionGroup>>>;
+
+/// How long to wait for a multiplexer command run next to a session's shell
+const MULTIPLEXER_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long reattaching one session at startup may take, so an unreachable host cannot hold
+/// up the server
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn ssh_config() -> Arc<Config> {
+    Arc::new(Config {
+        inactivity_timeout: Some(Duration::from_secs(3600)),
+        keepalive_interval: Some(Duration::from_secs(30)),
+        keepalive_max: 3,
+        ..Default::default()
+    })
+}
+
+/// Exit status or signal reported by the remote shell before its channel closed
+#[derive(Debug, Default)]
+struct RemoteExit {
+    exit_code: Option<u32>,
+    exit_signal: Option<String>,
+}
+
+/// Terminal a client asks for when creating a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionOptions {
+    pub cols: u32,
+    pub rows: u32,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    /// Overrides the connection's TERM
+    pub term_type: Option<String>,
+    pub name: Option<String>,
+}
+
+impl SessionOptions {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        Self {
+            cols,
+            rows,
+            pixel_width: 0,
+            pixel_height: 0,
+            term_type: None,
+            name: None,
+        }
+    }
+
+    pub fn with_pixel_size(mut self, width: u32, height: u32) -> Self {
+        self.pixel_width = width;
+        self.pixel_height = height;
+        self
+    }
+
+    pub fn with_term_type(mut self, term_type: impl Into<String>) -> Self {
+        self.term_type = Some(term_type.into());
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+/// Environment and PTY settings a connection asks for
+#[derive(Clone)]
+struct TerminalOptions {
+    term: String,
+    modes: Vec<(Pty, u32)>,
+    env: Vec<(String, String)>,
+}
+
+impl TerminalOptions {
+    async fn for_connection(pool: &PgPool, connection: &DbConnection) -> Result<Self> {
+        let mut modes = Vec::new();
+        for mode in ConnectionPtyMode::list_for_connection(pool, connection.id).await? {
+            match pty_mode(&mode.mode) {
+                Some(opcode) => modes.push((opcode, mode.value as u32)),
+                None => warn!("Skipping unknown terminal mode {}", mode.mode),
+            }
+        }
+
+        let env = ConnectionEnv::list_for_connection(pool, connection.id)
+            .await?
+            .into_iter()
+            .map(|env| (env.name, env.value))
+            .collect();
+
+        Ok(Self {
+            term: connection.term_type.clone().unwrap_or_else(|| DEFAULT_TERM.to_string()),
+            modes,
+            env,
+        })
+    }
+
+    /// Servers only accept names allowed by their AcceptEnv, so refusals are not errors
+    async fn send_env(&self, channel: &Channel<Msg>) -> Result<()> {
+        for (name, value) in &self.env {
+            channel
+                .set_env(false, name.as_str(), value.as_str())
+                .await
+                .map_err(|e| HiveError::Ssh(format!("Failed to set {}: {}", name, e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Remote directory uploads are written to unless configured otherwise
+pub const DEFAULT_UPLOAD_DIR: &str = "/tmp/hive_uploads";
+
 struct SessionHandler {
-    host: String,
-    output_tx: broadcast::Sender<Vec<u8>>,
+    verifier: HostKeyVerifier,
+    /// The interactive shell's channel; other channels (SFTP) carry no terminal output
+    shell_channel: Arc<OnceLock<ChannelId>>,
+    output: Arc<OutputLog>,
+    activity: Arc<Activity>,
+    exit: RemoteExit,
+    /// Fired when the server closes the channel; dropped unfired if the connection dies
+    exit_tx: Option<oneshot::Sender<RemoteExit>>,
+}
+
+impl SessionHandler {
+    fn is_shell(&self, channel: ChannelId) -> bool {
+        self.shell_channel.get() == Some(&channel)
+    }
+}
+
+pub(super) fn signal_name(signal: &Sig) -> String {
+    match signal {
+        Sig::Custom(name) => name.clone(),
+        signal => format!("{:?}", signal),
+    }
 }
 
 #[async_trait::async_trait]
 impl client::Handler for SessionHandler {
-    type Error = russh::Error;
+    type Error = HiveError;
 
     async fn check_server_key(
         &mut self,
         server_public_key: &PublicKey,
     ) -> std::result::Result<bool, Self::Error> {
-        info!(
-            "Accepting server key for {}: {:?}",
-            self.host,
-            server_public_key.fingerprint()
-        );
+        self.verifier.verify(server_public_key).await?;
         Ok(true)
     }
 
     async fn data(
         &mut self,
-        _channel: russh::ChannelId,
+        channel: russh::ChannelId,
         data: &[u8],
         _session: &mut client::Session,
     ) -> std::result::Result<(), Self::Error> {
+        if !self.is_shell(channel) {
+            return Ok(());
+        }
         debug!("Received {} bytes from SSH", data.len());
-        let _ = self.output_tx.send(data.to_vec());
+        self.output.append(data);
+        self.activity.touch();
         Ok(())
     }
 
     async fn extended_data(
         &mut self,
-        _channel: russh::ChannelId,
+        channel: russh::ChannelId,
         _ext: u32,
         data: &[u8],
         _session: &mut client::Session,
     ) -> std::result::Result<(), Self::Error> {
+        if !self.is_shell(channel) {
+            return Ok(());
+        }
         debug!("Received {} bytes of stderr from SSH", data.len());
-        let _ = self.output_tx.send(data.to_vec());
+        self.output.append(data);
+        self.activity.touch();
+        Ok(())
+    }
+
+    async fn exit_status(
+        &mut self,
+        channel: russh::ChannelId,
+        exit_status: u32,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        if !self.is_shell(channel) {
+            return Ok(());
+        }
+        debug!("Remote shell exited with status {}", exit_status);
+        self.exit.exit_code = Some(exit_status);
+        Ok(())
+    }
+
+    async fn exit_signal(
+        &mut self,
+        channel: russh::ChannelId,
+        signal: Sig,
+        core_dumped: bool,
+        error_message: &str,
+        _lang_tag: &str,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        if !self.is_shell(channel) {
+            return Ok(());
+        }
+        let signal = signal_name(&signal);
+        debug!(
+            "Remote shell killed by signal {} (core dumped: {}): {}",
+            signal, core_dumped, error_message
+        );
+        self.exit.exit_signal = Some(signal);
         Ok(())
     }
+
+    async fn channel_eof(
+        &mut self,
+        channel: russh::ChannelId,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        if self.is_shell(channel) {
+            debug!("Remote shell sent EOF");
+        }
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: russh::ChannelId,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        if !self.is_shell(channel) {
+            return Ok(());
+        }
+        if let Some(exit_tx) = self.exit_tx.take() {
+            let _ = exit_tx.send(std::mem::take(&mut self.exit));
+        }
+        Ok(())
+    }
+}
+
+/// Why a live session ended, delivered to every attached client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionEnd {
+    pub reason: String,
+    /// Set when the remote shell exited on its own
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<String>,
+}
+
+impl SessionEnd {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            exit_code: None,
+            exit_signal: None,
+        }
+    }
+
+    fn from_remote_exit(exit: RemoteExit) -> Self {
+        let reason = match (&exit.exit_signal, exit.exit_code) {
+            (Some(signal), _) => format!("Shell killed by signal {}", signal),
+            (None, Some(code)) => format!("Shell exited with status {}", code),
+            (None, None) => "Shell exited".to_string(),
+        };
+
+        Self {
+            reason,
+            exit_code: exit.exit_code.map(|code| code as i32),
+            exit_signal: exit.exit_signal,
+        }
+    }
+
+    fn is_remote_exit(&self) -> bool {
+        self.exit_code.is_some() || self.exit_signal.is_some()
+    }
 }
 
 pub struct ActiveSession {
     pub session_id: Uuid,
     pub connection_id: Uuid,
     pub user_id: Uuid,
+    handle: client::Handle<SessionHandler>,
     channel: Channel<Msg>,
-    output_tx: broadcast::Sender<Vec<u8>>,
+    output: Arc<OutputLog>,
+    scrollback: ScrollbackWriter,
+    ended_tx: watch::Sender<Option<SessionEnd>>,
+    link_tx: watch::Sender<LinkState>,
+    activity: Arc<Activity>,
+    /// SFTP subsystem on this connection, opened on first use
+    sftp: Mutex<Option<Arc<Sftp>>>,
+    /// How to open the shell again if the connection drops
+    spec: ShellSpec,
+    /// Latest size from `resize`, requested again on reconnect
+    window: std::sync::Mutex<(u32, u32)>,
+    /// Suspended with its connection kept; input is refused until it resumes
+    suspended: bool,
 }
 
 impl ActiveSession {
     pub async fn send(&self, data: &[u8]) -> Result<()> {
+        if self.suspended {
+            return Err(HiveError::Session("Session is suspended".into()));
+        }
+        if self.is_reconnecting() {
+            return Err(HiveError::Session("Session is reconnecting".into()));
+        }
+        self.activity.touch();
         self.channel
             .data(data)
             .await
@@ -75,6 +348,11 @@ impl ActiveSession {
     }
 
     pub async fn resize(&self, cols: u32, rows: u32) -> Result<()> {
+        *self.window.lock().unwrap() = (cols, rows);
+        self.output.resize_screen(cols, rows);
+        if self.is_reconnecting() {
+            return Ok(());
+        }
         self.channel
             .window_change(cols, rows, 0, 0)
             .await
@@ -82,32 +360,659 @@ impl ActiveSession {
         Ok(())
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
-        self.output_tx.subscribe()
+    pub(super) fn is_reconnecting(&self) -> bool {
+        *self.link_tx.borrow() != LinkState::Connected
+    }
+
+    fn is_ended(&self) -> bool {
+        self.ended_tx.borrow().is_some()
+    }
+
+    /// Suspended sessions do not count as idle or unattended
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Time since the last input or output
+    pub fn idle_for(&self) -> Duration {
+        self.activity.idle_for()
+    }
+
+    /// Time since a client was last attached, or None while one is
+    pub fn unattended_for(&self) -> Option<Duration> {
+        self.activity.unattended_for()
+    }
+
+    /// Changes whenever the SSH connection drops or is re-established
+    pub fn subscribe_link(&self) -> watch::Receiver<LinkState> {
+        self.link_tx.subscribe()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OutputChunk> {
+        self.output.subscribe()
+    }
+
+    /// Subscribe to live output along with whatever is still buffered from `offset` on
+    pub fn subscribe_from(&self, offset: u64) -> OutputSubscription {
+        self.output.subscribe_from(offset)
+    }
+
+    /// Offset the next byte of output will have
+    pub fn output_offset(&self) -> u64 {
+        self.output.end_offset()
+    }
+
+    /// Resolves to `Some` once the session has ended
+    pub fn subscribe_end(&self) -> watch::Receiver<Option<SessionEnd>> {
+        self.ended_tx.subscribe()
+    }
+
+    /// SFTP session on this session's SSH connection, opening it on first use
+    pub async fn sftp(&self) -> Result<Arc<Sftp>> {
+        let mut sftp = self.sftp.lock().await;
+        if let Some(sftp) = sftp.as_ref() {
+            return Ok(sftp.clone());
+        }
+
+        let session = Arc::new(Sftp::open(&self.handle).await?);
+        *sftp = Some(session.clone());
+        Ok(session)
+    }
+
+    /// Write all output produced so far to the database
+    pub async fn flush_scrollback(&self) {
+        self.scrollback.flush().await;
+    }
+
+    /// Notify attached clients, close the channel and SSH connection, then persist the last output.
+    ///
+    /// With `end_shell`, a shell kept in a multiplexer is ended too instead of left to reattach to.
+    async fn shutdown(&self, end: SessionEnd, end_shell: bool) {
+        self.ended_tx.send_replace(Some(end.clone()));
+
+        if let Some(persistence) = self.spec.persistence.filter(|_| end_shell) {
+            let command = persistence.kill_command(self.session_id);
+            if let Err(e) = command_succeeds(&self.handle, &command).await {
+                warn!("Failed to end {} session {}: {}", persistence.as_str(), self.session_id, e);
+            }
+        }
+
+        self.channel.eof().await.ok();
+        self.channel.close().await.ok();
+        self.handle
+            .disconnect(Disconnect::ByApplication, &end.reason, "en")
+            .await
+            .ok();
+
+        if let Some(sftp) = self.sftp.lock().await.take() {
+            sftp.close().await.ok();
+        }
+        self.scrollback.close().await;
+    }
+}
+
+/// Remove a session that has ended from the map and from every broadcast group, shutting it
+/// down if it was live. Returns whether it was.
+async fn remove_live_session(
+    sessions: &SessionMap,
+    groups: &GroupMap,
+    session_id: Uuid,
+    end: &SessionEnd,
+    end_shell: bool,
+) -> bool {
+    leave_groups(groups, session_id).await;
+    take_live_session(sessions, session_id, end, end_shell).await
+}
+
+/// Drop a session that ended from every group it belonged to
+async fn leave_groups(groups: &GroupMap, session_id: Uuid) {
+    for group in groups.write().await.values_mut() {
+        group.remove(session_id);
+    }
+}
+
+/// Remove a live session from the map and shut it down, returning whether it was live.
+///
+/// Unlike `remove_live_session` it stays in its groups, for sessions that are only detached.
+async fn take_live_session(
+    sessions: &SessionMap,
+    session_id: Uuid,
+    end: &SessionEnd,
+    end_shell: bool,
+) -> bool {
+    let session = sessions.write().await.remove(&session_id);
+
+    match session {
+        Some(session) => {
+            session.lock().await.shutdown(end.clone(), end_shell).await;
+            info!("Session {} closed: {}", session_id, end.reason);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Run `command` on a channel of its own, returning whether it exited with status 0
+async fn command_succeeds<H: client::Handler>(
+    handle: &client::Handle<H>,
+    command: &str,
+) -> Result<bool> {
+    let mut channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| HiveError::Ssh(format!("Failed to open channel: {}", e)))?;
+    channel
+        .exec(true, command)
+        .await
+        .map_err(|e| HiveError::Ssh(format!("Failed to run command: {}", e)))?;
+
+    let status = tokio::time::timeout(MULTIPLEXER_TIMEOUT, async {
+        while let Some(msg) = channel.wait().await {
+            if let ChannelMsg::ExitStatus { exit_status } = msg {
+                return Some(exit_status);
+            }
+        }
+        None
+    })
+    .await
+    .map_err(|_| HiveError::Ssh(format!("Timed out running {}", command)))?;
+
+    Ok(status == Some(0))
+}
+
+/// Follow a live session until it ends, re-establishing its connection when the transport drops
+async fn monitor_session(
+    connector: Connector,
+    sessions: SessionMap,
+    groups: GroupMap,
+    policy: ReconnectPolicy,
+    session: Weak<Mutex<ActiveSession>>,
+    session_id: Uuid,
+    mut exit_rx: oneshot::Receiver<RemoteExit>,
+) {
+    let end = loop {
+        match (&mut exit_rx).await {
+            Ok(exit) => break SessionEnd::from_remote_exit(exit),
+            Err(_) => match reconnect(&connector, policy, &session, session_id).await {
+                Some(next_exit_rx) => exit_rx = next_exit_rx,
+                None => break SessionEnd::new("SSH connection lost"),
+            },
+        }
+    };
+
+    // Sessions closed or suspended through the manager are already gone from the map, and a
+    // resumed one is live again under a new entry; only this task's own session ended here
+    let ended = {
+        let mut sessions = sessions.write().await;
+        let owned = sessions
+            .get(&session_id)
+            .is_some_and(|live| std::ptr::eq(Arc::as_ptr(live), session.as_ptr()));
+        if owned {
+            sessions.remove(&session_id)
+        } else {
+            None
+        }
+    };
+    if let Some(ended) = ended {
+        ended.lock().await.shutdown(end.clone(), false).await;
+        info!("Session {} closed: {}", session_id, end.reason);
+        leave_groups(&groups, session_id).await;
+        if let Err(e) = record_session_end(&connector.pool, session_id, &end).await {
+            warn!("Failed to record end of session {}: {}", session_id, e);
+        }
+    }
+}
+
+/// Open the session's shell again over a new connection, backing off between attempts.
+///
+/// Returns the new connection's exit receiver, or None once the attempts run out or the session
+/// has ended in the meantime.
+async fn reconnect(
+    connector: &Connector,
+    policy: ReconnectPolicy,
+    session: &Weak<Mutex<ActiveSession>>,
+    session_id: Uuid,
+) -> Option<oneshot::Receiver<RemoteExit>> {
+    let (spec, output, activity) = {
+        let session = session.upgrade()?;
+        let session = session.lock().await;
+        if session.is_ended() {
+            return None;
+        }
+        let (cols, rows) = *session.window.lock().unwrap();
+        let mut spec = session.spec.clone();
+        spec.options.cols = cols;
+        spec.options.rows = rows;
+        (spec, session.output.clone(), session.activity.clone())
+    };
+
+    let mut reason = "SSH connection lost".to_string();
+    for attempt in 1..=policy.max_attempts {
+        let delay = policy.delay(attempt);
+        {
+            let session = session.upgrade()?;
+            let session = session.lock().await;
+            if session.is_ended() {
+                return None;
+            }
+            session.link_tx.send_replace(LinkState::Reconnecting {
+                attempt,
+                delay,
+                reason: reason.clone(),
+            });
+        }
+        warn!(
+            "Session {} reconnecting in {:?} (attempt {}): {}",
+            session_id, delay, attempt, reason
+        );
+        tokio::time::sleep(delay).await;
+
+        let (connection, password) = (&spec.connection, &spec.password);
+        let shell = connector.connect_shell(connection, password, output.clone(), activity.clone());
+        let shell = match shell.await {
+            Ok(shell) => shell,
+            Err(e) => {
+                reason = e.to_string();
+                continue;
+            }
+        };
+        // Reattaching to the multiplexer brings back the old shell, which already ran the
+        // startup command; any other shell is fresh and runs it again
+        let fresh = match spec.persistence {
+            Some(persistence) => {
+                let check = persistence.has_session_command(session_id);
+                match command_succeeds(&shell.handle, &check).await {
+                    Ok(running) => !running,
+                    Err(e) => {
+                        reason = e.to_string();
+                        continue;
+                    }
+                }
+            }
+            None => true,
+        };
+        let prompt_rx = output.subscribe();
+        let channel = match shell.open_shell(&spec).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                reason = e.to_string();
+                continue;
+            }
+        };
+
+        let active_session = session.upgrade()?;
+        let mut session = active_session.lock().await;
+        if session.is_ended() {
+            // Closed while connecting; the new connection is not needed
+            shell.handle.disconnect(Disconnect::ByApplication, "", "en").await.ok();
+            return None;
+        }
+        // Sizes changed while reconnecting apply to the new PTY
+        let (cols, rows) = *session.window.lock().unwrap();
+        if (cols, rows) != (spec.options.cols, spec.options.rows) {
+            channel.window_change(cols, rows, 0, 0).await.ok();
+        }
+        session.handle = shell.handle;
+        session.channel = channel;
+        *session.sftp.get_mut() = None;
+        session.link_tx.send_replace(LinkState::Connected);
+        drop(session);
+        info!("Session {} reconnected after {} attempts", session_id, attempt);
+
+        if let Some(startup) = spec.startup.as_ref().filter(|_| fresh) {
+            startup.type_into(session_id, &active_session, prompt_rx).await;
+        }
+        return Some(shell.exit_rx);
+    }
+
+    warn!("Giving up reconnecting session {}: {}", session_id, reason);
+    None
+}
+
+async fn record_session_end(pool: &PgPool, session_id: Uuid, end: &SessionEnd) -> Result<()> {
+    if end.is_remote_exit() {
+        DbSession::record_exit(pool, session_id, end.exit_code, end.exit_signal.as_deref()).await?;
+    } else {
+        DbSession::close(pool, session_id).await?;
+    }
+    Ok(())
+}
+
+/// Output missed while detached, followed by the live stream
+pub struct ResumedAttach {
+    /// Offset of the first byte of `scrollback`
+    pub scrollback_offset: u64,
+    pub scrollback: Vec<u8>,
+    /// Requested offset, when output between it and `scrollback_offset` is no longer stored
+    pub truncated_from: Option<u64>,
+    /// Live output, starting right after `scrollback`
+    pub receiver: broadcast::Receiver<OutputChunk>,
+    pub ended: watch::Receiver<Option<SessionEnd>>,
+    /// Connection state, for telling clients about reconnects
+    pub link: watch::Receiver<LinkState>,
+    /// Buffered output, for recovering after `receiver` lags
+    pub output: Weak<OutputLog>,
+    /// Keep for as long as the client is attached
+    pub client: AttachedClient,
+    /// Current screen, for clients that draw it instead of replaying scrollback
+    pub snapshot: Option<ScreenSnapshot>,
+}
+
+/// Sessions left active by a previous server process, reconciled at startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreSummary {
+    /// Reattached to the shell kept running in tmux or screen
+    pub restored: usize,
+    /// Marked `lost`, since their shell ended with the previous process
+    pub lost: usize,
+}
+
+/// Authenticated SSH connection for a session, before its shell channel is opened
+struct ShellConnection {
+    handle: client::Handle<SessionHandler>,
+    shell_channel: Arc<OnceLock<ChannelId>>,
+    exit_rx: oneshot::Receiver<RemoteExit>,
+}
+
+impl ShellConnection {
+    /// Open the channel the session's output comes from and start the shell on it
+    async fn open_shell(&self, spec: &ShellSpec) -> Result<Channel<Msg>> {
+        let channel = self
+            .handle
+            .channel_open_session()
+            .await
+            .map_err(|e| HiveError::Ssh(format!("Failed to open channel: {}", e)))?;
+        let _ = self.shell_channel.set(channel.id());
+
+        let (terminal, options) = (&spec.terminal, &spec.options);
+        terminal.send_env(&channel).await?;
+        channel
+            .request_pty(
+                false,
+                &terminal.term,
+                options.cols,
+                options.rows,
+                options.pixel_width,
+                options.pixel_height,
+                &terminal.modes,
+            )
+            .await
+            .map_err(|e| HiveError::Ssh(format!("Failed to request PTY: {}", e)))?;
+
+        match &spec.command {
+            Some(command) => {
+                channel
+                    .exec(false, command.as_str())
+                    .await
+                    .map_err(|e| HiveError::Ssh(format!("Failed to run startup command: {}", e)))?;
+            }
+            None => {
+                channel
+                    .request_shell(false)
+                    .await
+                    .map_err(|e| HiveError::Ssh(format!("Failed to request shell: {}", e)))?;
+            }
+        }
+        Ok(channel)
+    }
+}
+
+/// Everything needed to open a session's shell, kept to open it again after a reconnect
+#[derive(Clone)]
+struct ShellSpec {
+    connection: DbConnection,
+    /// Held in memory only, for reconnecting password-authenticated sessions
+    password: String,
+    terminal: TerminalOptions,
+    options: SessionOptions,
+    /// Runs in place of the login shell, e.g. an exec-mode startup command or the
+    /// multiplexer holding the shell
+    command: Option<String>,
+    /// Multiplexer the shell runs in, so it outlives the SSH connection
+    persistence: Option<Persistence>,
+    /// Run again whenever a reconnect starts a fresh shell rather than reattaching
+    startup: Option<StartupCommand>,
+}
+
+/// What runs in place of the login shell: an exec-mode startup command, inside the
+/// multiplexer when there is one.
+///
+/// A multiplexer only runs it when it starts a new session, not when it reattaches.
+fn shell_command(
+    session_id: Uuid,
+    persistence: Option<Persistence>,
+    startup: Option<&StartupCommand>,
+) -> Option<String> {
+    let exec_script = startup
+        .filter(|startup| startup.mode == StartupMode::Exec)
+        .map(|startup| startup.script.as_str());
+    match persistence {
+        Some(persistence) => Some(persistence.attach_command(session_id, exec_script)),
+        None => exec_script.map(str::to_string),
+    }
+}
+
+/// Opens authenticated SSH connections for sessions, from the manager or a reconnect task
+#[derive(Clone)]
+struct Connector {
+    pool: PgPool,
+    host_key_policy: HostKeyPolicy,
+    master_key: Option<MasterKey>,
+}
+
+impl Connector {
+    /// Use the connection's SSH key when it has one, the password otherwise
+    async fn authenticate<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+        connection: &DbConnection,
+        password: &str,
+    ) -> Result<bool> {
+        let Some(ssh_key_id) = connection.ssh_key_id else {
+            return handle
+                .authenticate_password(&connection.username, password)
+                .await
+                .map_err(|e| HiveError::Ssh(format!("Authentication failed: {}", e)));
+        };
+
+        let ssh_key = SshKey::find_by_id(&self.pool, ssh_key_id)
+            .await?
+            .ok_or_else(|| HiveError::Session("SSH key not found".into()))?;
+
+        if ssh_key.user_id != connection.user_id {
+            return Err(HiveError::Auth("Not authorized to use this SSH key".into()));
+        }
+
+        let master_key = self
+            .master_key
+            .as_ref()
+            .ok_or_else(|| HiveError::Config("Server master key is not configured".into()))?;
+
+        let private_key = master_key.open(&ssh_key.private_key_encrypted)?;
+        let private_key = String::from_utf8(private_key)
+            .map_err(|_| HiveError::Ssh("Stored private key is not valid UTF-8".into()))?;
+        let key_pair = parse_private_key(&private_key)?;
+
+        handle
+            .authenticate_publickey(&connection.username, Arc::new(key_pair))
+            .await
+            .map_err(|e| HiveError::Ssh(format!("Authentication failed: {}", e)))
+    }
+
+    /// Open an SSH connection to `connection` and log in
+    async fn connect<H>(
+        &self,
+        connection: &DbConnection,
+        password: &str,
+        handler: H,
+    ) -> Result<client::Handle<H>>
+    where
+        H: client::Handler<Error = HiveError> + Send + 'static,
+    {
+        let addr = format!("{}:{}", connection.host, connection.port);
+        let mut handle = client::connect(ssh_config(), &addr, handler)
+            .await
+            .map_err(|e| match e {
+                HiveError::Ssh(msg) => HiveError::Ssh(format!("Failed to connect: {}", msg)),
+                e => e,
+            })?;
+
+        if !self.authenticate(&mut handle, connection, password).await? {
+            return Err(HiveError::Auth("SSH authentication failed".into()));
+        }
+        Ok(handle)
+    }
+
+    /// Connect for a session whose shell output goes to `output`
+    async fn connect_shell(
+        &self,
+        connection: &DbConnection,
+        password: &str,
+        output: Arc<OutputLog>,
+        activity: Arc<Activity>,
+    ) -> Result<ShellConnection> {
+        let (exit_tx, exit_rx) = oneshot::channel();
+        let shell_channel = Arc::new(OnceLock::new());
+        let handler = SessionHandler {
+            shell_channel: shell_channel.clone(),
+            verifier: HostKeyVerifier::for_connection(
+                self.pool.clone(),
+                connection,
+                self.host_key_policy,
+            ),
+            output,
+            activity,
+            exit: RemoteExit::default(),
+            exit_tx: Some(exit_tx),
+        };
+
+        let handle = self.connect(connection, password, handler).await?;
+        Ok(ShellConnection {
+            handle,
+            shell_channel,
+            exit_rx,
+        })
+    }
+}
+
+impl ResumedAttach {
+    /// Offset of the first byte `receiver` will deliver
+    pub fn live_offset(&self) -> u64 {
+        self.scrollback_offset + self.scrollback.len() as u64
     }
 }
 
 pub struct SessionManager {
     pool: PgPool,
-    sessions: RwLock<HashMap<Uuid, Arc<Mutex<ActiveSession>>>>,
+    sessions: SessionMap,
+    connector: Connector,
+    reconnect_policy: ReconnectPolicy,
+    upload_dir: String,
+    /// Broadcast groups; kept in memory like the sessions they contain
+    groups: GroupMap,
 }
 
 impl SessionManager {
     pub fn new(pool: PgPool) -> Self {
         Self {
-            pool,
-            sessions: RwLock::new(HashMap::new()),
+            pool: pool.clone(),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            connector: Connector {
+                pool,
+                host_key_policy: HostKeyPolicy::default(),
+                master_key: None,
+            },
+            reconnect_policy: ReconnectPolicy::default(),
+            upload_dir: DEFAULT_UPLOAD_DIR.to_string(),
+            groups: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    pub fn with_host_key_policy(mut self, policy: HostKeyPolicy) -> Self {
+        self.connector.host_key_policy = policy;
+        self
+    }
+
+    /// Key used to decrypt stored SSH private keys
+    pub fn with_master_key(mut self, master_key: MasterKey) -> Self {
+        self.connector.master_key = Some(master_key);
+        self
+    }
+
+    /// Backoff for re-establishing sessions whose SSH connection drops
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// Remote directory uploaded files are written to
+    pub fn with_upload_dir(mut self, upload_dir: impl Into<String>) -> Self {
+        self.upload_dir = upload_dir.into();
+        self
+    }
+
+    /// Start the session's shell and make it live
+    async fn start_shell(
+        &self,
+        db_session: &DbSession,
+        shell: ShellConnection,
+        output: Arc<OutputLog>,
+        activity: Arc<Activity>,
+        spec: ShellSpec,
+    ) -> Result<Arc<Mutex<ActiveSession>>> {
+        let channel = shell.open_shell(&spec).await?;
+        output.resize_screen(spec.options.cols, spec.options.rows);
+
+        info!("SSH session {} established", db_session.id);
+
+        // Persist output in batches for as long as the session lives
+        let scrollback = ScrollbackWriter::spawn(self.pool.clone(), db_session.id, output.clone());
+
+        // Store active session
+        let window = std::sync::Mutex::new((spec.options.cols, spec.options.rows));
+        let active_session = ActiveSession {
+            session_id: db_session.id,
+            connection_id: db_session.connection_id,
+            user_id: db_session.user_id,
+            handle: shell.handle,
+            channel,
+            output,
+            scrollback,
+            ended_tx: watch::channel(None).0,
+            link_tx: watch::channel(LinkState::Connected).0,
+            activity,
+            sftp: Mutex::new(None),
+            spec,
+            window,
+            suspended: false,
+        };
+
+        let active_session = Arc::new(Mutex::new(active_session));
+        self.sessions
+            .write()
+            .await
+            .insert(db_session.id, active_session.clone());
+
+        tokio::spawn(monitor_session(
+            self.connector.clone(),
+            self.sessions.clone(),
+            self.groups.clone(),
+            self.reconnect_policy,
+            Arc::downgrade(&active_session),
+            db_session.id,
+            shell.exit_rx,
+        ));
+
+        Ok(active_session)
+    }
+
     pub async fn create_session(
         &self,
         user_id: Uuid,
         connection_id: Uuid,
-        cols: u32,
-        rows: u32,
+        options: SessionOptions,
         password: &str,
-    ) -> Result<(Uuid, broadcast::Receiver<Vec<u8>>)> {
+    ) -> Result<(Uuid, broadcast::Receiver<OutputChunk>)> {
         // Get connection details
         let connection = DbConnection::find_by_id(&self.pool, connection_id)
             .await?
@@ -117,101 +1022,281 @@ impl SessionManager {
             return Err(HiveError::Auth("Not authorized to use this connection".into()));
         }
 
+        // Resolve the startup command up front so a bad one fails before connecting
+        let startup = StartupCommand::for_connection(&self.pool, &connection).await?;
+        let persistence = Persistence::parse(&connection.persistence)?;
+        let mut terminal = TerminalOptions::for_connection(&self.pool, &connection).await?;
+        if let Some(term_type) = options.term_type.clone() {
+            terminal.term = term_type;
+        }
+
         // Create database session record
         let db_session = DbSession::create(&self.pool, user_id, connection_id).await?;
+        DbSession::set_terminal(
+            &self.pool,
+            db_session.id,
+            options.name.as_deref(),
+            options.cols,
+            options.rows,
+            options.pixel_width,
+            options.pixel_height,
+            &terminal.term,
+        )
+        .await?;
 
         info!(
             "Creating SSH session {} for connection {} ({}:{})",
             db_session.id, connection.name, connection.host, connection.port
         );
 
-        // Create broadcast channel for output
-        let (output_tx, output_rx) = broadcast::channel(1024);
+        // Output log numbers every byte and fans it out to subscribers
+        let output = Arc::new(OutputLog::new(1024));
+        let output_rx = output.subscribe();
+        // Subscribed before the shell starts so the first prompt is not missed
+        let prompt_rx = output.subscribe();
 
-        let config = Config {
-            inactivity_timeout: Some(std::time::Duration::from_secs(3600)),
-            keepalive_interval: Some(std::time::Duration::from_secs(30)),
-            keepalive_max: 3,
-            ..Default::default()
-        };
-        let config = Arc::new(config);
+        let command = shell_command(db_session.id, persistence, startup.as_ref());
+        if startup.as_ref().is_some_and(|startup| startup.mode == StartupMode::Exec) {
+            info!("Running startup command instead of a shell in session {}", db_session.id);
+        }
 
-        let handler = SessionHandler {
-            host: connection.host.clone(),
-            output_tx: output_tx.clone(),
+        let spec = ShellSpec {
+            connection,
+            password: password.to_string(),
+            terminal,
+            options,
+            command,
+            persistence,
+            startup: startup.clone(),
+        };
+        let activity = Arc::new(Activity::new(self.pool.clone(), db_session.id));
+        let shell = async {
+            let shell = self
+                .connector
+                .connect_shell(&spec.connection, password, output.clone(), activity.clone())
+                .await?;
+            self.start_shell(&db_session, shell, output, activity, spec).await
+        };
+        let active_session = match shell.await {
+            Ok(active_session) => active_session,
+            Err(e) => {
+                // Never started, so there is nothing to reattach to after a restart
+                if let Err(e) = DbSession::close(&self.pool, db_session.id).await {
+                    warn!("Failed to close session {}: {}", db_session.id, e);
+                }
+                return Err(e);
+            }
         };
 
-        let addr = format!("{}:{}", connection.host, connection.port);
+        if let Some(startup) = &startup {
+            startup.type_into(db_session.id, &active_session, prompt_rx).await;
+        }
 
-        let mut handle = client::connect(config, &addr, handler)
-            .await
-            .map_err(|e| HiveError::Ssh(format!("Failed to connect: {}", e)))?;
+        Ok((db_session.id, output_rx))
+    }
 
-        let authenticated = handle
-            .authenticate_password(&connection.username, password)
-            .await
-            .map_err(|e| HiveError::Ssh(format!("Authentication failed: {}", e)))?;
+    /// Reconcile sessions the database lists as active or suspended with the ones live in memory.
+    ///
+    /// Meant for startup: sessions whose shell was kept in tmux or screen are reattached, and
+    /// the rest are marked `lost` because their shell ended with the previous server process.
+    /// Suspended sessions with a multiplexer stay suspended until resumed.
+    pub async fn restore_sessions(&self) -> Result<RestoreSummary> {
+        let mut orphaned = Vec::new();
+        for db_session in DbSession::list_with_status(&self.pool, "active").await? {
+            if self.get_session(db_session.id).await.is_none() {
+                orphaned.push(db_session);
+            }
+        }
 
-        if !authenticated {
-            return Err(HiveError::Auth("SSH authentication failed".into()));
+        let restores = orphaned.iter().map(|db_session| async move {
+            let restore = self.restore_session(db_session, "");
+            let result = tokio::time::timeout(RESTORE_TIMEOUT, restore).await.unwrap_or_else(|_| {
+                Err(HiveError::Ssh("Timed out reconnecting to the host".into()))
+            });
+            (db_session.id, result)
+        });
+        let mut results = futures::future::join_all(restores).await;
+
+        for db_session in DbSession::list_with_status(&self.pool, "suspended").await? {
+            if self.get_session(db_session.id).await.is_some() {
+                continue;
+            }
+            let connection = DbConnection::find_by_id(&self.pool, db_session.connection_id).await?;
+            if connection.is_none_or(|connection| connection.persistence == "none") {
+                let reason = "Suspended shell did not outlive the server";
+                results.push((db_session.id, Err(HiveError::Session(reason.into()))));
+            }
+        }
+
+        let mut summary = RestoreSummary::default();
+        for (session_id, result) in results {
+            match result {
+                Ok(()) => {
+                    info!("Restored session {}", session_id);
+                    summary.restored += 1;
+                }
+                Err(e) => {
+                    info!("Session {} lost: {}", session_id, e);
+                    DbSession::transition(&self.pool, session_id, "lost").await?;
+                    summary.lost += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Reattach a session without a connection to the multiplexer still holding its shell.
+    ///
+    /// `password` may be empty for connections that log in with an SSH key.
+    async fn restore_session(&self, db_session: &DbSession, password: &str) -> Result<()> {
+        let connection = DbConnection::find_by_id(&self.pool, db_session.connection_id)
+            .await?
+            .ok_or_else(|| HiveError::Session("Connection not found".into()))?;
+
+        let persistence = Persistence::parse(&connection.persistence)?
+            .ok_or_else(|| HiveError::Session("Shell did not outlive the server".into()))?;
+        // Passwords are never stored, so only key-based connections can log in on their own
+        if connection.ssh_key_id.is_none() && password.is_empty() {
+            return Err(HiveError::Session("No SSH key to reconnect with".into()));
+        }
+
+        let mut terminal = TerminalOptions::for_connection(&self.pool, &connection).await?;
+        if let Some(term_type) = db_session.term_type.clone() {
+            terminal.term = term_type;
         }
+        let options = SessionOptions::new(db_session.cols as u32, db_session.rows as u32)
+            .with_pixel_size(db_session.pixel_width as u32, db_session.pixel_height as u32);
+
+        // Offsets carry on from the output persisted before the restart
+        let offset = ScrollbackChunk::end_offset(&self.pool, db_session.id).await?;
+        let output = Arc::new(OutputLog::starting_at(1024, offset));
+
+        let activity = Arc::new(Activity::new(self.pool.clone(), db_session.id));
+        let shell = self
+            .connector
+            .connect_shell(&connection, password, output.clone(), activity.clone())
+            .await?;
+        let check = persistence.has_session_command(db_session.id);
+        if !command_succeeds(&shell.handle, &check).await? {
+            return Err(HiveError::Session(format!(
+                "{} session no longer running",
+                persistence.as_str()
+            )));
+        }
+
+        // The startup command already ran when the session was created; it is kept for a
+        // reconnect that finds the multiplexer session gone and starts a fresh shell
+        let startup = match StartupCommand::for_connection(&self.pool, &connection).await {
+            Ok(startup) => startup,
+            Err(e) => {
+                warn!("Ignoring startup command of session {}: {}", db_session.id, e);
+                None
+            }
+        };
+        let spec = ShellSpec {
+            command: shell_command(db_session.id, Some(persistence), startup.as_ref()),
+            connection,
+            password: password.to_string(),
+            terminal,
+            options,
+            persistence: Some(persistence),
+            startup,
+        };
+        self.start_shell(db_session, shell, output, activity, spec).await?;
+        Ok(())
+    }
+
+    /// Run one command on a saved connection over a fresh SSH connection, without a PTY
+    pub async fn exec(
+        &self,
+        user_id: Uuid,
+        connection_id: Uuid,
+        password: &str,
+        command: &str,
+    ) -> Result<ExecProcess> {
+        let connection = DbConnection::find_by_id(&self.pool, connection_id)
+            .await?
+            .ok_or_else(|| HiveError::Session("Connection not found".into()))?;
+
+        if connection.user_id != user_id {
+            return Err(HiveError::Auth("Not authorized to use this connection".into()));
+        }
+
+        info!(
+            "Running command on connection {} ({}:{})",
+            connection.name, connection.host, connection.port
+        );
+
+        // Commands get the connection's environment but no PTY
+        let terminal = TerminalOptions::for_connection(&self.pool, &connection).await?;
+
+        let handler = ExecHandler {
+            verifier: HostKeyVerifier::for_connection(
+                self.pool.clone(),
+                &connection,
+                self.connector.host_key_policy,
+            ),
+        };
+        let handle = self.connector.connect(&connection, password, handler).await?;
 
         let channel = handle
             .channel_open_session()
             .await
             .map_err(|e| HiveError::Ssh(format!("Failed to open channel: {}", e)))?;
-
+        terminal.send_env(&channel).await?;
         channel
-            .request_pty(false, "xterm-256color", cols, rows, 0, 0, &[])
+            .exec(true, command)
             .await
-            .map_err(|e| HiveError::Ssh(format!("Failed to request PTY: {}", e)))?;
+            .map_err(|e| HiveError::Ssh(format!("Failed to run command: {}", e)))?;
 
-        channel
-            .request_shell(false)
-            .await
-            .map_err(|e| HiveError::Ssh(format!("Failed to request shell: {}", e)))?;
+        Ok(ExecProcess {
+            handle,
+            channel,
+            exit: Some(ExecExit::default()),
+        })
+    }
 
-        info!("SSH session {} established", db_session.id);
+    /// Write buffered output of every live session, e.g. before the server exits
+    pub async fn flush_scrollback(&self) {
+        let sessions: Vec<_> = self.sessions.read().await.values().cloned().collect();
+        for session in sessions {
+            session.lock().await.flush_scrollback().await;
+        }
+    }
 
-        // Spawn background task to save scrollback
-        let scrollback_pool = self.pool.clone();
-        let scrollback_session_id = db_session.id;
-        let mut scrollback_rx = output_tx.subscribe();
-        tokio::spawn(async move {
-            loop {
-                match scrollback_rx.recv().await {
-                    Ok(data) => {
-                        if let Err(e) =
-                            ScrollbackChunk::append(&scrollback_pool, scrollback_session_id, &data)
-                                .await
-                        {
-                            error!("Failed to save scrollback: {}", e);
-                        }
-                    }
-                    Err(broadcast::error::RecvError::Closed) => {
-                        debug!("Scrollback channel closed for session {}", scrollback_session_id);
-                        break;
-                    }
-                    Err(broadcast::error::RecvError::Lagged(n)) => {
-                        debug!("Scrollback lagged {} messages", n);
-                    }
-                }
-            }
-        });
+    /// Shutdown future for `serve_with_shutdown`: once `signal` resolves, write the buffered
+    /// output of every live session and disconnect it, as `disconnect_all` does.
+    ///
+    /// The server then waits for connections to close, which attached terminals only do once
+    /// their session has ended, so this must not wait until serving returns.
+    pub async fn disconnect_on(&self, signal: impl Future<Output = ()>) {
+        signal.await;
+        info!("Shutting down, disconnecting sessions");
+        self.disconnect_all().await;
+    }
 
-        // Store active session
-        let active_session = ActiveSession {
-            session_id: db_session.id,
-            connection_id,
-            user_id,
-            channel,
-            output_tx,
-        };
+    /// Drop every live SSH connection, e.g. before the server exits.
+    ///
+    /// Sessions stay active in the database, so shells kept in tmux or screen are reattached
+    /// by `restore_sessions` on the next start.
+    pub async fn disconnect_all(&self) {
+        let session_ids: Vec<_> = self.sessions.read().await.keys().copied().collect();
+        let end = SessionEnd::new("Server shutting down");
+        for session_id in session_ids {
+            take_live_session(&self.sessions, session_id, &end, false).await;
+        }
+    }
 
-        let mut sessions = self.sessions.write().await;
-        sessions.insert(db_session.id, Arc::new(Mutex::new(active_session)));
+    /// Make the database copy of a live session's scrollback current before reading it
+    pub async fn flush_session_scrollback(&self, session_id: Uuid) {
+        if let Some(session) = self.get_session(session_id).await {
+            session.lock().await.flush_scrollback().await;
+        }
+    }
 
-        Ok((db_session.id, output_rx))
+    /// Every session live in memory
+    pub async fn live_sessions(&self) -> Vec<Arc<Mutex<ActiveSession>>> {
+        self.sessions.read().await.values().cloned().collect()
     }
 
     pub async fn get_session(&self, session_id: Uuid) -> Option<Arc<Mutex<ActiveSession>>> {
@@ -220,26 +1305,87 @@ impl SessionManager {
     }
 
     pub async fn close_session(&self, session_id: Uuid) -> Result<()> {
-        let mut sessions = self.sessions.write().await;
+        self.end_session(session_id, SessionEnd::new("Session closed")).await
+    }
 
-        if let Some(session) = sessions.remove(&session_id) {
-            let session = session.lock().await;
-            session.channel.close().await.ok();
+    /// Tear down a live session, ending its shell, and mark it closed in the database
+    pub async fn end_session(&self, session_id: Uuid, end: SessionEnd) -> Result<()> {
+        // Unfinished uploads cannot resume once the session is gone
+        match UploadTransfer::fail_for_session(&self.pool, session_id).await {
+            Ok(transfers) => self.remove_partial_uploads(&transfers).await,
+            Err(e) => warn!("Failed to end uploads of session {}: {}", session_id, e),
+        }
+        remove_live_session(&self.sessions, &self.groups, session_id, &end, true).await;
+
+        // Update database even if the session was no longer live (e.g. after a restart)
+        record_session_end(&self.pool, session_id, &end).await
+    }
 
-            // Update database
-            DbSession::close(&self.pool, session_id).await?;
+    /// Take a live session out of the active list and the idle limits until it is resumed.
+    ///
+    /// A shell kept in tmux or screen is detached and its SSH connection dropped. Otherwise the
+    /// connection and scrollback capture keep running, but input is refused.
+    pub async fn suspend_session(&self, session_id: Uuid) -> Result<DbSession> {
+        let session = self
+            .get_session(session_id)
+            .await
+            .ok_or_else(|| HiveError::Session("Session not active in memory".into()))?;
+        let db_session = DbSession::transition(&self.pool, session_id, "suspended").await?;
 
-            info!("Session {} closed", session_id);
+        let detach = {
+            let mut session = session.lock().await;
+            session.suspended = session.spec.persistence.is_none();
+            !session.suspended
+        };
+        if detach {
+            let end = SessionEnd::new("Session suspended");
+            take_live_session(&self.sessions, session_id, &end, false).await;
         }
 
-        Ok(())
+        info!("Suspended session {}", session_id);
+        Ok(db_session)
+    }
+
+    /// Make a suspended session active again, reattaching to its multiplexer if it was detached.
+    ///
+    /// `password` is only needed to reattach over a connection without an SSH key.
+    pub async fn resume_session(&self, session_id: Uuid, password: &str) -> Result<DbSession> {
+        let db_session = DbSession::find_by_id(&self.pool, session_id)
+            .await?
+            .ok_or_else(|| HiveError::Session("Session not found".into()))?;
+
+        if db_session.status != "suspended" {
+            return Err(HiveError::Session("Session is not suspended".into()));
+        }
+
+        match self.get_session(session_id).await {
+            Some(session) => {
+                let mut session = session.lock().await;
+                session.suspended = false;
+                session.activity.reset();
+            }
+            None => self.restore_session(&db_session, password).await?,
+        }
+
+        let db_session = match DbSession::transition(&self.pool, session_id, "active").await {
+            Ok(db_session) => db_session,
+            Err(e) => {
+                // Closed while reattaching, so the connection is not needed
+                let end = SessionEnd::new("Session closed");
+                remove_live_session(&self.sessions, &self.groups, session_id, &end, false).await;
+                return Err(e);
+            }
+        };
+
+        info!("Resumed session {}", session_id);
+        Ok(db_session)
     }
 
     pub async fn attach_to_session(
         &self,
         session_id: Uuid,
         user_id: Uuid,
-    ) -> Result<broadcast::Receiver<Vec<u8>>> {
+    ) -> Result<broadcast::Receiver<OutputChunk>> {
         // Verify ownership
         let db_session = DbSession::find_by_id(&self.pool, session_id)
             .await?
@@ -273,6 +1419,7 @@ impl SessionManager {
             return Err(HiveError::Auth("Not authorized to access this session".into()));
         }
 
+        self.flush_session_scrollback(session_id).await;
         let scrollback = ScrollbackChunk::get_all(&self.pool, session_id).await?;
         Ok(scrollback)
     }
@@ -293,6 +1440,7 @@ impl SessionManager {
             return Err(HiveError::Auth("Not authorized to access this session".into()));
         }
 
+        self.flush_session_scrollback(session_id).await;
         let scrollback = ScrollbackChunk::get_from_offset(&self.pool, session_id, offset).await?;
         Ok(scrollback)
     }
@@ -308,6 +1456,7 @@ impl SessionManager {
             return Err(HiveError::Auth("Not authorized to access this session".into()));
         }
 
+        self.flush_session_scrollback(session_id).await;
         let size = ScrollbackChunk::total_size(&self.pool, session_id).await?;
         Ok(size)
     }
@@ -318,7 +1467,101 @@ impl SessionManager {
         session_id: Uuid,
         user_id: Uuid,
         last_seen_offset: Option<usize>,
-    ) -> Result<(Vec<u8>, broadcast::Receiver<Vec<u8>>)> {
+    ) -> Result<(Vec<u8>, broadcast::Receiver<OutputChunk>)> {
+        let resumed = self
+            .attach_from(session_id, user_id, Some(last_seen_offset.unwrap_or(0) as u64))
+            .await?;
+        Ok((resumed.scrollback, resumed.receiver))
+    }
+
+    /// Attach to a live session, first replaying output after `last_seen_offset`.
+    ///
+    /// The scrollback and the live receiver join without gaps or overlap. Without an offset
+    /// only live output is delivered.
+    pub async fn attach_from(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+        last_seen_offset: Option<u64>,
+    ) -> Result<ResumedAttach> {
+        let session = self.attachable_session(session_id, user_id).await?;
+
+        let (subscription, ended, link, output, client) = {
+            let session = session.lock().await;
+            (
+                session.subscribe_from(last_seen_offset.unwrap_or(u64::MAX)),
+                session.subscribe_end(),
+                session.subscribe_link(),
+                Arc::downgrade(&session.output),
+                AttachedClient::new(session.activity.clone()),
+            )
+        };
+
+        // Output older than the in-memory buffer has already been persisted
+        let mut scrollback_offset = subscription.buffered_offset;
+        let mut scrollback = Vec::new();
+        if let Some(from) = last_seen_offset.filter(|&from| from < subscription.buffered_offset) {
+            let (persisted_offset, persisted) = ScrollbackChunk::read_range(
+                &self.pool,
+                session_id,
+                from,
+                subscription.buffered_offset,
+            )
+            .await?;
+            // Only if it joins the buffer; output lost in between is reported as truncated
+            if persisted_offset + persisted.len() as u64 == subscription.buffered_offset {
+                scrollback_offset = persisted_offset;
+                scrollback = persisted;
+            }
+        }
+        scrollback.extend_from_slice(&subscription.buffered);
+
+        // Retention or a full output buffer may have lost part of what the client missed
+        let truncated_from = last_seen_offset.filter(|&from| from < scrollback_offset);
+
+        Ok(ResumedAttach {
+            scrollback_offset,
+            scrollback,
+            truncated_from,
+            receiver: subscription.receiver,
+            ended,
+            link,
+            output,
+            client,
+            snapshot: None,
+        })
+    }
+
+    /// Attach to a live session, starting with a snapshot of its current screen instead of
+    /// replaying scrollback
+    pub async fn attach_with_snapshot(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<ResumedAttach> {
+        let session = self.attachable_session(session_id, user_id).await?;
+        let session = session.lock().await;
+        let (snapshot, receiver) = session.output.subscribe_with_snapshot();
+
+        Ok(ResumedAttach {
+            scrollback_offset: snapshot.offset,
+            scrollback: Vec::new(),
+            truncated_from: None,
+            receiver,
+            ended: session.subscribe_end(),
+            link: session.subscribe_link(),
+            output: Arc::downgrade(&session.output),
+            client: AttachedClient::new(session.activity.clone()),
+            snapshot: Some(snapshot),
+        })
+    }
+
+    /// An active, live session owned by `user_id`
+    async fn attachable_session(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Arc<Mutex<ActiveSession>>> {
         // Verify ownership
         let db_session = DbSession::find_by_id(&self.pool, session_id)
             .await?
@@ -332,21 +1575,218 @@ impl SessionManager {
             return Err(HiveError::Session("Session is not active".into()));
         }
 
-        // Get scrollback data
-        let scrollback = match last_seen_offset {
-            Some(offset) => ScrollbackChunk::get_from_offset(&self.pool, session_id, offset).await?,
-            None => ScrollbackChunk::get_all(&self.pool, session_id).await?,
-        };
+        self.get_session(session_id)
+            .await
+            .ok_or_else(|| HiveError::Session("Session not active in memory".into()))
+    }
 
-        // Subscribe to live stream
-        let sessions = self.sessions.read().await;
-        let session = sessions
-            .get(&session_id)
+    /// A live session owned by `user_id`
+    async fn owned_live_session(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Arc<Mutex<ActiveSession>>> {
+        let session = self
+            .get_session(session_id)
+            .await
             .ok_or_else(|| HiveError::Session("Session not active in memory".into()))?;
 
+        if session.lock().await.user_id != user_id {
+            return Err(HiveError::Auth("Not authorized to access this session".into()));
+        }
+        Ok(session)
+    }
+
+    /// SFTP session on the SSH connection of a live session owned by `user_id`
+    pub async fn sftp(&self, session_id: Uuid, user_id: Uuid) -> Result<Arc<Sftp>> {
+        let session = self.owned_live_session(session_id, user_id).await?;
+        let sftp = session.lock().await.sftp().await?;
+        Ok(sftp)
+    }
+
+    /// Remote directory uploaded files are written to
+    pub fn upload_dir(&self) -> &str {
+        &self.upload_dir
+    }
+
+    /// Fail uploads that have received nothing for `idle`, deleting their partial files.
+    ///
+    /// Returns how many expired.
+    pub async fn expire_uploads(&self, idle: Duration) -> Result<u64> {
+        let idle = chrono::Duration::from_std(idle).unwrap_or(chrono::Duration::MAX);
+        let cutoff = chrono::Utc::now().checked_sub_signed(idle).unwrap_or_default();
+        let transfers = UploadTransfer::expire_idle(&self.pool, cutoff).await?;
+
+        for transfer in &transfers {
+            info!("Upload {} expired at {} bytes", transfer.id, transfer.received_bytes);
+        }
+        self.remove_partial_uploads(&transfers).await;
+        Ok(transfers.len() as u64)
+    }
+
+    /// Delete the partial files of failed transfers, over their sessions' SFTP connections
+    async fn remove_partial_uploads(&self, transfers: &[UploadTransfer]) {
+        for transfer in transfers {
+            let path = transfer.partial_path.as_str();
+            let sftp = match self.get_session(transfer.session_id).await {
+                Some(session) => session.lock().await.sftp().await,
+                None => Err(HiveError::Session("Session not live".into())),
+            };
+            let removed = match sftp {
+                Ok(sftp) => sftp.remove_file(path).await.map_err(sftp_error),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = removed {
+                warn!("Left partial upload {} on the remote host: {}", path, e);
+            }
+        }
+    }
+
+    /// Type `data` into the shell of a live session owned by `user_id`
+    pub async fn send_input(&self, session_id: Uuid, user_id: Uuid, data: &[u8]) -> Result<()> {
+        let session = self.owned_live_session(session_id, user_id).await?;
         let session = session.lock().await;
-        let receiver = session.subscribe();
+        session.send(data).await
+    }
+
+    /// Start a broadcast group of live sessions owned by `user_id`
+    pub async fn create_group(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        session_ids: &[Uuid],
+    ) -> Result<SessionGroup> {
+        for &session_id in session_ids {
+            self.owned_live_session(session_id, user_id).await?;
+        }
+
+        let mut group = SessionGroup {
+            id: Uuid::new_v4(),
+            user_id,
+            name: name.to_string(),
+            session_ids: Vec::new(),
+        };
+        for &session_id in session_ids {
+            group.add(session_id);
+        }
+
+        self.groups.write().await.insert(group.id, group.clone());
+        info!("Created session group {} with {} sessions", group.id, group.session_ids.len());
+        Ok(group)
+    }
+
+    /// Groups owned by `user_id`, by name
+    pub async fn list_groups(&self, user_id: Uuid) -> Vec<SessionGroup> {
+        let mut groups: Vec<_> = self
+            .groups
+            .read()
+            .await
+            .values()
+            .filter(|group| group.user_id == user_id)
+            .cloned()
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+        groups
+    }
 
-        Ok((scrollback, receiver))
+    /// Add and remove members; added sessions must be live and owned by `user_id`
+    pub async fn update_group(
+        &self,
+        group_id: Uuid,
+        user_id: Uuid,
+        add: &[Uuid],
+        remove: &[Uuid],
+    ) -> Result<SessionGroup> {
+        self.owned_group(group_id, user_id).await?;
+        for &session_id in add {
+            self.owned_live_session(session_id, user_id).await?;
+        }
+
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(&group_id)
+            .ok_or_else(|| HiveError::Session("Session group not found".into()))?;
+        for &session_id in add {
+            group.add(session_id);
+        }
+        for &session_id in remove {
+            group.remove(session_id);
+        }
+        Ok(group.clone())
+    }
+
+    pub async fn delete_group(&self, group_id: Uuid, user_id: Uuid) -> Result<()> {
+        self.owned_group(group_id, user_id).await?;
+        self.groups.write().await.remove(&group_id);
+        info!("Deleted session group {}", group_id);
+        Ok(())
+    }
+
+    /// Type `data` into every session of a group at once, reporting each send
+    pub async fn broadcast(
+        &self,
+        group_id: Uuid,
+        user_id: Uuid,
+        data: &[u8],
+    ) -> Result<Vec<BroadcastResult>> {
+        let group = self.owned_group(group_id, user_id).await?;
+
+        let sends = group.session_ids.iter().map(|&session_id| async move {
+            BroadcastResult {
+                session_id,
+                result: self.send_input(session_id, user_id, data).await,
+            }
+        });
+        let results = futures::future::join_all(sends).await;
+
+        for failed in results.iter().filter(|r| r.result.is_err()) {
+            debug!("Broadcast to session {} failed: {:?}", failed.session_id, failed.result);
+        }
+        Ok(results)
+    }
+
+    async fn owned_group(&self, group_id: Uuid, user_id: Uuid) -> Result<SessionGroup> {
+        let group = self
+            .groups
+            .read()
+            .await
+            .get(&group_id)
+            .cloned()
+            .ok_or_else(|| HiveError::Session("Session group not found".into()))?;
+
+        if group.user_id != user_id {
+            return Err(HiveError::Auth("Not authorized to use this session group".into()));
+        }
+        Ok(group)
+    }
+
+    /// Write a file into the upload directory on the session's remote host.
+    ///
+    /// The name is sanitized and numbered if it already exists, so nothing is overwritten.
+    pub async fn upload_file(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<Upload> {
+        let sftp = self.sftp(session_id, user_id).await?;
+
+        let filename = sanitize_filename(filename);
+        create_dir_all(&sftp, &self.upload_dir).await?;
+        let (path, mut file) = create_unique(&sftp, &self.upload_dir, &filename).await?;
+
+        file.write_all(data).await?;
+        file.shutdown().await?;
+
+        let path = sftp.canonicalize(path.as_str()).await.map_err(sftp_error)?;
+        let size = data.len() as i64;
+        let upload = Upload::create(&self.pool, session_id, &filename, &path, size).await?;
+
+        info!(
+            "Uploaded {} ({} bytes) to {} for session {}",
+            filename, size, path, session_id
+        );
+        Ok(upload)
     }
 }
@@ -1,5 +1,27 @@
+mod activity;
+mod exec;
+mod group;
 mod manager;
+mod output;
+mod persistence;
+mod reaper;
+mod reconnect;
+mod scrollback;
+mod screen;
 mod service;
+mod startup;
 
-pub use manager::SessionManager;
+pub use activity::AttachedClient;
+pub use exec::{ExecEvent, ExecExit, ExecProcess};
+pub use group::{BroadcastResult, SessionGroup};
+pub use manager::{
+    RestoreSummary, ResumedAttach, SessionEnd, SessionManager, SessionOptions, DEFAULT_UPLOAD_DIR,
+};
+pub use output::{OutputChunk, OutputLog, OutputSubscription};
+pub use persistence::Persistence;
+pub use reaper::{ReapSummary, ReaperPolicy};
+pub use reconnect::{LinkState, ReconnectPolicy};
+pub use scrollback::{RetentionSummary, ScrollbackRetention};
+pub use screen::{ScreenSnapshot, MAX_TERMINAL_CELLS};
 pub use service::TerminalService;
+pub use startup::{expand_variables, prompt_regex, StartupMode};
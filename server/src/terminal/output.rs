@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use super::screen::{Screen, ScreenSnapshot, MAX_TERMINAL_CELLS};
+
+/// Persisted output kept in memory so recent reattaches skip the database
+const RETAINED_PERSISTED_BYTES: usize = 256 * 1024;
+/// Hard cap on buffered output, reached only if persistence falls far behind
+const MAX_BUFFERED_BYTES: usize = 16 * 1024 * 1024;
+/// Screen size until the session's terminal size is known
+const DEFAULT_SCREEN_SIZE: (u16, u16) = (80, 24);
+
+/// Terminal output tagged with the absolute offset of its first byte in the session stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputChunk {
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl OutputChunk {
+    /// Offset just past the last byte of this chunk
+    pub fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+impl Deref for OutputChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Output buffered in memory at the moment a subscriber joined
+pub struct OutputSubscription {
+    pub receiver: broadcast::Receiver<OutputChunk>,
+    /// Offset of the first byte of `buffered`
+    pub buffered_offset: u64,
+    pub buffered: Vec<u8>,
+}
+
+impl OutputSubscription {
+    /// Offset of the first byte the receiver will deliver
+    pub fn live_offset(&self) -> u64 {
+        self.buffered_offset + self.buffered.len() as u64
+    }
+}
+
+struct LogState {
+    /// Offset of `buffer[0]`
+    start: u64,
+    buffer: VecDeque<u8>,
+    /// Everything before this offset is in the database
+    persisted: u64,
+    /// What the output up to `end()` draws
+    screen: Screen,
+}
+
+impl LogState {
+    fn end(&self) -> u64 {
+        self.start + self.buffer.len() as u64
+    }
+
+    fn read_from(&self, offset: u64) -> Option<Vec<u8>> {
+        if offset < self.start || offset > self.end() {
+            return None;
+        }
+        let skip = (offset - self.start) as usize;
+        Some(self.buffer.iter().skip(skip).copied().collect())
+    }
+
+    /// Offset of the first byte not yet in the database
+    fn unpersisted_offset(&self) -> u64 {
+        self.persisted.max(self.start)
+    }
+
+    fn trim(&mut self) {
+        // Drop persisted bytes beyond the retention window
+        let evictable = (self.persisted.saturating_sub(self.start) as usize)
+            .min(self.buffer.len().saturating_sub(RETAINED_PERSISTED_BYTES));
+
+        // Never grow without bound, even if that loses unpersisted bytes. They are never
+        // stored, and readers of the scrollback report the gap this leaves in the offsets.
+        let overflow = self.buffer.len().saturating_sub(MAX_BUFFERED_BYTES);
+        if overflow > evictable {
+            warn!("Output buffer full, dropping {} unpersisted bytes", overflow - evictable);
+        }
+
+        let drop = evictable.max(overflow);
+        self.buffer.drain(..drop);
+        self.start += drop as u64;
+    }
+}
+
+/// Ordered record of a session's output, and the fan-out to live subscribers.
+///
+/// Appending and subscribing share one lock, so a subscriber sees every byte exactly once:
+/// either in the buffered snapshot or through its receiver. The same goes for the emulated
+/// screen, which is always current up to the end of the log.
+pub struct OutputLog {
+    state: Mutex<LogState>,
+    tx: broadcast::Sender<OutputChunk>,
+}
+
+impl OutputLog {
+    pub fn new(capacity: usize) -> Self {
+        Self::starting_at(capacity, 0)
+    }
+
+    /// Continue a stream whose first `offset` bytes are already in the database
+    pub fn starting_at(capacity: usize, offset: u64) -> Self {
+        Self {
+            state: Mutex::new(LogState {
+                start: offset,
+                buffer: VecDeque::new(),
+                persisted: offset,
+                screen: Screen::new(DEFAULT_SCREEN_SIZE.0, DEFAULT_SCREEN_SIZE.1),
+            }),
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Record output and broadcast it, returning its offset
+    pub fn append(&self, data: &[u8]) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let offset = state.end();
+        state.buffer.extend(data);
+        state.screen.process(data);
+        state.trim();
+
+        let _ = self.tx.send(OutputChunk {
+            offset,
+            data: data.to_vec(),
+        });
+        offset
+    }
+
+    /// Offset the next byte of output will have
+    pub fn end_offset(&self) -> u64 {
+        self.state.lock().unwrap().end()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OutputChunk> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribe to live output, also returning what is still buffered from `offset` on.
+    ///
+    /// If `offset` predates the buffer, the bytes before `buffered_offset` must come from
+    /// the database.
+    pub fn subscribe_from(&self, offset: u64) -> OutputSubscription {
+        let state = self.state.lock().unwrap();
+        let buffered_offset = offset.clamp(state.start, state.end());
+
+        OutputSubscription {
+            receiver: self.tx.subscribe(),
+            buffered_offset,
+            buffered: state.read_from(buffered_offset).unwrap_or_default(),
+        }
+    }
+
+    /// Subscribe to live output, starting right after a snapshot of the current screen
+    pub fn subscribe_with_snapshot(&self) -> (ScreenSnapshot, broadcast::Receiver<OutputChunk>) {
+        let state = self.state.lock().unwrap();
+        (state.screen.snapshot(state.end()), self.tx.subscribe())
+    }
+
+    /// Size the emulated screen like the session's terminal
+    pub fn resize_screen(&self, cols: u32, rows: u32) {
+        let clamp = |cells: u32| cells.clamp(1, MAX_TERMINAL_CELLS) as u16;
+        self.state.lock().unwrap().screen.resize(clamp(cols), clamp(rows));
+    }
+
+    /// Buffered output from `offset` on, if it is still in memory
+    pub fn read_from(&self, offset: u64) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().read_from(offset)
+    }
+
+    /// Up to `limit` bytes of output not yet written to the database, oldest first
+    pub fn unpersisted(&self, limit: usize) -> Option<OutputChunk> {
+        let state = self.state.lock().unwrap();
+        let offset = state.unpersisted_offset();
+        let skip = (offset - state.start) as usize;
+        let data: Vec<u8> = state.buffer.iter().skip(skip).take(limit).copied().collect();
+
+        (!data.is_empty()).then_some(OutputChunk { offset, data })
+    }
+
+    /// Number of buffered bytes not yet written to the database
+    pub fn unpersisted_len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        (state.end() - state.unpersisted_offset()) as usize
+    }
+
+    /// Record that everything before `offset` has been written to the database
+    pub fn mark_persisted(&self, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.persisted = state.persisted.max(offset);
+        state.trim();
+    }
+}
@@ -0,0 +1,71 @@
+use uuid::Uuid;
+
+use crate::ssh::shell_quote;
+use crate::{HiveError, Result};
+
+/// Terminal multiplexer that keeps a session's shell running on the remote host while no
+/// SSH connection is attached, e.g. across a server restart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persistence {
+    Tmux,
+    Screen,
+}
+
+impl Persistence {
+    /// None for connections that run the shell directly
+    pub fn parse(persistence: &str) -> Result<Option<Self>> {
+        match persistence {
+            "" | "none" => Ok(None),
+            "tmux" => Ok(Some(Self::Tmux)),
+            "screen" => Ok(Some(Self::Screen)),
+            other => Err(HiveError::Config(format!("Unknown persistence: {}", other))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tmux => "tmux",
+            Self::Screen => "screen",
+        }
+    }
+
+    /// Name of the multiplexer session that holds a Hive session's shell
+    pub fn session_name(session_id: Uuid) -> String {
+        format!("hive-{}", session_id)
+    }
+
+    /// Attach to the session's shell, starting it (running `script` if given) when missing
+    pub fn attach_command(&self, session_id: Uuid, script: Option<&str>) -> String {
+        let name = Self::session_name(session_id);
+        let mut command = match self {
+            Self::Tmux => format!("tmux new-session -A -s {}", name),
+            Self::Screen => format!("screen -D -R -S {}", name),
+        };
+        if let Some(script) = script {
+            let script = shell_quote(script);
+            match self {
+                Self::Tmux => command.push_str(&format!(" {}", script)),
+                Self::Screen => command.push_str(&format!(" sh -c {}", script)),
+            }
+        }
+        command
+    }
+
+    /// Exits with status 0 while the session's shell is still running
+    pub fn has_session_command(&self, session_id: Uuid) -> String {
+        let name = Self::session_name(session_id);
+        match self {
+            Self::Tmux => format!("tmux has-session -t {}", name),
+            Self::Screen => format!("screen -S {} -Q select .", name),
+        }
+    }
+
+    /// End the session's shell for good
+    pub fn kill_command(&self, session_id: Uuid) -> String {
+        let name = Self::session_name(session_id);
+        match self {
+            Self::Tmux => format!("tmux kill-session -t {}", name),
+            Self::Screen => format!("screen -S {} -X quit", name),
+        }
+    }
+}
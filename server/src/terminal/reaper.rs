@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use super::manager::{SessionEnd, SessionManager};
+
+/// When live sessions are suspended or closed for inactivity, and when abandoned uploads
+/// expire; unset limits are not enforced.
+///
+/// Suspended sessions are left alone.
+#[derive(Debug, Clone, Default)]
+pub struct ReaperPolicy {
+    /// Close sessions no client has been attached to for this long
+    pub close_unattended_after: Option<Duration>,
+    /// Close sessions without input or output for this long
+    pub close_idle_after: Option<Duration>,
+    /// Suspend sessions without input or output for this long
+    pub suspend_idle_after: Option<Duration>,
+    /// Fail uploads that have received nothing for this long and delete their partial files
+    pub expire_uploads_after: Option<Duration>,
+}
+
+/// Sessions closed or suspended, and uploads expired, by one reaper pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReapSummary {
+    pub unattended: u64,
+    pub idle: u64,
+    pub suspended: u64,
+    pub expired_uploads: u64,
+}
+
+impl ReapSummary {
+    /// Sessions closed
+    pub fn total(&self) -> u64 {
+        self.unattended + self.idle
+    }
+}
+
+/// Whole minutes, or seconds below a minute
+fn describe(duration: Duration) -> String {
+    match duration.as_secs() {
+        secs if secs < 60 => format!("{}s", secs),
+        secs if secs < 3600 => format!("{}m", secs / 60),
+        secs => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+impl ReaperPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.close_unattended_after.is_none()
+            && self.close_idle_after.is_none()
+            && self.suspend_idle_after.is_none()
+            && self.expire_uploads_after.is_none()
+    }
+
+    /// Close or suspend every live session past a limit, logging why, then expire uploads
+    pub async fn apply(&self, manager: &SessionManager) -> ReapSummary {
+        let mut summary = ReapSummary::default();
+
+        for session in manager.live_sessions().await {
+            let (session_id, idle_for, unattended_for) = {
+                let session = session.lock().await;
+                if session.is_suspended() {
+                    continue;
+                }
+                (session.session_id, session.idle_for(), session.unattended_for())
+            };
+
+            let unattended = unattended_for.filter(|&unattended| {
+                self.close_unattended_after.is_some_and(|max| unattended >= max)
+            });
+            let reason = if let Some(unattended) = unattended {
+                summary.unattended += 1;
+                format!("No client attached for {}", describe(unattended))
+            } else if self.close_idle_after.is_some_and(|max| idle_for >= max) {
+                summary.idle += 1;
+                format!("Idle for {}", describe(idle_for))
+            } else if self.suspend_idle_after.is_some_and(|max| idle_for >= max) {
+                info!("Suspending session {}: Idle for {}", session_id, describe(idle_for));
+                match manager.suspend_session(session_id).await {
+                    Ok(_) => summary.suspended += 1,
+                    Err(e) => error!("Failed to suspend session {}: {}", session_id, e),
+                }
+                continue;
+            } else {
+                continue;
+            };
+
+            info!("Reaping session {}: {}", session_id, reason);
+            if let Err(e) = manager.end_session(session_id, SessionEnd::new(reason)).await {
+                error!("Failed to reap session {}: {}", session_id, e);
+            }
+        }
+
+        if let Some(idle) = self.expire_uploads_after {
+            match manager.expire_uploads(idle).await {
+                Ok(expired) => summary.expired_uploads = expired,
+                Err(e) => error!("Failed to expire uploads: {}", e),
+            }
+        }
+
+        summary
+    }
+
+    /// Periodically close sessions past a limit while the server runs
+    pub fn spawn(
+        self,
+        manager: Arc<SessionManager>,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let summary = self.apply(&manager).await;
+                if summary.total() > 0 {
+                    info!("Session reaper closed {} sessions", summary.total());
+                }
+                if summary.suspended > 0 {
+                    info!("Session reaper suspended {} sessions", summary.suspended);
+                }
+                if summary.expired_uploads > 0 {
+                    info!("Session reaper expired {} uploads", summary.expired_uploads);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// Whether a live session's SSH connection is up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    Connected,
+    /// The connection dropped; attempt number `attempt` starts after `delay`
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        /// Why the connection dropped or the previous attempt failed
+        reason: String,
+    },
+}
+
+/// How a session re-establishes its SSH connection after the transport drops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Attempts before the session ends; 0 ends it as soon as the connection drops
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Wait before attempt `attempt` (from 1), doubling each time up to `max_delay`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
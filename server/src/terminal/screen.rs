@@ -0,0 +1,88 @@
+use vt100::{Callbacks, Parser};
+
+/// Largest terminal dimension accepted, in cells; the emulated screen holds cols * rows of them
+pub const MAX_TERMINAL_CELLS: u32 = 1000;
+
+/// Enters the alternate screen, which `Screen::state_formatted` leaves out
+const ENTER_ALTERNATE_SCREEN: &[u8] = b"\x1b[?1049h";
+
+/// Keeps the window title, which vt100 reports through a callback instead of storing
+#[derive(Default)]
+struct TitleTracker {
+    title: String,
+}
+
+impl Callbacks for TitleTracker {
+    fn set_window_title(&mut self, _: &mut vt100::Screen, title: &[u8]) {
+        self.title = String::from_utf8_lossy(title).into_owned();
+    }
+}
+
+/// What a terminal fed a session's output so far would show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenSnapshot {
+    /// Offset of the first byte of output after the snapshot
+    pub offset: u64,
+    pub cols: u16,
+    pub rows: u16,
+    /// Escape sequences that redraw the screen, its modes and cursor on a reset terminal
+    pub contents: Vec<u8>,
+    /// Zero-based row and column
+    pub cursor: (u16, u16),
+    pub cursor_visible: bool,
+    pub alternate_screen: bool,
+    pub application_cursor: bool,
+    pub application_keypad: bool,
+    pub bracketed_paste: bool,
+    pub title: String,
+}
+
+/// Emulates a terminal on a session's output to track the current screen grid, cursor,
+/// modes and title.
+///
+/// History is left to scrollback, so only the visible screen is kept.
+pub struct Screen {
+    parser: Parser<TitleTracker>,
+}
+
+impl Screen {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self {
+            parser: Parser::new_with_callbacks(rows, cols, 0, TitleTracker::default()),
+        }
+    }
+
+    pub fn process(&mut self, data: &[u8]) {
+        self.parser.process(data);
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.parser.screen_mut().set_size(rows, cols);
+    }
+
+    /// The screen as it stands after output up to `offset`
+    pub fn snapshot(&self, offset: u64) -> ScreenSnapshot {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+
+        let mut contents = Vec::new();
+        if screen.alternate_screen() {
+            contents.extend_from_slice(ENTER_ALTERNATE_SCREEN);
+        }
+        contents.extend(screen.state_formatted());
+
+        ScreenSnapshot {
+            offset,
+            cols,
+            rows,
+            contents,
+            cursor: screen.cursor_position(),
+            cursor_visible: !screen.hide_cursor(),
+            alternate_screen: screen.alternate_screen(),
+            application_cursor: screen.application_cursor(),
+            application_keypad: screen.application_keypad(),
+            bracketed_paste: screen.bracketed_paste(),
+            title: self.parser.callbacks().title.clone(),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgPool;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use crate::db::ScrollbackChunk;
+use crate::Result;
+
+use super::output::{OutputChunk, OutputLog};
+
+/// Flush as soon as this much output is waiting
+const FLUSH_BYTES: usize = 64 * 1024;
+/// Longest a byte of output waits before being written
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Limits on stored scrollback; unset limits are not enforced.
+///
+/// Whole chunks are removed oldest first, so the offsets of what remains never change.
+#[derive(Debug, Clone, Default)]
+pub struct ScrollbackRetention {
+    /// Bytes kept per session
+    pub max_session_bytes: Option<u64>,
+    /// Bytes kept per user, across all of their sessions
+    pub max_user_bytes: Option<u64>,
+    /// Age after which chunks are removed, the same for every session and user
+    pub max_age: Option<Duration>,
+}
+
+/// Chunks removed by one retention pass, per limit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionSummary {
+    pub expired: u64,
+    pub over_session_cap: u64,
+    pub over_user_cap: u64,
+}
+
+impl RetentionSummary {
+    pub fn total(&self) -> u64 {
+        self.expired + self.over_session_cap + self.over_user_cap
+    }
+}
+
+impl ScrollbackRetention {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_session_bytes.is_none() && self.max_user_bytes.is_none() && self.max_age.is_none()
+    }
+
+    /// Delete stored scrollback that exceeds any limit
+    pub async fn apply(&self, pool: &PgPool) -> Result<RetentionSummary> {
+        let mut summary = RetentionSummary::default();
+
+        if let Some(max_age) = self.max_age {
+            let max_age = chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX);
+            let cutoff = Utc::now().checked_sub_signed(max_age).unwrap_or_default();
+            summary.expired = ScrollbackChunk::delete_older_than(pool, cutoff).await?;
+        }
+        if let Some(max_bytes) = self.max_session_bytes {
+            summary.over_session_cap = ScrollbackChunk::trim_sessions_to(pool, max_bytes).await?;
+        }
+        if let Some(max_bytes) = self.max_user_bytes {
+            summary.over_user_cap = ScrollbackChunk::trim_users_to(pool, max_bytes).await?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Periodically enforce the limits while the server runs
+    pub fn spawn_gc(self, pool: PgPool, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.apply(&pool).await {
+                    Ok(summary) if summary.total() > 0 => {
+                        info!("Scrollback GC removed {} chunks", summary.total());
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Scrollback GC failed: {}", e),
+                }
+            }
+        })
+    }
+}
+
+enum Command {
+    Flush(oneshot::Sender<()>),
+    Close(oneshot::Sender<()>),
+}
+
+/// Background task that persists a session's output in batches.
+///
+/// Unwritten output stays in the session's `OutputLog` until it is flushed, so the writer only
+/// uses the broadcast as a wake-up and never loses bytes when it lags. Dropping the handle
+/// flushes whatever is left and stops the task.
+pub struct ScrollbackWriter {
+    commands: mpsc::Sender<Command>,
+}
+
+impl ScrollbackWriter {
+    pub fn spawn(pool: PgPool, session_id: Uuid, output: Arc<OutputLog>) -> Self {
+        let (commands, commands_rx) = mpsc::channel(8);
+        let output_rx = output.subscribe();
+        let task = WriterTask {
+            pool,
+            session_id,
+            output,
+        };
+        tokio::spawn(task.run(output_rx, commands_rx));
+
+        Self { commands }
+    }
+
+    /// Write all output produced so far
+    pub async fn flush(&self) {
+        self.request(Command::Flush).await;
+    }
+
+    /// Write all output produced so far and stop the task
+    pub async fn close(&self) {
+        self.request(Command::Close).await;
+    }
+
+    async fn request(&self, command: fn(oneshot::Sender<()>) -> Command) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.commands.send(command(done_tx)).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+struct WriterTask {
+    pool: PgPool,
+    session_id: Uuid,
+    output: Arc<OutputLog>,
+}
+
+impl WriterTask {
+    async fn run(
+        self,
+        mut output_rx: broadcast::Receiver<OutputChunk>,
+        mut commands: mpsc::Receiver<Command>,
+    ) {
+        let mut deadline: Option<Instant> = None;
+
+        let done = loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Flush(done)) => {
+                        deadline = self.flush().await;
+                        let _ = done.send(());
+                    }
+                    Some(Command::Close(done)) => break Some(done),
+                    None => break None,
+                },
+                result = output_rx.recv() => match result {
+                    // Lagging only skips wake-ups; the bytes are read back from the log
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if self.output.unpersisted_len() >= FLUSH_BYTES {
+                            deadline = self.flush().await;
+                        } else if deadline.is_none() {
+                            deadline = Some(Instant::now() + FLUSH_INTERVAL);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break None,
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    deadline = self.flush().await;
+                }
+            }
+        };
+
+        self.flush().await;
+        debug!("Scrollback writer for session {} stopped", self.session_id);
+        if let Some(done) = done {
+            let _ = done.send(());
+        }
+    }
+
+    /// Write pending output, returning when to retry if the database refused it
+    async fn flush(&self) -> Option<Instant> {
+        while let Some(chunk) = self.output.unpersisted(FLUSH_BYTES) {
+            if let Err(e) =
+                ScrollbackChunk::append_at(&self.pool, self.session_id, chunk.offset, &chunk.data)
+                    .await
+            {
+                error!("Failed to save scrollback for session {}: {}", self.session_id, e);
+                return Some(Instant::now() + FLUSH_INTERVAL);
+            }
+            self.output.mark_persisted(chunk.end());
+        }
+        None
+    }
+}
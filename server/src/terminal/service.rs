@@ -1,17 +1,28 @@
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::Stream;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 use tonic::{Request, Response, Status, Streaming};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use super::SessionManager;
+use super::{
+    ExecEvent, LinkState, OutputChunk, ResumedAttach, ScreenSnapshot, SessionManager,
+    MAX_TERMINAL_CELLS,
+};
+use crate::api::authenticated_user_id;
 use crate::proto::terminal_server::Terminal;
-use crate::proto::{terminal_input, terminal_output, Error as ProtoError, TerminalInput, TerminalOutput};
+use crate::ssh::shell_quote;
+use crate::proto::{
+    exec_output, terminal_input, terminal_output, Error as ProtoError, ExecExit, ExecOutput,
+    ExecRequest, FileUploaded, ScreenSnapshot as ProtoScreenSnapshot, ScrollbackTruncated,
+    SessionClosed, SessionReconnected, SessionReconnecting, TerminalInput, TerminalOutput,
+};
+use crate::HiveError;
 
 pub struct TerminalService {
     session_manager: Arc<SessionManager>,
@@ -21,26 +32,107 @@ impl TerminalService {
     pub fn new(session_manager: Arc<SessionManager>) -> Self {
         Self { session_manager }
     }
+}
+
+/// Largest scrollback frame sent when replaying missed output
+const SCROLLBACK_FRAME_SIZE: usize = 64 * 1024;
+/// Exec timeout when the request does not set one
+const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest timeout an exec request may ask for
+const MAX_EXEC_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Writes output frames in offset order, dropping bytes the client already has
+struct OutputForwarder {
+    tx: mpsc::Sender<Result<TerminalOutput, Status>>,
+    next_offset: u64,
+}
+
+impl OutputForwarder {
+    async fn send(&self, offset: u64, payload: terminal_output::Payload) -> bool {
+        self.tx
+            .send(Ok(TerminalOutput {
+                payload: Some(payload),
+                offset,
+            }))
+            .await
+            .is_ok()
+    }
+
+    async fn send_scrollback(&mut self, offset: u64, data: &[u8]) -> bool {
+        self.next_offset = offset;
+        for frame in data.chunks(SCROLLBACK_FRAME_SIZE) {
+            let payload = terminal_output::Payload::Scrollback(frame.to_vec());
+            if !self.send(self.next_offset, payload).await {
+                return false;
+            }
+            self.next_offset += frame.len() as u64;
+        }
+        true
+    }
+
+    async fn send_live(&mut self, chunk: OutputChunk) -> bool {
+        if chunk.end() <= self.next_offset {
+            return true;
+        }
+        if chunk.offset > self.next_offset {
+            warn!(
+                "Output gap: expected offset {}, got {}",
+                self.next_offset, chunk.offset
+            );
+        }
 
-    fn extract_user_id(request: &Request<Streaming<TerminalInput>>) -> Result<Uuid, Status> {
-        request
-            .metadata()
-            .get("x-user-id")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| Uuid::parse_str(s).ok())
-            .ok_or_else(|| Status::unauthenticated("Missing or invalid user ID"))
+        let offset = chunk.offset.max(self.next_offset);
+        let data = chunk.data[(offset - chunk.offset) as usize..].to_vec();
+        self.next_offset = chunk.end();
+        self.send(offset, terminal_output::Payload::Data(data)).await
+    }
+}
+
+fn snapshot_to_proto(snapshot: ScreenSnapshot) -> ProtoScreenSnapshot {
+    ProtoScreenSnapshot {
+        cols: snapshot.cols.into(),
+        rows: snapshot.rows.into(),
+        contents: snapshot.contents,
+        cursor_row: snapshot.cursor.0.into(),
+        cursor_col: snapshot.cursor.1.into(),
+        cursor_visible: snapshot.cursor_visible,
+        alternate_screen: snapshot.alternate_screen,
+        application_cursor: snapshot.application_cursor,
+        application_keypad: snapshot.application_keypad,
+        bracketed_paste: snapshot.bracketed_paste,
+        title: snapshot.title,
+    }
+}
+
+fn attach_error_to_status(e: HiveError) -> Status {
+    match e {
+        HiveError::Auth(msg) => Status::permission_denied(msg),
+        HiveError::Session(msg) => Status::not_found(msg),
+        e => Status::internal(format!("Failed to attach: {}", e)),
+    }
+}
+
+fn exec_error_to_status(e: HiveError) -> Status {
+    match e {
+        HiveError::Auth(msg) => Status::permission_denied(msg),
+        HiveError::Session(msg) => Status::not_found(msg),
+        e @ (HiveError::HostKeyMismatch { .. } | HiveError::HostKeyUnknown { .. }) => {
+            Status::failed_precondition(e.to_string())
+        }
+        e => Status::internal(format!("Failed to run command: {}", e)),
     }
 }
 
 #[tonic::async_trait]
 impl Terminal for TerminalService {
     type AttachStream = Pin<Box<dyn Stream<Item = Result<TerminalOutput, Status>> + Send>>;
+    type ExecStream = Pin<Box<dyn Stream<Item = Result<ExecOutput, Status>> + Send>>;
 
     async fn attach(
         &self,
         request: Request<Streaming<TerminalInput>>,
     ) -> Result<Response<Self::AttachStream>, Status> {
-        let user_id = Self::extract_user_id(&request)?;
+        let user_id = authenticated_user_id(&request)?;
         let mut input_stream = request.into_inner();
 
         // Wait for the first message to get the session_id
@@ -55,101 +147,360 @@ impl Terminal for TerminalService {
 
         info!("User {} attaching to session {}", user_id, session_id);
 
-        // Get the session and subscribe to output
+        // Subscribe to output, starting from the current screen or replaying what the client
+        // missed
+        let resumed = if first_msg.snapshot {
+            self.session_manager.attach_with_snapshot(session_id, user_id).await
+        } else {
+            self.session_manager
+                .attach_from(session_id, user_id, first_msg.last_seen_offset)
+                .await
+        }
+        .map_err(attach_error_to_status)?;
+
         let session = self
             .session_manager
             .get_session(session_id)
             .await
             .ok_or_else(|| Status::not_found("Session not found"))?;
 
-        let output_rx = {
-            let session = session.lock().await;
-            if session.user_id != user_id {
-                return Err(Status::permission_denied("Not authorized to access this session"));
-            }
-            session.subscribe()
-        };
-
         // Create gRPC output stream
         let (output_tx, output_rx_grpc) = mpsc::channel::<Result<TerminalOutput, Status>>(1024);
 
         // Task to forward SSH output to gRPC stream
-        let output_tx_clone = output_tx.clone();
-        let mut output_rx = output_rx;
+        let mut forwarder = OutputForwarder {
+            tx: output_tx.clone(),
+            next_offset: resumed.live_offset(),
+        };
+        let ResumedAttach {
+            scrollback_offset,
+            scrollback,
+            truncated_from,
+            receiver: mut output_rx,
+            ended: mut end_rx,
+            link: mut link_rx,
+            output,
+            client,
+            snapshot,
+        } = resumed;
         tokio::spawn(async move {
-            loop {
-                match output_rx.recv().await {
-                    Ok(data) => {
-                        if let Err(e) = output_tx_clone
-                            .send(Ok(TerminalOutput {
-                                payload: Some(terminal_output::Payload::Data(data)),
-                            }))
-                            .await
-                        {
-                            debug!("Output channel closed: {}", e);
-                            break;
+            // Counts as attached until the client goes away or the session ends
+            let _client = client;
+
+            if let Some(requested_offset) = truncated_from {
+                let payload = terminal_output::Payload::Truncated(ScrollbackTruncated {
+                    requested_offset,
+                    available_offset: scrollback_offset,
+                });
+                if !forwarder.send(scrollback_offset, payload).await {
+                    return;
+                }
+            }
+
+            if !scrollback.is_empty()
+                && !forwarder.send_scrollback(scrollback_offset, &scrollback).await
+            {
+                return;
+            }
+
+            if let Some(snapshot) = snapshot {
+                let offset = snapshot.offset;
+                let payload = terminal_output::Payload::Snapshot(snapshot_to_proto(snapshot));
+                if !forwarder.send(offset, payload).await {
+                    return;
+                }
+            }
+
+            // Attempts seen since the connection dropped, for the reconnected frame
+            let mut attempts = 0;
+            // Report a reconnect already under way when the client attached
+            link_rx.mark_changed();
+            let end = loop {
+                tokio::select! {
+                    biased;
+                    result = output_rx.recv() => match result {
+                        Ok(chunk) => {
+                            if !forwarder.send_live(chunk).await {
+                                debug!("Output channel closed");
+                                return;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            info!("SSH output channel closed");
+                            break end_rx.borrow().clone();
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Lagged behind {} messages, replaying from buffer", n);
+                            let offset = forwarder.next_offset;
+                            let Some(data) = output.upgrade().and_then(|o| o.read_from(offset)) else {
+                                let _ = forwarder
+                                    .send(offset, terminal_output::Payload::Error(ProtoError {
+                                        code: "OUTPUT_LAGGED".to_string(),
+                                        message: "Output is no longer buffered, reattach from the last offset".to_string(),
+                                    }))
+                                    .await;
+                                return;
+                            };
+                            if !forwarder.send_live(OutputChunk { offset, data }).await {
+                                return;
+                            }
+                        }
+                    },
+                    Ok(()) = link_rx.changed() => {
+                        let state = link_rx.borrow_and_update().clone();
+                        let payload = match state {
+                            LinkState::Reconnecting { attempt, delay, reason } => {
+                                attempts = attempt;
+                                terminal_output::Payload::Reconnecting(SessionReconnecting {
+                                    attempt,
+                                    retry_in_ms: delay.as_millis() as u32,
+                                    reason,
+                                })
+                            }
+                            LinkState::Connected if attempts > 0 => {
+                                let payload =
+                                    terminal_output::Payload::Reconnected(SessionReconnected {
+                                        attempts,
+                                    });
+                                attempts = 0;
+                                payload
+                            }
+                            LinkState::Connected => continue,
+                        };
+                        if !forwarder.send(forwarder.next_offset, payload).await {
+                            return;
                         }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-                        info!("SSH output channel closed");
-                        break;
+                    _ = forwarder.tx.closed() => {
+                        debug!("Client detached from session {}", session_id);
+                        return;
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        warn!("Lagged behind {} messages", n);
+                    _ = end_rx.changed() => {
+                        // Flush output produced before the session ended
+                        while let Ok(chunk) = output_rx.try_recv() {
+                            if !forwarder.send_live(chunk).await {
+                                return;
+                            }
+                        }
+                        break end_rx.borrow().clone();
                     }
                 }
+            };
+
+            if let Some(end) = end {
+                let payload = terminal_output::Payload::Closed(SessionClosed {
+                    session_id: session_id.to_string(),
+                    reason: end.reason,
+                    exit_code: end.exit_code,
+                    exit_signal: end.exit_signal.unwrap_or_default(),
+                });
+                let _ = forwarder.send(forwarder.next_offset, payload).await;
             }
         });
 
         // Task to handle input from gRPC stream
         let session_for_input = session.clone();
         let output_tx_for_input = output_tx.clone();
+        let session_manager = self.session_manager.clone();
+        let mut input_end_rx = session.lock().await.subscribe_end();
         tokio::spawn(async move {
-            while let Some(result) = input_stream.next().await {
-                match result {
-                    Ok(input) => {
-                        let session = session_for_input.lock().await;
+            loop {
+                let result = tokio::select! {
+                    result = input_stream.next() => match result {
+                        Some(result) => result,
+                        None => break,
+                    },
+                    // Stop reading once the session ends, so the response stream can finish
+                    _ = input_end_rx.wait_for(Option::is_some) => break,
+                };
+                let input = match result {
+                    Ok(input) => input,
+                    Err(e) => {
+                        error!("Input stream error: {}", e);
+                        break;
+                    }
+                };
 
-                        match input.payload {
-                            Some(terminal_input::Payload::Data(data)) => {
-                                debug!("Received {} bytes of input", data.len());
-                                if let Err(e) = session.send(&data).await {
-                                    error!("Failed to send input: {}", e);
-                                    let _ = output_tx_for_input
-                                        .send(Ok(TerminalOutput {
-                                            payload: Some(terminal_output::Payload::Error(ProtoError {
-                                                code: "SSH_ERROR".to_string(),
-                                                message: format!("Failed to send input: {}", e),
-                                            })),
-                                        }))
-                                        .await;
-                                    break;
+                match input.payload {
+                    Some(terminal_input::Payload::Data(data)) => {
+                        debug!("Received {} bytes of input", data.len());
+                        let session = session_for_input.lock().await;
+                        if let Err(e) = session.send(&data).await {
+                            // Input is refused only until the session resumes or the connection
+                            // is back; the stream stays usable and ends with the session
+                            let code = if session.is_suspended() {
+                                debug!("Dropped input while suspended: {}", e);
+                                "SESSION_SUSPENDED"
+                            } else if session.is_reconnecting() {
+                                debug!("Dropped input while reconnecting: {}", e);
+                                "SESSION_RECONNECTING"
+                            } else {
+                                error!("Failed to send input: {}", e);
+                                "SSH_ERROR"
+                            };
+                            let _ = output_tx_for_input
+                                .send(Ok(TerminalOutput {
+                                    payload: Some(terminal_output::Payload::Error(ProtoError {
+                                        code: code.to_string(),
+                                        message: format!("Failed to send input: {}", e),
+                                    })),
+                                    offset: session.output_offset(),
+                                }))
+                                .await;
+                        }
+                    }
+                    Some(terminal_input::Payload::Resize(resize)) => {
+                        debug!("Resizing to {}x{}", resize.cols, resize.rows);
+                        let session = session_for_input.lock().await;
+                        if resize.cols > MAX_TERMINAL_CELLS || resize.rows > MAX_TERMINAL_CELLS {
+                            let _ = output_tx_for_input
+                                .send(Ok(TerminalOutput {
+                                    payload: Some(terminal_output::Payload::Error(ProtoError {
+                                        code: "INVALID_RESIZE".to_string(),
+                                        message: format!(
+                                            "Terminal size {}x{} is too large",
+                                            resize.cols, resize.rows
+                                        ),
+                                    })),
+                                    offset: session.output_offset(),
+                                }))
+                                .await;
+                        } else if let Err(e) = session.resize(resize.cols, resize.rows).await {
+                            warn!("Failed to resize: {}", e);
+                        }
+                    }
+                    Some(terminal_input::Payload::File(file)) => {
+                        // Written on its own task so keystrokes and resizes are not held up
+                        let session_manager = session_manager.clone();
+                        let session = session_for_input.clone();
+                        let output_tx = output_tx_for_input.clone();
+                        tokio::spawn(async move {
+                            info!("File upload: {} ({} bytes)", file.filename, file.data.len());
+                            let payload = match session_manager
+                                .upload_file(session_id, user_id, &file.filename, &file.data)
+                                .await
+                            {
+                                Ok(upload) => {
+                                    if file.paste_path {
+                                        let session = session.lock().await;
+                                        let quoted = shell_quote(&upload.path);
+                                        if let Err(e) = session.send(quoted.as_bytes()).await {
+                                            warn!("Failed to paste upload path: {}", e);
+                                        }
+                                    }
+                                    terminal_output::Payload::File(FileUploaded {
+                                        path: upload.path,
+                                        filename: upload.filename,
+                                    })
                                 }
-                            }
-                            Some(terminal_input::Payload::Resize(resize)) => {
-                                debug!("Resizing to {}x{}", resize.cols, resize.rows);
-                                if let Err(e) = session.resize(resize.cols, resize.rows).await {
-                                    warn!("Failed to resize: {}", e);
+                                Err(e) => {
+                                    warn!("File upload failed: {}", e);
+                                    let message =
+                                        format!("Failed to upload {}: {}", file.filename, e);
+                                    terminal_output::Payload::Error(ProtoError {
+                                        code: "UPLOAD_FAILED".to_string(),
+                                        message,
+                                    })
                                 }
+                            };
+
+                            let offset = session.lock().await.output_offset();
+                            let _ = output_tx
+                                .send(Ok(TerminalOutput {
+                                    payload: Some(payload),
+                                    offset,
+                                }))
+                                .await;
+                        });
+                    }
+                    None => {}
+                }
+            }
+
+            info!("Input stream ended for session {}", session_id);
+        });
+
+        let output_stream = ReceiverStream::new(output_rx_grpc);
+        Ok(Response::new(Box::pin(output_stream)))
+    }
+
+    async fn exec(
+        &self,
+        request: Request<ExecRequest>,
+    ) -> Result<Response<Self::ExecStream>, Status> {
+        let user_id = authenticated_user_id(&request)?;
+        let req = request.into_inner();
+
+        let connection_id = Uuid::parse_str(&req.connection_id)
+            .map_err(|_| Status::invalid_argument("Invalid connection ID"))?;
+        if req.command.trim().is_empty() {
+            return Err(Status::invalid_argument("Command is required"));
+        }
+        let timeout = match req.timeout_seconds {
+            0 => DEFAULT_EXEC_TIMEOUT,
+            seconds => Duration::from_secs(seconds.into()).min(MAX_EXEC_TIMEOUT),
+        };
+
+        let mut process = self
+            .session_manager
+            .exec(user_id, connection_id, &req.password, &req.command)
+            .await
+            .map_err(exec_error_to_status)?;
+
+        let (tx, rx) = mpsc::channel::<Result<ExecOutput, Status>>(64);
+        tokio::spawn(async move {
+            let send = |payload| {
+                let tx = tx.clone();
+                async move { tx.send(Ok(ExecOutput { payload: Some(payload) })).await.is_ok() }
+            };
+
+            let deadline = tokio::time::sleep(timeout);
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    event = process.next() => {
+                        let payload = match event {
+                            Some(ExecEvent::Stdout(data)) => exec_output::Payload::Stdout(data),
+                            Some(ExecEvent::Stderr(data)) => exec_output::Payload::Stderr(data),
+                            Some(ExecEvent::Exit(exit)) => {
+                                debug!("Command on {} exited: {:?}", connection_id, exit);
+                                send(exec_output::Payload::Exit(ExecExit {
+                                    exit_code: exit.exit_code,
+                                    exit_signal: exit.exit_signal.unwrap_or_default(),
+                                    timed_out: false,
+                                }))
+                                .await;
+                                break;
                             }
-                            Some(terminal_input::Payload::File(file)) => {
-                                info!("File upload: {} ({} bytes)", file.filename, file.data.len());
-                                // TODO: Handle file upload - save to temp dir and send path
-                            }
-                            None => {}
+                            None => break,
+                        };
+                        if !send(payload).await {
+                            process.kill().await;
+                            break;
                         }
                     }
-                    Err(e) => {
-                        error!("Input stream error: {}", e);
+                    _ = &mut deadline => {
+                        info!("Command on {} timed out after {:?}", connection_id, timeout);
+                        process.kill().await;
+                        send(exec_output::Payload::Exit(ExecExit {
+                            exit_code: None,
+                            exit_signal: String::new(),
+                            timed_out: true,
+                        }))
+                        .await;
+                        break;
+                    }
+                    _ = tx.closed() => {
+                        info!("Command on {} cancelled by the client", connection_id);
+                        process.kill().await;
                         break;
                     }
                 }
             }
 
-            info!("Input stream ended for session {}", session_id);
+            process.close().await;
         });
 
-        let output_stream = ReceiverStream::new(output_rx_grpc);
-        Ok(Response::new(Box::pin(output_stream)))
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
     }
 }
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use regex::bytes::Regex;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::manager::ActiveSession;
+use super::output::OutputChunk;
+use crate::db::{Connection, ConnectionVariable};
+use crate::{HiveError, Result};
+
+/// How long prompt mode waits for the prompt before giving up on the startup command
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Output kept for matching the prompt
+const PROMPT_WINDOW: usize = 4096;
+
+/// How a connection's startup command is run once the SSH channel is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupMode {
+    /// Typed into the interactive shell as soon as it starts
+    Typed,
+    /// Run with an exec request instead of starting a shell
+    Exec,
+    /// Typed into the shell once its output matches the prompt pattern
+    Prompt,
+}
+
+impl StartupMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "" | "typed" => Ok(Self::Typed),
+            "exec" => Ok(Self::Exec),
+            "prompt" => Ok(Self::Prompt),
+            other => Err(HiveError::Config(format!("Unknown startup mode: {}", other))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Typed => "typed",
+            Self::Exec => "exec",
+            Self::Prompt => "prompt",
+        }
+    }
+}
+
+/// Compile a prompt pattern so it only matches at the end of the output so far
+pub fn prompt_regex(pattern: &str) -> Result<Regex> {
+    if pattern.is_empty() {
+        return Err(HiveError::Config("Prompt mode needs a prompt pattern".into()));
+    }
+    Regex::new(&format!(r"(?:{})\z", pattern))
+        .map_err(|e| HiveError::Config(format!("Invalid prompt pattern: {}", e)))
+}
+
+/// Replace `${name}` with the variable's value.
+///
+/// Unknown names are left as they are, so the remote shell can still expand its own variables.
+pub fn expand_variables(template: &str, variables: &HashMap<String, String>) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) if variables.contains_key(&after[..end]) => {
+                expanded.push_str(&variables[&after[..end]]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                expanded.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// A connection's startup command, ready to run
+#[derive(Clone)]
+pub(super) struct StartupCommand {
+    pub mode: StartupMode,
+    /// Variables substituted; typed commands end with a newline
+    pub script: String,
+    pub prompt: Option<Regex>,
+}
+
+impl StartupCommand {
+    /// None when the connection has no startup command
+    pub async fn for_connection(
+        pool: &sqlx::PgPool,
+        connection: &Connection,
+    ) -> Result<Option<Self>> {
+        let Some(command) = connection.startup_command.as_deref().filter(|c| !c.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let mode = StartupMode::parse(&connection.startup_mode)?;
+        let prompt = match mode {
+            StartupMode::Prompt => {
+                Some(prompt_regex(connection.startup_prompt.as_deref().unwrap_or_default())?)
+            }
+            _ => None,
+        };
+
+        let variables: HashMap<String, String> =
+            ConnectionVariable::list_for_connection(pool, connection.id)
+                .await?
+                .into_iter()
+                .map(|v| (v.name, v.value))
+                .collect();
+
+        // A typed script runs line by line, so the last line needs a newline too
+        let mut script = expand_variables(command, &variables).replace("\r\n", "\n");
+        if mode != StartupMode::Exec && !script.ends_with('\n') {
+            script.push('\n');
+        }
+
+        Ok(Some(Self { mode, script, prompt }))
+    }
+
+    /// Type the command into a shell that has just started.
+    ///
+    /// Exec-mode commands already ran in place of the shell, so there is nothing to type.
+    /// `prompt_rx` must be subscribed before the shell started, so the prompt is not missed.
+    pub async fn type_into(
+        &self,
+        session_id: Uuid,
+        session: &Arc<Mutex<ActiveSession>>,
+        prompt_rx: broadcast::Receiver<OutputChunk>,
+    ) {
+        match (self.mode, &self.prompt) {
+            (StartupMode::Typed, _) => {
+                // Input typed before the shell reads it waits in the PTY
+                match session.lock().await.send(self.script.as_bytes()).await {
+                    Ok(()) => info!("Typed startup command into session {}", session_id),
+                    Err(e) => warn!("Failed to type startup command: {}", e),
+                }
+            }
+            (StartupMode::Prompt, Some(prompt)) => {
+                tokio::spawn(type_after_prompt(
+                    session_id,
+                    Arc::downgrade(session),
+                    prompt_rx,
+                    prompt.clone(),
+                    self.script.clone(),
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Type `script` into the session once the end of its output matches `prompt`
+pub(super) async fn type_after_prompt(
+    session_id: Uuid,
+    session: Weak<Mutex<ActiveSession>>,
+    mut output_rx: broadcast::Receiver<OutputChunk>,
+    prompt: Regex,
+    script: String,
+) {
+    let mut window: Vec<u8> = Vec::new();
+    let matched = tokio::time::timeout(PROMPT_TIMEOUT, async {
+        loop {
+            match output_rx.recv().await {
+                Ok(chunk) => {
+                    window.extend_from_slice(&chunk);
+                    if window.len() > PROMPT_WINDOW {
+                        window.drain(..window.len() - PROMPT_WINDOW);
+                    }
+                    if prompt.is_match(&window) {
+                        return true;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return false,
+            }
+        }
+    })
+    .await;
+
+    if !matches!(matched, Ok(true)) {
+        warn!("Prompt never appeared in session {}; startup command not run", session_id);
+        return;
+    }
+
+    let Some(session) = session.upgrade() else {
+        return;
+    };
+    let session = session.lock().await;
+    match session.send(script.as_bytes()).await {
+        Ok(()) => info!("Ran startup command in session {} after prompt", session_id),
+        Err(e) => warn!("Failed to run startup command in session {}: {}", session_id, e),
+    }
+}
@@ -0,0 +1,229 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+use crate::{HiveError, Result};
+
+pub const DEFAULT_TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate files the server presents, and optionally the CA client certificates must chain to
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// When set, clients must present a certificate signed by this CA (mutual TLS)
+    pub client_ca_path: Option<PathBuf>,
+    pub reload_interval: Duration,
+}
+
+impl TlsOptions {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+            reload_interval: DEFAULT_TLS_RELOAD_INTERVAL,
+        }
+    }
+
+    pub fn with_client_ca(mut self, client_ca_path: impl Into<PathBuf>) -> Self {
+        self.client_ca_path = Some(client_ca_path.into());
+        self
+    }
+
+    pub fn with_reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.cert_path, &self.key_path]
+            .into_iter()
+            .chain(self.client_ca_path.as_ref())
+    }
+}
+
+/// TLS acceptor whose certificates can be swapped while the server keeps running.
+///
+/// New connections use the most recently loaded configuration; established ones are unaffected.
+#[derive(Clone)]
+pub struct ReloadingTlsAcceptor {
+    inner: Arc<AcceptorInner>,
+}
+
+struct AcceptorInner {
+    options: TlsOptions,
+    config: RwLock<Arc<ServerConfig>>,
+    digest: Mutex<Vec<u8>>,
+}
+
+impl ReloadingTlsAcceptor {
+    pub fn new(options: TlsOptions) -> Result<Self> {
+        let digest = files_digest(&options)?;
+        let config = load_server_config(&options)?;
+
+        Ok(Self {
+            inner: Arc::new(AcceptorInner {
+                options,
+                config: RwLock::new(Arc::new(config)),
+                digest: Mutex::new(digest),
+            }),
+        })
+    }
+
+    pub fn options(&self) -> &TlsOptions {
+        &self.inner.options
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.inner.config.read().unwrap().clone())
+    }
+
+    /// Reload the certificate files if their contents changed.
+    ///
+    /// Returns whether a new configuration was installed. On error the previous one stays active.
+    pub fn reload(&self) -> Result<bool> {
+        let options = &self.inner.options;
+        let digest = files_digest(options)?;
+        if *self.inner.digest.lock().unwrap() == digest {
+            return Ok(false);
+        }
+
+        let config = load_server_config(options)?;
+        *self.inner.config.write().unwrap() = Arc::new(config);
+        *self.inner.digest.lock().unwrap() = digest;
+
+        info!("Reloaded TLS certificate from {}", options.cert_path.display());
+        Ok(true)
+    }
+
+    /// Periodically pick up renewed certificates
+    pub fn spawn_reloader(&self) -> tokio::task::JoinHandle<()> {
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(acceptor.options().reload_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = acceptor.reload() {
+                    warn!("Failed to reload TLS certificate, keeping the current one: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Accept TCP connections and yield those that complete a TLS handshake.
+    ///
+    /// Handshakes run concurrently, and failed ones are logged and dropped rather than ending the stream.
+    pub fn incoming(&self, listener: TcpListener) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+        let (tx, rx) = mpsc::channel(64);
+        let acceptor = self.clone();
+
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let _ = stream.set_nodelay(true);
+
+                let tls = acceptor.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+}
+
+fn read_file(path: &PathBuf) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| HiveError::Config(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn files_digest(options: &TlsOptions) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    for path in options.paths() {
+        hasher.update(read_file(path)?);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = read_file(path)?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| HiveError::Config(format!("Invalid certificate in {}: {}", path.display(), e)))?;
+
+    if certs.is_empty() {
+        return Err(HiveError::Config(format!("No certificates found in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &PathBuf) -> Result<PrivateKeyDer<'static>> {
+    let pem = read_file(path)?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| HiveError::Config(format!("Invalid private key in {}: {}", path.display(), e)))?
+        .ok_or_else(|| HiveError::Config(format!("No private key found in {}", path.display())))
+}
+
+fn load_server_config(options: &TlsOptions) -> Result<ServerConfig> {
+    let provider: Arc<CryptoProvider> = Arc::new(ring::default_provider());
+    let tls_error = |e: tokio_rustls::rustls::Error| HiveError::Config(format!("TLS error: {}", e));
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+
+    let builder = match &options.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| HiveError::Config(format!("Invalid client CA: {}", e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(load_certs(&options.cert_path)?, load_private_key(&options.key_path)?)
+        .map_err(tls_error)?;
+
+    // gRPC runs over HTTP/2 only
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(config)
+}
@@ -1,11 +1,14 @@
-use hive_server::db::{create_pool, run_migrations, ApiKey, User};
+use hive_server::db::{create_pool, run_migrations, ApiKey, Connection, User};
 use hive_server::proto::auth_client::AuthClient;
 use hive_server::proto::auth_server::AuthServer;
-use hive_server::proto::ApiKeyRequest;
-use hive_server::api::AuthService;
+use hive_server::proto::connections_client::ConnectionsClient;
+use hive_server::proto::connections_server::ConnectionsServer;
+use hive_server::proto::{ApiKeyRequest, Empty};
+use hive_server::api::{ApiKeyAuthLayer, AuthService, ConnectionsService};
 use sqlx::PgPool;
 use std::net::SocketAddr;
 use tonic::transport::Server;
+use tonic::{Code, Request};
 
 async fn setup_test_db() -> PgPool {
     let database_url =
@@ -192,3 +195,111 @@ async fn test_api_key_revocation() {
     // Cleanup
     cleanup_test_data(&pool, test_username).await;
 }
+
+fn with_api_key<T>(message: T, key: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", key).parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn test_api_key_auth_layer() {
+    let pool = setup_test_db().await;
+    let test_username = "test_user_auth_layer";
+    let other_username = "test_user_auth_layer_other";
+
+    cleanup_test_data(&pool, test_username).await;
+    cleanup_test_data(&pool, other_username).await;
+
+    let user = User::create(&pool, test_username)
+        .await
+        .expect("Failed to create user");
+    let other = User::create(&pool, other_username)
+        .await
+        .expect("Failed to create user");
+
+    Connection::create(&pool, user.id, "mine", "localhost", 22, "me", None, None)
+        .await
+        .expect("Failed to create connection");
+    Connection::create(&pool, other.id, "theirs", "localhost", 22, "them", None, None)
+        .await
+        .expect("Failed to create connection");
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "layer-test", &raw_key)
+        .await
+        .expect("Failed to create API key");
+
+    let revoked_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "layer-revoked", &revoked_key)
+        .await
+        .expect("Failed to create API key");
+    ApiKey::revoke(&pool, &revoked_key)
+        .await
+        .expect("Revocation failed");
+
+    let addr: SocketAddr = "[::1]:50053".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_handle = tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(AuthServer::new(AuthService::new(server_pool.clone())))
+            .add_service(ConnectionsServer::new(ConnectionsService::new(server_pool)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let mut connections = ConnectionsClient::connect("http://[::1]:50053")
+        .await
+        .expect("Failed to connect to gRPC server");
+
+    // Valid key only sees the key owner's connections
+    let response = connections
+        .list(with_api_key(Empty {}, &raw_key))
+        .await
+        .expect("Authenticated list should succeed")
+        .into_inner();
+    assert_eq!(response.connections.len(), 1);
+    assert_eq!(response.connections[0].name, "mine");
+
+    // Missing key
+    let status = connections.list(Request::new(Empty {})).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // Spoofed user header without a key is not trusted
+    let mut spoofed = Request::new(Empty {});
+    spoofed
+        .metadata_mut()
+        .insert("x-user-id", other.id.to_string().parse().unwrap());
+    let status = connections.list(spoofed).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // Revoked key
+    let status = connections
+        .list(with_api_key(Empty {}, &revoked_key))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // Auth service stays reachable without a key
+    let mut auth = AuthClient::connect("http://[::1]:50053")
+        .await
+        .expect("Failed to connect to gRPC server");
+    let response = auth
+        .validate_api_key(ApiKeyRequest {
+            api_key: raw_key.clone(),
+        })
+        .await
+        .expect("Auth service should not require a key")
+        .into_inner();
+    assert!(response.valid);
+
+    cleanup_test_data(&pool, test_username).await;
+    cleanup_test_data(&pool, other_username).await;
+    server_handle.abort();
+}
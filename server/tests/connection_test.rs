@@ -1,5 +1,15 @@
-use hive_server::db::{create_pool, run_migrations, Connection, Session, User};
+use hive_server::api::{ApiKeyAuthLayer, ConnectionsService};
+use hive_server::db::{
+    create_pool, run_migrations, ApiKey, Connection, ConnectionVariable, Session, User,
+};
+use hive_server::proto::connections_client::ConnectionsClient;
+use hive_server::proto::connections_server::ConnectionsServer;
+use hive_server::proto::{CreateConnectionRequest, UpdateConnectionRequest};
 use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tonic::transport::Server;
+use tonic::{Code, Request};
 
 async fn setup_test_db() -> PgPool {
     let database_url =
@@ -195,6 +205,42 @@ async fn test_session_crud() {
     cleanup_test_user(&pool, test_username).await;
 }
 
+#[tokio::test]
+async fn test_session_status_transitions() {
+    let pool = setup_test_db().await;
+    let test_username = "test_user_session_transitions";
+    cleanup_test_user(&pool, test_username).await;
+
+    let user = User::create(&pool, test_username).await.unwrap();
+    let conn = Connection::create(
+        &pool, user.id, "Test Server", "localhost", 2222, "testuser", None, None,
+    )
+    .await
+    .unwrap();
+    let session = Session::create(&pool, user.id, conn.id).await.unwrap();
+
+    let suspended = Session::transition(&pool, session.id, "suspended").await.unwrap();
+    assert_eq!(suspended.status, "suspended");
+    assert!(Session::list_active_for_user(&pool, user.id).await.unwrap().is_empty());
+    let err = Session::transition(&pool, session.id, "suspended").await.unwrap_err();
+    assert!(err.to_string().contains("from suspended to suspended"), "{}", err);
+
+    let resumed = Session::transition(&pool, session.id, "active").await.unwrap();
+    assert_eq!(resumed.status, "active");
+
+    // Closed is final, and closing again changes nothing
+    assert!(Session::close(&pool, session.id).await.unwrap());
+    assert!(!Session::close(&pool, session.id).await.unwrap());
+    assert!(!Session::record_exit(&pool, session.id, Some(1), None).await.unwrap());
+    for status in ["active", "suspended", "lost"] {
+        assert!(Session::transition(&pool, session.id, status).await.is_err());
+    }
+    let closed = Session::find_by_id(&pool, session.id).await.unwrap().unwrap();
+    assert_eq!((closed.status.as_str(), closed.exit_code), ("closed", None));
+
+    cleanup_test_user(&pool, test_username).await;
+}
+
 #[tokio::test]
 async fn test_multiple_connections() {
     let pool = setup_test_db().await;
@@ -234,3 +280,248 @@ async fn test_multiple_connections() {
     // Cleanup
     cleanup_test_user(&pool, test_username).await;
 }
+
+#[tokio::test]
+async fn test_connection_tags() {
+    let pool = setup_test_db().await;
+    let test_username = "test_user_conn_tags";
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+
+    let user = User::create(&pool, test_username)
+        .await
+        .expect("Failed to create user");
+
+    let mut ids = Vec::new();
+    for (name, tags) in [("web1", vec!["web", "prod"]), ("web2", vec!["web"]), ("db", vec![])] {
+        let conn = Connection::create(&pool, user.id, name, "example.com", 22, "user", None, None)
+            .await
+            .expect("Failed to create connection");
+        assert!(conn.tags.is_empty());
+
+        let tags: Vec<String> = tags.into_iter().map(String::from).collect();
+        let conn = Connection::set_tags(&pool, conn.id, &tags)
+            .await
+            .expect("Failed to set tags")
+            .expect("Connection not found");
+        assert_eq!(conn.tags, tags);
+        ids.push(conn.id);
+    }
+
+    // Tag lookups are by exact tag, sorted by name
+    let web = Connection::list_by_tag(&pool, user.id, "web").await.unwrap();
+    let names: Vec<_> = web.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["web1", "web2"]);
+    assert_eq!(Connection::list_by_tag(&pool, user.id, "prod").await.unwrap().len(), 1);
+    assert!(Connection::list_by_tag(&pool, user.id, "we").await.unwrap().is_empty());
+
+    // Setting tags replaces them
+    Connection::set_tags(&pool, ids[0], &[]).await.unwrap();
+    assert!(Connection::list_by_tag(&pool, user.id, "prod").await.unwrap().is_empty());
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+}
+
+#[tokio::test]
+async fn test_connection_startup_options() {
+    let pool = setup_test_db().await;
+    let test_username = "test_user_conn_startup";
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+
+    let user = User::create(&pool, test_username)
+        .await
+        .expect("Failed to create user");
+    let startup = Some("cd ${dir}");
+    let conn = Connection::create(&pool, user.id, "app", "example.com", 22, "user", None, startup)
+        .await
+        .expect("Failed to create connection");
+    assert_eq!(conn.startup_mode, "typed");
+    assert!(conn.startup_prompt.is_none());
+
+    let conn = Connection::set_startup(&pool, conn.id, "prompt", Some(r"\$ "))
+        .await
+        .expect("Failed to set startup options")
+        .expect("Connection not found");
+    assert_eq!(conn.startup_mode, "prompt");
+    assert_eq!(conn.startup_prompt.as_deref(), Some(r"\$ "));
+
+    // Unknown modes are rejected by the database too
+    assert!(Connection::set_startup(&pool, conn.id, "later", None).await.is_err());
+
+    let variables = vec![
+        ("dir".to_string(), "/srv/app".to_string()),
+        ("env".to_string(), "prod".to_string()),
+    ];
+    let stored = ConnectionVariable::replace_for_connection(&pool, conn.id, &variables)
+        .await
+        .expect("Failed to set variables");
+    assert_eq!(stored.len(), 2);
+
+    // Replacing drops variables that are no longer given
+    ConnectionVariable::replace_for_connection(&pool, conn.id, &variables[1..])
+        .await
+        .expect("Failed to set variables");
+    let stored = ConnectionVariable::list_for_connection(&pool, conn.id).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!((stored[0].name.as_str(), stored[0].value.as_str()), ("env", "prod"));
+
+    // Variables go with their connection
+    Connection::delete(&pool, conn.id).await.unwrap();
+    assert!(ConnectionVariable::list_for_connection(&pool, conn.id).await.unwrap().is_empty());
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+}
+
+fn with_api_key<T>(message: T, key: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", key).parse().unwrap());
+    request
+}
+
+fn map<V: Clone>(entries: &[(&str, V)]) -> HashMap<String, V> {
+    entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+}
+
+#[tokio::test]
+async fn test_connection_terminal_settings_api() {
+    let pool = setup_test_db().await;
+    let test_username = "test_user_conn_terminal";
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+
+    let user = User::create(&pool, test_username)
+        .await
+        .expect("Failed to create user");
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "terminal-settings", &raw_key)
+        .await
+        .expect("Failed to create API key");
+
+    let addr: SocketAddr = "[::1]:50065".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_handle = tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(ConnectionsServer::new(ConnectionsService::new(server_pool)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let mut client = ConnectionsClient::connect("http://[::1]:50065")
+        .await
+        .expect("Failed to connect to gRPC server");
+
+    let created = client
+        .create(with_api_key(
+            CreateConnectionRequest {
+                name: "box".into(),
+                host: "example.com".into(),
+                port: 22,
+                username: "user".into(),
+                term_type: "vt100".into(),
+                env: map(&[("LANG", "en_US.UTF-8".to_string())]),
+                pty_modes: map(&[("ECHO", 0), ("VERASE", 127)]),
+                variables: map(&[("dir", "/srv".to_string())]),
+                persistence: "tmux".into(),
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Create should succeed")
+        .into_inner();
+    assert_eq!(created.term_type, "vt100");
+    assert_eq!(created.startup_mode, "typed");
+    assert_eq!(created.env, map(&[("LANG", "en_US.UTF-8".to_string())]));
+    assert_eq!(created.pty_modes, map(&[("ECHO", 0), ("VERASE", 127)]));
+    assert_eq!(created.variables, map(&[("dir", "/srv".to_string())]));
+    assert_eq!(created.persistence, "tmux");
+
+    // Update replaces every setting; an empty TERM restores the default
+    let updated = client
+        .update(with_api_key(
+            UpdateConnectionRequest {
+                id: created.id.clone(),
+                name: "box".into(),
+                host: "example.com".into(),
+                port: 22,
+                username: "user".into(),
+                startup_command: "echo ready".into(),
+                startup_mode: "prompt".into(),
+                startup_prompt: "[$#] ".into(),
+                env: map(&[("LC_ALL", "C".to_string())]),
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Update should succeed")
+        .into_inner();
+    assert_eq!(updated.term_type, "");
+    assert_eq!(updated.startup_mode, "prompt");
+    assert_eq!(updated.startup_prompt, "[$#] ");
+    assert_eq!(updated.env, map(&[("LC_ALL", "C".to_string())]));
+    assert!(updated.pty_modes.is_empty());
+    assert!(updated.variables.is_empty());
+    assert_eq!(updated.persistence, "none");
+
+    let listed = client
+        .list(with_api_key(hive_server::proto::Empty {}, &raw_key))
+        .await
+        .expect("List should succeed")
+        .into_inner();
+    assert_eq!(listed.connections[0].env, updated.env);
+
+    // Invalid settings are rejected before anything is stored
+    let invalid = [
+        CreateConnectionRequest {
+            pty_modes: map(&[("NOPE", 1)]),
+            ..Default::default()
+        },
+        CreateConnectionRequest {
+            env: map(&[("BAD-NAME", "x".to_string())]),
+            ..Default::default()
+        },
+        CreateConnectionRequest {
+            term_type: "xterm 256".into(),
+            ..Default::default()
+        },
+        CreateConnectionRequest {
+            startup_mode: "prompt".into(),
+            startup_prompt: "(".into(),
+            ..Default::default()
+        },
+        CreateConnectionRequest {
+            persistence: "byobu".into(),
+            ..Default::default()
+        },
+        CreateConnectionRequest {
+            startup_mode: "later".into(),
+            ..Default::default()
+        },
+    ];
+    for request in invalid {
+        let status = client.create(with_api_key(request, &raw_key)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+    let listed = client
+        .list(with_api_key(hive_server::proto::Empty {}, &raw_key))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(listed.connections.len(), 1);
+
+    // Cleanup
+    cleanup_test_user(&pool, test_username).await;
+    server_handle.abort();
+}
@@ -0,0 +1,726 @@
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Streaming};
+
+use hive_server::api::{ApiKeyAuthLayer, FilesService};
+use hive_server::db::{
+    create_pool, run_migrations, ApiKey, Connection, Upload, UploadTransfer, User,
+};
+use hive_server::proto::files_client::FilesClient;
+use hive_server::proto::files_server::FilesServer;
+use hive_server::proto::{
+    upload_event, upload_request, ChmodRequest, DownloadRequest, ListDirRequest,
+    MkdirRequest, ReadFileRequest, RemoveRequest, RenameRequest, UploadChunk, UploadEvent,
+    UploadRequest, UploadStart, WriteFileRequest,
+};
+use hive_server::terminal::{ReaperPolicy, SessionManager, SessionOptions};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("filestest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+async fn create_test_connection(pool: &PgPool, user_id: Uuid) -> Connection {
+    let name = format!("filesconn_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    Connection::create(
+        pool,
+        user_id,
+        &name,
+        "localhost",
+        2222,
+        "testuser",
+        None,
+        None,
+    )
+    .await
+    .unwrap()
+}
+
+fn with_api_key<T>(message: T, api_key: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", api_key).parse().unwrap());
+    request
+}
+
+async fn start_server(pool: &PgPool, manager: Arc<SessionManager>, port: u16) -> String {
+    let addr: SocketAddr = format!("[::1]:{}", port).parse().unwrap();
+    let server_pool = pool.clone();
+    let files_service = FilesService::new(pool.clone(), manager);
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(FilesServer::new(files_service))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    format!("http://[::1]:{}", port)
+}
+
+async fn begin_upload(
+    client: &mut FilesClient<Channel>,
+    api_key: &str,
+    start: UploadStart,
+) -> Result<(mpsc::Sender<UploadRequest>, Streaming<UploadEvent>), tonic::Status> {
+    let (tx, rx) = mpsc::channel(16);
+    tx.send(UploadRequest {
+        payload: Some(upload_request::Payload::Start(start)),
+    })
+    .await
+    .unwrap();
+
+    let events = client
+        .upload(with_api_key(ReceiverStream::new(rx), api_key))
+        .await?
+        .into_inner();
+    Ok((tx, events))
+}
+
+async fn send_chunks(tx: &mpsc::Sender<UploadRequest>, data: &[u8], from: usize) {
+    for (i, chunk) in data[from..].chunks(CHUNK_SIZE).enumerate() {
+        tx.send(UploadRequest {
+            payload: Some(upload_request::Payload::Chunk(UploadChunk {
+                offset: (from + i * CHUNK_SIZE) as u64,
+                data: chunk.to_vec(),
+            })),
+        })
+        .await
+        .unwrap();
+    }
+}
+
+async fn next_event(
+    events: &mut Streaming<UploadEvent>,
+) -> Result<upload_event::Event, tonic::Status> {
+    tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("Timeout waiting for upload event")
+        .expect("Upload stream ended")
+        .map(|event| event.event.expect("Empty upload event"))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+async fn download(
+    client: &mut FilesClient<Channel>,
+    api_key: &str,
+    request: DownloadRequest,
+) -> (Vec<u64>, Vec<u8>, u64) {
+    let mut stream = client.download(with_api_key(request, api_key)).await.unwrap().into_inner();
+    let (mut offsets, mut data, mut file_size) = (Vec::new(), Vec::new(), 0);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        offsets.push(chunk.offset);
+        data.extend_from_slice(&chunk.data);
+        file_size = chunk.file_size;
+    }
+    (offsets, data, file_size)
+}
+
+#[tokio::test]
+async fn test_chunked_upload_resumes_after_disconnect() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "files-test", &raw_key).await.unwrap();
+
+    let upload_dir = tempfile::TempDir::new().unwrap();
+    let manager = Arc::new(
+        SessionManager::new(pool.clone()).with_upload_dir(upload_dir.path().to_str().unwrap()),
+    );
+    let url = start_server(&pool, manager.clone(), 50057).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let data: Vec<u8> = (0..5 * CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect();
+    let mut client = FilesClient::connect(url).await.unwrap();
+
+    // Send the first two chunks, then drop the stream
+    let (tx, mut events) = begin_upload(
+        &mut client,
+        &raw_key,
+        UploadStart {
+            session_id: session_id.to_string(),
+            filename: "big.log".into(),
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("Upload should start");
+
+    let upload_id = match next_event(&mut events).await.unwrap() {
+        upload_event::Event::Started(started) => {
+            assert_eq!(started.offset, 0);
+            started.upload_id
+        }
+        other => panic!("Expected Started, got {:?}", other),
+    };
+
+    send_chunks(&tx, &data[..2 * CHUNK_SIZE], 0).await;
+    for expected in [CHUNK_SIZE, 2 * CHUNK_SIZE] {
+        match next_event(&mut events).await.unwrap() {
+            upload_event::Event::Progress(progress) => {
+                assert_eq!(progress.received, expected as u64);
+                assert_eq!(progress.size, data.len() as u64);
+            }
+            other => panic!("Expected Progress, got {:?}", other),
+        }
+    }
+    drop(tx);
+    drop(events);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Resuming continues from the bytes already written
+    let (tx, mut events) = begin_upload(
+        &mut client,
+        &raw_key,
+        UploadStart {
+            upload_id: upload_id.clone(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("Upload should resume");
+
+    match next_event(&mut events).await.unwrap() {
+        upload_event::Event::Started(started) => {
+            assert_eq!(started.upload_id, upload_id);
+            assert_eq!(started.offset, 2 * CHUNK_SIZE as u64);
+        }
+        other => panic!("Expected Started, got {:?}", other),
+    }
+
+    send_chunks(&tx, &data, 2 * CHUNK_SIZE).await;
+    let completed = loop {
+        match next_event(&mut events).await.unwrap() {
+            upload_event::Event::Progress(_) => {}
+            upload_event::Event::Completed(completed) => break completed,
+            other => panic!("Unexpected event {:?}", other),
+        }
+    };
+
+    assert_eq!(completed.filename, "big.log");
+    assert_eq!(completed.size, data.len() as u64);
+    assert_eq!(completed.sha256, sha256_hex(&data));
+    assert_eq!(std::fs::read(&completed.path).unwrap(), data);
+
+    // Only the finished file is left behind
+    let names: Vec<_> = std::fs::read_dir(upload_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, vec!["big.log".to_string()]);
+
+    let uploads = Upload::list_for_session(&pool, session_id).await.unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].path, completed.path);
+
+    // A finished upload cannot be resumed
+    let err = begin_upload(
+        &mut client,
+        &raw_key,
+        UploadStart {
+            upload_id,
+            ..Default::default()
+        },
+    )
+    .await
+    .expect_err("Completed upload should not resume");
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_rejects_bad_checksum_and_other_users() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other_user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "files-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other_user.id, "files-test", &other_key).await.unwrap();
+
+    let upload_dir = tempfile::TempDir::new().unwrap();
+    let manager = Arc::new(
+        SessionManager::new(pool.clone()).with_upload_dir(upload_dir.path().to_str().unwrap()),
+    );
+    let url = start_server(&pool, manager.clone(), 50058).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let data = b"not what the checksum says".to_vec();
+    let mut client = FilesClient::connect(url).await.unwrap();
+    let start = UploadStart {
+        session_id: session_id.to_string(),
+        filename: "notes.txt".into(),
+        size: data.len() as u64,
+        sha256: sha256_hex(b"something else"),
+        ..Default::default()
+    };
+
+    // Another user can neither start an upload on the session nor resume this one
+    let err = begin_upload(&mut client, &other_key, start.clone())
+        .await
+        .expect_err("Other user should not upload to this session");
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let (tx, mut events) = begin_upload(&mut client, &raw_key, start)
+        .await
+        .expect("Upload should start");
+    let upload_id = match next_event(&mut events).await.unwrap() {
+        upload_event::Event::Started(started) => started.upload_id,
+        other => panic!("Expected Started, got {:?}", other),
+    };
+
+    let err = begin_upload(
+        &mut client,
+        &other_key,
+        UploadStart {
+            upload_id,
+            ..Default::default()
+        },
+    )
+    .await
+    .expect_err("Other user should not resume this upload");
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // The checksum is verified before the file is put in place
+    send_chunks(&tx, &data, 0).await;
+    let err = loop {
+        match next_event(&mut events).await {
+            Ok(upload_event::Event::Progress(_)) => {}
+            Ok(other) => panic!("Unexpected event {:?}", other),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(err.code(), Code::DataLoss);
+
+    assert_eq!(std::fs::read_dir(upload_dir.path()).unwrap().count(), 0);
+    assert!(Upload::list_for_session(&pool, session_id).await.unwrap().is_empty());
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sftp_browser_operations() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other_user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "files-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other_user.id, "files-test", &other_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let url = start_server(&pool, manager.clone(), 50059).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let session_id = session_id.to_string();
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path().to_str().unwrap().to_string();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+    std::fs::write(dir.path().join("app.log"), &data).unwrap();
+
+    let mut client = FilesClient::connect(url).await.unwrap();
+
+    // Mkdir, with and without parents
+    client
+        .mkdir(with_api_key(
+            MkdirRequest {
+                session_id: session_id.clone(),
+                path: format!("{}/a/b", root),
+                parents: true,
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Mkdir with parents should succeed");
+    let err = client
+        .mkdir(with_api_key(
+            MkdirRequest {
+                session_id: session_id.clone(),
+                path: format!("{}/missing/c", root),
+                parents: false,
+            },
+            &raw_key,
+        ))
+        .await
+        .expect_err("Mkdir without parents should fail");
+    assert_eq!(err.code(), Code::NotFound);
+
+    // ListDir reports names, types and sizes
+    let listing = client
+        .list_dir(with_api_key(
+            ListDirRequest {
+                session_id: session_id.clone(),
+                path: root.clone(),
+            },
+            &raw_key,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(listing.path, std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap());
+    let names: Vec<_> = listing.entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "app.log"]);
+    assert!(listing.entries[0].is_dir);
+    assert!(!listing.entries[1].is_dir);
+    assert_eq!(listing.entries[1].size, data.len() as u64);
+    assert_eq!(listing.entries[1].path, format!("{}/app.log", listing.path));
+    assert!(!listing.entries[1].modified.is_empty());
+
+    // Download the whole file, then a range
+    let path = format!("{}/app.log", root);
+    let (offsets, whole, file_size) = download(
+        &mut client,
+        &raw_key,
+        DownloadRequest {
+            session_id: session_id.clone(),
+            path: path.clone(),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(whole, data);
+    assert_eq!(file_size, data.len() as u64);
+    assert_eq!(offsets[0], 0);
+    assert!(offsets.len() > 1);
+
+    let (offsets, range, _) = download(
+        &mut client,
+        &raw_key,
+        DownloadRequest {
+            session_id: session_id.clone(),
+            path: path.clone(),
+            offset: 150_000,
+            length: 100_000,
+        },
+    )
+    .await;
+    assert_eq!(offsets[0], 150_000);
+    assert_eq!(range, &data[150_000..]);
+
+    let err = client
+        .download(with_api_key(
+            DownloadRequest {
+                session_id: session_id.clone(),
+                path: path.clone(),
+                offset: data.len() as u64 + 1,
+                length: 0,
+            },
+            &raw_key,
+        ))
+        .await
+        .expect_err("Offset past the end should fail");
+    assert_eq!(err.code(), Code::OutOfRange);
+
+    // Chmod and Rename
+    client
+        .chmod(with_api_key(
+            ChmodRequest {
+                session_id: session_id.clone(),
+                path: path.clone(),
+                mode: 0o600,
+            },
+            &raw_key,
+        ))
+        .await
+        .unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let renamed = format!("{}/a/b/app.log", root);
+    client
+        .rename(with_api_key(
+            RenameRequest {
+                session_id: session_id.clone(),
+                from: path.clone(),
+                to: renamed.clone(),
+            },
+            &raw_key,
+        ))
+        .await
+        .unwrap();
+    assert!(!std::path::Path::new(&path).exists());
+    assert_eq!(std::fs::read(&renamed).unwrap(), data);
+
+    // Remove deletes files and empty directories only
+    let err = client
+        .remove(with_api_key(
+            RemoveRequest {
+                session_id: session_id.clone(),
+                path: format!("{}/a/b", root),
+            },
+            &raw_key,
+        ))
+        .await
+        .expect_err("Non-empty directory should not be removed");
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    for path in [renamed, format!("{}/a/b", root)] {
+        client
+            .remove(with_api_key(
+                RemoveRequest {
+                    session_id: session_id.clone(),
+                    path: path.clone(),
+                },
+                &raw_key,
+            ))
+            .await
+            .unwrap();
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    // Another user cannot browse this session
+    let err = client
+        .list_dir(with_api_key(
+            ListDirRequest {
+                session_id: session_id.clone(),
+                path: root.clone(),
+            },
+            &other_key,
+        ))
+        .await
+        .expect_err("Other user should not list files");
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    manager.close_session(session_id.parse().unwrap()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_edit_file_with_version_check() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "files-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let url = start_server(&pool, manager.clone(), 50060).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let session_id = session_id.to_string();
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = format!("{}/app.conf", dir.path().to_str().unwrap());
+    std::fs::write(&path, "port = 80\n").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+    let mut client = FilesClient::connect(url).await.unwrap();
+    let read = |client: &mut FilesClient<Channel>| {
+        let request = with_api_key(
+            ReadFileRequest {
+                session_id: session_id.clone(),
+                path: path.clone(),
+            },
+            &raw_key,
+        );
+        let mut client = client.clone();
+        async move { client.read(request).await.map(|r| r.into_inner()) }
+    };
+    let write = |client: &mut FilesClient<Channel>, content: &str, version: &str| {
+        let request = with_api_key(
+            WriteFileRequest {
+                session_id: session_id.clone(),
+                path: path.clone(),
+                content: content.as_bytes().to_vec(),
+                version: version.to_string(),
+            },
+            &raw_key,
+        );
+        let mut client = client.clone();
+        async move { client.write(request).await.map(|r| r.into_inner()) }
+    };
+
+    let original = read(&mut client).await.unwrap();
+    assert_eq!(original.content, b"port = 80\n");
+    assert!(!original.version.is_empty());
+
+    // Writing against the version that was read replaces the file
+    let written = write(&mut client, "port = 8080\n", &original.version).await.unwrap();
+    assert!(written.conflict.is_none());
+    assert_ne!(written.version, original.version);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 8080\n");
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert_eq!(read(&mut client).await.unwrap().version, written.version);
+
+    // The file is replaced in one step, so it never goes missing while being rewritten
+    let watching = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let watcher = std::thread::spawn({
+        let (watching, path) = (watching.clone(), path.clone());
+        move || {
+            let mut missing = 0;
+            while watching.load(std::sync::atomic::Ordering::Relaxed) {
+                if !std::path::Path::new(&path).exists() {
+                    missing += 1;
+                }
+            }
+            missing
+        }
+    });
+    let mut written = written;
+    for i in 0..20 {
+        written = write(&mut client, &format!("port = {}\n", i), &written.version).await.unwrap();
+    }
+    watching.store(false, std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(watcher.join().unwrap(), 0);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    // A stale version is refused with the current content attached
+    std::fs::write(&path, "port = 9090\n").unwrap();
+    let refused = write(&mut client, "port = 1\n", &written.version).await.unwrap();
+    assert!(refused.version.is_empty());
+    let current = refused.conflict.expect("Stale write should conflict");
+    assert_eq!(current.content, b"port = 9090\n");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 9090\n");
+
+    // The current version from the conflict can be used to retry
+    write(&mut client, "port = 1\n", &current.version).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 1\n");
+
+    // An empty version only creates files
+    let refused = write(&mut client, "x", "").await.unwrap();
+    assert_eq!(refused.conflict.expect("Existing file should conflict").content, b"port = 1\n");
+    std::fs::remove_file(&path).unwrap();
+    let err = read(&mut client).await.expect_err("Missing file should not be read");
+    assert_eq!(err.code(), Code::NotFound);
+    write(&mut client, "new\n", "").await.unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+
+    manager.close_session(session_id.parse().unwrap()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_abandoned_uploads_expire() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "files-test", &raw_key).await.unwrap();
+
+    let upload_dir = tempfile::TempDir::new().unwrap();
+    let manager = Arc::new(
+        SessionManager::new(pool.clone()).with_upload_dir(upload_dir.path().to_str().unwrap()),
+    );
+    let url = start_server(&pool, manager.clone(), 50072).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let data = vec![7u8; 2 * CHUNK_SIZE];
+    let mut client = FilesClient::connect(url).await.unwrap();
+    let start_upload = |filename: &str| {
+        let start = UploadStart {
+            session_id: session_id.to_string(),
+            filename: filename.into(),
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+            ..Default::default()
+        };
+        let mut client = client.clone();
+        let raw_key = raw_key.clone();
+        async move {
+            let (tx, mut events) = begin_upload(&mut client, &raw_key, start).await.unwrap();
+            let upload_id = match next_event(&mut events).await.unwrap() {
+                upload_event::Event::Started(started) => started.upload_id,
+                other => panic!("Expected Started, got {:?}", other),
+            };
+            (upload_id.parse::<Uuid>().unwrap(), tx, events)
+        }
+    };
+
+    // One upload is abandoned halfway, another is still in use
+    let (abandoned, tx, mut events) = start_upload("abandoned.bin").await;
+    send_chunks(&tx, &data[..CHUNK_SIZE], 0).await;
+    next_event(&mut events).await.unwrap();
+    drop((tx, events));
+    let (active, _active_tx, _active_events) = start_upload("active.bin").await;
+
+    sqlx::query("UPDATE upload_transfers SET updated_at = NOW() - INTERVAL '2 days' WHERE id = $1")
+        .bind(abandoned)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let policy = ReaperPolicy {
+        expire_uploads_after: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    assert!(policy.apply(&manager).await.expired_uploads >= 1);
+
+    let transfer = UploadTransfer::find_by_id(&pool, abandoned).await.unwrap().unwrap();
+    assert_eq!(transfer.status, "failed");
+    assert!(!std::path::Path::new(&transfer.partial_path).exists());
+    let err = begin_upload(
+        &mut client,
+        &raw_key,
+        UploadStart {
+            upload_id: abandoned.to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect_err("Expired upload should not resume");
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    let transfer = UploadTransfer::find_by_id(&pool, active).await.unwrap().unwrap();
+    assert_eq!(transfer.status, "in_progress");
+    assert!(std::path::Path::new(&transfer.partial_path).exists());
+
+    // Closing the session ends its unfinished uploads too
+    manager.close_session(session_id).await.unwrap();
+    let transfer = UploadTransfer::find_by_id(&pool, active).await.unwrap().unwrap();
+    assert_eq!(transfer.status, "failed");
+    assert_eq!(std::fs::read_dir(upload_dir.path()).unwrap().count(), 0);
+}
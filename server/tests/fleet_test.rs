@@ -0,0 +1,277 @@
+use sqlx::PgPool;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tonic::Code;
+
+use hive_server::api::{ApiKeyAuthLayer, FleetService};
+use hive_server::db::{create_pool, run_migrations, ApiKey, Connection, User};
+use hive_server::proto::fleet_client::FleetClient;
+use hive_server::proto::fleet_server::FleetServer;
+use hive_server::proto::{
+    fleet_event, Empty, FleetEvent, FleetRunRequest, GetFleetRunRequest,
+};
+use hive_server::terminal::SessionManager;
+
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("fleettest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+async fn create_tagged_connection(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    port: i32,
+    tags: &[&str],
+) -> Connection {
+    let connection = Connection::create(
+        pool,
+        user_id,
+        name,
+        "localhost",
+        port,
+        "testuser",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+    Connection::set_tags(pool, connection.id, &tags).await.unwrap().unwrap()
+}
+
+fn with_api_key<T>(message: T, api_key: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", api_key).parse().unwrap());
+    request
+}
+
+async fn collect_events(stream: &mut tonic::Streaming<FleetEvent>) -> Vec<FleetEvent> {
+    let mut events = Vec::new();
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(event) = stream.next().await {
+            events.push(event.unwrap());
+        }
+    })
+    .await
+    .expect("Timeout waiting for fleet run");
+    events
+}
+
+#[tokio::test]
+async fn test_fleet_run_by_tag_records_results() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other_user = create_test_user(&pool).await;
+    let tag = format!("web-{}", Uuid::new_v4());
+
+    let web1 = create_tagged_connection(&pool, user.id, "web1", 2222, &[&tag]).await;
+    let web2 = create_tagged_connection(&pool, user.id, "web2", 2222, &[&tag]).await;
+    // Nothing listens on port 1, so this host fails to connect
+    let down = create_tagged_connection(&pool, user.id, "web3", 1, &[&tag]).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "fleet-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other_user.id, "fleet-test", &other_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50062".parse().unwrap();
+    let server_pool = pool.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(FleetServer::new(FleetService::new(server_pool, manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = FleetClient::connect("http://[::1]:50062").await.unwrap();
+    let mut stream = client
+        .run(with_api_key(
+            FleetRunRequest {
+                tag: tag.clone(),
+                command: "echo hello; echo warn >&2; exit 0".into(),
+                password: "testpass".into(),
+                concurrency: 2,
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Fleet run should start")
+        .into_inner();
+    let events = collect_events(&mut stream).await;
+
+    // The run starts with every host pending and ends with a summary
+    let run_id = events[0].run_id.clone();
+    let started = match &events[0].event {
+        Some(fleet_event::Event::Started(run)) => run.clone(),
+        other => panic!("Expected Started, got {:?}", other),
+    };
+    let names: Vec<_> = started.hosts.iter().map(|h| h.connection_name.as_str()).collect();
+    assert_eq!(names, vec!["web1", "web2", "web3"]);
+    assert!(started.hosts.iter().all(|h| h.status == "pending"));
+
+    let finished = match &events.last().unwrap().event {
+        Some(fleet_event::Event::Finished(run)) => run.clone(),
+        other => panic!("Expected Finished, got {:?}", other),
+    };
+    assert_eq!(finished.status, "complete");
+    let statuses: Vec<_> = finished.hosts.iter().map(|h| h.status.as_str()).collect();
+    assert_eq!(statuses, vec!["succeeded", "succeeded", "error"]);
+    assert!(!finished.hosts[2].error.is_empty());
+
+    // Every host reports start, output and result, tagged with its connection
+    for connection in [&web1, &web2] {
+        let id = connection.id.to_string();
+        let host_events: Vec<_> = events.iter().filter(|e| e.connection_id == id).collect();
+        assert!(matches!(host_events[0].event, Some(fleet_event::Event::HostStarted(_))));
+        assert!(host_events
+            .iter()
+            .any(|e| e.event == Some(fleet_event::Event::Stdout(b"hello\n".to_vec()))));
+        assert!(host_events
+            .iter()
+            .any(|e| e.event == Some(fleet_event::Event::Stderr(b"warn\n".to_vec()))));
+        match &host_events.last().unwrap().event {
+            Some(fleet_event::Event::HostFinished(result)) => {
+                assert_eq!(result.exit_code, Some(0));
+                assert!(result.stdout.is_empty());
+            }
+            other => panic!("Expected HostFinished, got {:?}", other),
+        }
+    }
+    let down_id = down.id.to_string();
+    assert!(events.iter().any(|e| e.connection_id == down_id
+        && matches!(&e.event, Some(fleet_event::Event::HostFinished(r)) if r.status == "error")));
+
+    // The stored run keeps each host's output for later review
+    let stored = client
+        .get_run(with_api_key(GetFleetRunRequest { id: run_id.clone() }, &raw_key))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(stored.command, "echo hello; echo warn >&2; exit 0");
+    assert_eq!(stored.hosts[0].stdout, b"hello\n");
+    assert_eq!(stored.hosts[0].stderr, b"warn\n");
+    assert!(!stored.hosts[0].finished_at.is_empty());
+    assert!(stored.hosts[2].stdout.is_empty());
+
+    let runs = client
+        .list_runs(with_api_key(Empty {}, &raw_key))
+        .await
+        .unwrap()
+        .into_inner()
+        .runs;
+    assert_eq!(runs[0].id, run_id);
+    assert!(runs[0].hosts[0].stdout.is_empty());
+
+    // Runs and connections belong to their owner
+    let err = client
+        .get_run(with_api_key(GetFleetRunRequest { id: run_id }, &other_key))
+        .await
+        .expect_err("Other user should not see the run");
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let err = client
+        .run(with_api_key(
+            FleetRunRequest {
+                connection_ids: vec![web1.id.to_string()],
+                command: "true".into(),
+                ..Default::default()
+            },
+            &other_key,
+        ))
+        .await
+        .expect_err("Other user should not run on these connections");
+    assert_eq!(err.code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_fleet_run_by_ids_reports_failures_and_timeouts() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let first = create_tagged_connection(&pool, user.id, "first", 2222, &[]).await;
+    let second = create_tagged_connection(&pool, user.id, "second", 2222, &[]).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "fleet-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50063".parse().unwrap();
+    let server_pool = pool.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(FleetServer::new(FleetService::new(server_pool, manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = FleetClient::connect("http://[::1]:50063").await.unwrap();
+    let run = |command: &str, timeout_seconds: u32| {
+        with_api_key(
+            FleetRunRequest {
+                // Duplicates run once
+                connection_ids: vec![
+                    first.id.to_string(),
+                    second.id.to_string(),
+                    first.id.to_string(),
+                ],
+                command: command.into(),
+                password: "testpass".into(),
+                timeout_seconds,
+                ..Default::default()
+            },
+            &raw_key,
+        )
+    };
+
+    let mut stream = client.run(run("exit 2", 0)).await.unwrap().into_inner();
+    let events = collect_events(&mut stream).await;
+    let finished = match &events.last().unwrap().event {
+        Some(fleet_event::Event::Finished(run)) => run.clone(),
+        other => panic!("Expected Finished, got {:?}", other),
+    };
+    assert_eq!(finished.hosts.len(), 2);
+    assert!(finished.hosts.iter().all(|h| h.status == "failed" && h.exit_code == Some(2)));
+
+    let mut stream = client.run(run("sleep 30", 1)).await.unwrap().into_inner();
+    let events = collect_events(&mut stream).await;
+    let finished = match &events.last().unwrap().event {
+        Some(fleet_event::Event::Finished(run)) => run.clone(),
+        other => panic!("Expected Finished, got {:?}", other),
+    };
+    assert!(finished.hosts.iter().all(|h| h.status == "timed_out"));
+
+    let err = client
+        .run(with_api_key(
+            FleetRunRequest {
+                command: "true".into(),
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect_err("A run needs targets");
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
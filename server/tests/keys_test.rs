@@ -0,0 +1,167 @@
+use sqlx::PgPool;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use hive_server::crypto::MasterKey;
+use hive_server::db::{create_pool, run_migrations, Connection, SshKey, User};
+use hive_server::ssh::{openssh_public_key, parse_private_key, parse_public_key};
+use hive_server::terminal::{SessionManager, SessionOptions};
+
+// Authorized for testuser on the SSH test container (see docker-compose.yml)
+const TEST_PRIVATE_KEY: &str = include_str!("fixtures/test_ed25519");
+const TEST_PUBLIC_KEY: &str = include_str!("fixtures/test_ed25519.pub");
+
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("keytest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+fn test_master_key() -> MasterKey {
+    MasterKey::parse(&hex::encode([7u8; 32])).unwrap()
+}
+
+#[test]
+fn test_master_key_seal_and_open() {
+    let master_key = test_master_key();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    assert!(sealed.starts_with("v1:"));
+    assert!(!sealed.contains("OPENSSH PRIVATE KEY"));
+
+    // Fresh nonce per seal
+    assert_ne!(sealed, master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap());
+
+    let opened = master_key.open(&sealed).unwrap();
+    assert_eq!(opened, TEST_PRIVATE_KEY.as_bytes());
+
+    // Same key in base64 form opens it too
+    let base64_key = MasterKey::parse("BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=").unwrap();
+    assert_eq!(base64_key.open(&sealed).unwrap(), TEST_PRIVATE_KEY.as_bytes());
+
+    // Wrong key and tampered data are rejected
+    let other = MasterKey::parse(&hex::encode([8u8; 32])).unwrap();
+    assert!(other.open(&sealed).is_err());
+    assert!(master_key.open(&sealed.replace("v1:", "v1:AA")).is_err());
+
+    // Keys of the wrong length are rejected
+    assert!(MasterKey::parse("deadbeef").is_err());
+}
+
+#[test]
+fn test_parse_ssh_keys() {
+    let key_pair = parse_private_key(TEST_PRIVATE_KEY).unwrap();
+    let public_key = key_pair.clone_public_key().unwrap();
+
+    assert_eq!(parse_public_key(TEST_PUBLIC_KEY).unwrap(), public_key);
+    assert_eq!(
+        format!("{} hive-test", openssh_public_key(&public_key)),
+        TEST_PUBLIC_KEY.trim()
+    );
+
+    assert!(parse_private_key("not a key").is_err());
+    assert!(parse_public_key("").is_err());
+}
+
+#[tokio::test]
+async fn test_ssh_key_crud() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = test_master_key();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    let key = SshKey::create(&pool, user.id, "laptop", &sealed, TEST_PUBLIC_KEY.trim())
+        .await
+        .unwrap();
+    assert_eq!(key.name, "laptop");
+
+    let found = SshKey::find_by_id(&pool, key.id).await.unwrap().unwrap();
+    assert_eq!(master_key.open(&found.private_key_encrypted).unwrap(), TEST_PRIVATE_KEY.as_bytes());
+
+    let keys = SshKey::list_for_user(&pool, user.id).await.unwrap();
+    assert_eq!(keys.len(), 1);
+
+    // Deleting a key detaches it from connections
+    let connection = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(connection.ssh_key_id, Some(key.id));
+
+    assert!(SshKey::delete(&pool, key.id).await.unwrap());
+    assert!(SshKey::find_by_id(&pool, key.id).await.unwrap().is_none());
+
+    let connection = Connection::find_by_id(&pool, connection.id).await.unwrap().unwrap();
+    assert_eq!(connection.ssh_key_id, None);
+}
+
+#[tokio::test]
+async fn test_session_manager_publickey_auth() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = test_master_key();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    let key = SshKey::create(&pool, user.id, "test", &sealed, TEST_PUBLIC_KEY.trim())
+        .await
+        .unwrap();
+    let connection = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+
+    // Without the master key the stored key cannot be used
+    let manager = SessionManager::new(pool.clone());
+    let result = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "")
+        .await;
+    assert!(result.is_err(), "Expected failure without master key");
+
+    // With it, no password is needed
+    let manager = SessionManager::new(pool.clone()).with_master_key(master_key);
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "")
+        .await
+        .expect("Public key authentication failed");
+
+    let receive_result = tokio::time::timeout(Duration::from_secs(5), output_rx.recv()).await;
+    assert!(receive_result.is_ok(), "Timeout waiting for initial output");
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_session_manager_rejects_unauthorized_key() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = test_master_key();
+
+    // A valid key the server does not accept must not fall back to the password
+    let other = russh::keys::key::KeyPair::generate_ed25519();
+    let mut pem = Vec::new();
+    russh::keys::encode_pkcs8_pem(&other, &mut pem).unwrap();
+    let sealed = master_key.seal(&pem).unwrap();
+
+    let key = SshKey::create(&pool, user.id, "other", &sealed, "")
+        .await
+        .unwrap();
+    let connection = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+
+    let manager = SessionManager::new(pool.clone()).with_master_key(master_key);
+    let result = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await;
+    assert!(result.is_err(), "Expected unauthorized key to be rejected");
+}
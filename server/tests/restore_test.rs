@@ -0,0 +1,221 @@
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use hive_server::crypto::MasterKey;
+use hive_server::db::{
+    create_pool, run_migrations, Connection, FleetRun, FleetRunHost, Session, SshKey, User,
+};
+use hive_server::terminal::{ExecEvent, OutputChunk, SessionManager, SessionOptions};
+
+// Authorized for testuser on the SSH test container (see docker-compose.yml)
+const TEST_PRIVATE_KEY: &str = include_str!("fixtures/test_ed25519");
+const TEST_PUBLIC_KEY: &str = include_str!("fixtures/test_ed25519.pub");
+
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("restoretest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+fn test_master_key() -> MasterKey {
+    MasterKey::parse(&hex::encode([7u8; 32])).unwrap()
+}
+
+async fn wait_for_output(output_rx: &mut broadcast::Receiver<OutputChunk>, marker: &str) -> bool {
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    let mut output = String::new();
+    while std::time::Instant::now() < deadline {
+        match tokio::time::timeout(Duration::from_millis(100), output_rx.recv()).await {
+            Ok(Ok(chunk)) => {
+                output.push_str(&String::from_utf8_lossy(&chunk));
+                if output.contains(marker) {
+                    return true;
+                }
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(_)) => return false,
+            Err(_) => continue,
+        }
+    }
+    false
+}
+
+/// Ask the remote host whether the tmux session holding a Hive session's shell still exists
+async fn tmux_has_session(
+    manager: &SessionManager,
+    user_id: Uuid,
+    connection_id: Uuid,
+    session_id: Uuid,
+) -> bool {
+    let command = format!("tmux has-session -t hive-{}", session_id);
+    let mut process = manager.exec(user_id, connection_id, "", &command).await.unwrap();
+    while let Some(event) = process.next().await {
+        if let ExecEvent::Exit(exit) = event {
+            return exit.exit_code == Some(0);
+        }
+    }
+    false
+}
+
+// Restoring looks at every active session in the database, so this is the only test here
+// that creates sessions
+#[tokio::test]
+async fn test_restore_sessions_after_restart() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = test_master_key();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    let key = SshKey::create(&pool, user.id, "test", &sealed, TEST_PUBLIC_KEY.trim())
+        .await
+        .unwrap();
+    let keyed = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+    Connection::set_persistence(&pool, keyed.id, "tmux").await.unwrap();
+    let plain = Connection::create(
+        &pool, user.id, "plain", "localhost", 2222, "testuser", None, None,
+    )
+    .await
+    .unwrap();
+
+    let before = SessionManager::new(pool.clone()).with_master_key(master_key.clone());
+    let (plain_id, _) = before
+        .create_session(user.id, plain.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let (kept_id, mut output_rx) = before
+        .create_session(user.id, keyed.id, SessionOptions::new(100, 30), "")
+        .await
+        .unwrap();
+
+    // Leave state behind in the shell running inside tmux
+    before.send_input(kept_id, user.id, b"HIVE_MARK=still_here\n").await.unwrap();
+    before.send_input(kept_id, user.id, b"echo set_$HIVE_MARK\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "set_still_here").await, "Shell never ran");
+    before.flush_scrollback().await;
+    let offset_before = before
+        .get_session(kept_id)
+        .await
+        .unwrap()
+        .lock()
+        .await
+        .output_offset();
+
+    // A restart drops every connection but leaves the rows active
+    before.disconnect_all().await;
+    drop(before);
+    let after = SessionManager::new(pool.clone()).with_master_key(master_key);
+    assert!(
+        tmux_has_session(&after, user.id, keyed.id, kept_id).await,
+        "tmux session ended with the connection"
+    );
+    let kept = Session::find_by_id(&pool, kept_id).await.unwrap().unwrap();
+    assert_eq!(kept.status, "active");
+
+    // A host that accepts connections but never answers is given up on, not waited for
+    let blackhole = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let blackhole_port = blackhole.local_addr().unwrap().port() as i32;
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = blackhole.accept().await {
+            held.push(socket);
+        }
+    });
+    let silent = Connection::create(
+        &pool, user.id, "silent", "127.0.0.1", blackhole_port, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+    Connection::set_persistence(&pool, silent.id, "tmux").await.unwrap();
+    let silent_session = Session::create(&pool, user.id, silent.id).await.unwrap();
+
+    let started = std::time::Instant::now();
+    let summary = after.restore_sessions().await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(20), "Restore waited on the silent host");
+    assert!(summary.restored >= 1, "Nothing restored: {:?}", summary);
+    assert!(summary.lost >= 2, "Nothing lost: {:?}", summary);
+    let silent_session = Session::find_by_id(&pool, silent_session.id).await.unwrap().unwrap();
+    assert_eq!(silent_session.status, "lost");
+
+    // Without a multiplexer the shell is gone for good
+    let plain_session = Session::find_by_id(&pool, plain_id).await.unwrap().unwrap();
+    assert_eq!(plain_session.status, "lost");
+    assert!(after.attach_from(plain_id, user.id, None).await.is_err());
+
+    // The tmux session is reattached with its shell state, and offsets carry on
+    let kept = Session::find_by_id(&pool, kept_id).await.unwrap().unwrap();
+    assert_eq!(kept.status, "active");
+    let mut resumed = after.attach_from(kept_id, user.id, Some(0)).await.unwrap();
+    assert!(resumed.live_offset() >= offset_before);
+    assert!(String::from_utf8_lossy(&resumed.scrollback).contains("set_still_here"));
+
+    after.send_input(kept_id, user.id, b"echo again_$HIVE_MARK\n").await.unwrap();
+    assert!(
+        wait_for_output(&mut resumed.receiver, "again_still_here").await,
+        "Restored session did not land in the same shell"
+    );
+
+    // Closing for real ends the tmux session too
+    after.close_session(kept_id).await.unwrap();
+    assert!(
+        !tmux_has_session(&after, user.id, keyed.id, kept_id).await,
+        "tmux session outlived the closed session"
+    );
+}
+
+// Interrupting looks at every unfinished fleet run, so it stays out of fleet_test.rs, whose
+// runs are in flight while other tests there run
+#[tokio::test]
+async fn test_unfinished_fleet_runs_are_interrupted() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+
+    let mut connections = Vec::new();
+    for name in ["done", "busy", "queued"] {
+        let connection = Connection::create(
+            &pool, user.id, name, "localhost", 2222, "testuser", None, None,
+        )
+        .await
+        .unwrap();
+        connections.push(connection);
+    }
+
+    let (finished, _) = FleetRun::create(&pool, user.id, "true", &connections).await.unwrap();
+    FleetRun::finish(&pool, finished.id).await.unwrap();
+
+    // The previous process had finished one host and was running another
+    let (run, mut hosts) = FleetRun::create(&pool, user.id, "uptime", &connections).await.unwrap();
+    hosts[0].status = "succeeded".into();
+    hosts[0].exit_code = Some(0);
+    hosts[1].status = "running".into();
+    for host in &hosts[..2] {
+        host.save(&pool).await.unwrap();
+    }
+
+    assert!(FleetRun::interrupt_unfinished(&pool).await.unwrap() >= 1);
+
+    let run = FleetRun::find_by_id(&pool, run.id).await.unwrap().unwrap();
+    assert_eq!(run.status, "interrupted");
+    assert!(run.finished_at.is_some());
+    let hosts = FleetRunHost::list_for_run(&pool, run.id).await.unwrap();
+    let statuses: Vec<_> = hosts.iter().map(|host| host.status.as_str()).collect();
+    assert_eq!(statuses, vec!["succeeded", "interrupted", "interrupted"]);
+    assert!(hosts[1].error.is_some() && hosts[2].finished_at.is_some());
+
+    // Finished runs are left as they were
+    let finished = FleetRun::find_by_id(&pool, finished.id).await.unwrap().unwrap();
+    assert_eq!(finished.status, "complete");
+    let hosts = FleetRunHost::list_for_run(&pool, finished.id).await.unwrap();
+    assert!(hosts.iter().all(|host| host.status == "pending"));
+}
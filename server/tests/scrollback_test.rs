@@ -0,0 +1,362 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use sqlx::PgPool;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Duration;
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use uuid::Uuid;
+
+use hive_server::api::{ApiKeyAuthLayer, SessionsService};
+use hive_server::db::{
+    create_pool, run_migrations, ApiKey, Connection, ScrollbackChunk, Session, User,
+};
+use hive_server::proto::sessions_client::SessionsClient;
+use hive_server::proto::sessions_server::SessionsServer;
+use hive_server::proto::{
+    get_scrollback_request, ByteRange, GetScrollbackRequest, ScrollbackPage, TimeRange,
+};
+use hive_server::terminal::{OutputLog, ScrollbackRetention, SessionManager, SessionOptions};
+
+// Retention passes are global, so every session they could touch stays within these limits
+// except the ones a test trims on purpose.
+const SESSION_CAP: u64 = 64 * 1024;
+
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("sbtest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+async fn create_test_connection(pool: &PgPool, user_id: Uuid) -> Connection {
+    let name = format!("sbconn_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    Connection::create(pool, user_id, &name, "localhost", 2222, "testuser", None, None)
+        .await
+        .unwrap()
+}
+
+fn with_api_key<T>(message: T, api_key: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", api_key).parse().unwrap());
+    request
+}
+
+async fn get_scrollback(
+    client: &mut SessionsClient<tonic::transport::Channel>,
+    api_key: &str,
+    session_id: Uuid,
+    range: Option<get_scrollback_request::Range>,
+) -> Result<Vec<ScrollbackPage>, tonic::Status> {
+    let request = GetScrollbackRequest {
+        session_id: session_id.to_string(),
+        range,
+    };
+    let mut stream = client.get_scrollback(with_api_key(request, api_key)).await?.into_inner();
+
+    let mut pages = Vec::new();
+    while let Some(page) = stream.next().await {
+        pages.push(page?);
+    }
+    Ok(pages)
+}
+
+#[tokio::test]
+async fn test_scrollback_chunks_are_compressed() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let session = Session::create(&pool, user.id, connection.id).await.unwrap();
+
+    let data = "build step ok\r\n".repeat(3000).into_bytes();
+    ScrollbackChunk::append(&pool, session.id, &data).await.unwrap();
+
+    let (compressed, stored, size): (bool, i32, i32) = sqlx::query_as(
+        "SELECT compressed, LENGTH(data), size FROM scrollback_chunks WHERE session_id = $1",
+    )
+    .bind(session.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(compressed);
+    assert!((stored as usize) < data.len() / 10, "Repetitive output should compress well");
+    assert_eq!(size as usize, data.len());
+
+    // Reads are transparent
+    assert_eq!(ScrollbackChunk::get_all(&pool, session.id).await.unwrap(), data);
+    assert_eq!(ScrollbackChunk::total_size(&pool, session.id).await.unwrap(), data.len());
+    let from_offset = ScrollbackChunk::get_from_offset(&pool, session.id, 100).await.unwrap();
+    assert_eq!(from_offset, &data[100..]);
+
+    ScrollbackChunk::delete_for_session(&pool, session.id).await.unwrap();
+    Session::close(&pool, session.id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_scrollback_retention_keeps_offsets() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let session = Session::create(&pool, user.id, connection.id).await.unwrap();
+
+    // Three 40KB chunks; only the newest fits under the session cap
+    let chunk = |byte: u8| vec![byte; 40 * 1024];
+    for (i, byte) in [b'a', b'b', b'c'].into_iter().enumerate() {
+        let offset = (i * 40 * 1024) as u64;
+        ScrollbackChunk::append_at(&pool, session.id, offset, &chunk(byte)).await.unwrap();
+    }
+
+    let retention = ScrollbackRetention {
+        max_session_bytes: Some(SESSION_CAP),
+        ..Default::default()
+    };
+    let summary = retention.apply(&pool).await.unwrap();
+    assert!(summary.over_session_cap >= 2);
+
+    // What remains keeps its original offsets
+    let newest = 80 * 1024;
+    assert_eq!(ScrollbackChunk::first_offset(&pool, session.id).await.unwrap(), Some(newest));
+    assert_eq!(ScrollbackChunk::end_offset(&pool, session.id).await.unwrap(), 120 * 1024);
+
+    let (start, data) = ScrollbackChunk::read_range(&pool, session.id, 0, u64::MAX).await.unwrap();
+    assert_eq!(start, newest, "Range must report where stored output resumes");
+    assert_eq!(data, chunk(b'c'));
+
+    let (start, data) =
+        ScrollbackChunk::read_range(&pool, session.id, newest + 10, newest + 20).await.unwrap();
+    assert_eq!(start, newest + 10);
+    assert_eq!(data, vec![b'c'; 10]);
+
+    // Age limit removes everything written before the cutoff
+    sqlx::query("UPDATE scrollback_chunks SET created_at = NOW() - INTERVAL '2 days' WHERE session_id = $1")
+        .bind(session.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let retention = ScrollbackRetention {
+        max_age: Some(Duration::from_secs(24 * 3600)),
+        ..Default::default()
+    };
+    retention.apply(&pool).await.unwrap();
+    assert_eq!(ScrollbackChunk::first_offset(&pool, session.id).await.unwrap(), None);
+
+    // Offsets carry on even with nothing left stored
+    assert_eq!(ScrollbackChunk::end_offset(&pool, session.id).await.unwrap(), 120 * 1024);
+    ScrollbackChunk::append(&pool, session.id, b"next").await.unwrap();
+    let (start, data) = ScrollbackChunk::read_range(&pool, session.id, 0, u64::MAX).await.unwrap();
+    assert_eq!((start, data.as_slice()), (120 * 1024, b"next".as_slice()));
+
+    Session::close(&pool, session.id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_before_retained_output_is_truncated() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    // Produce more output than the server keeps in memory
+    let session = manager.get_session(session_id).await.unwrap();
+    {
+        let session = session.lock().await;
+        session.send(b"seq 1 80000\n").await.unwrap();
+    }
+
+    let mut tail = Vec::new();
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while !String::from_utf8_lossy(&tail).contains("\n80000\r\n") {
+            match output_rx.recv().await {
+                Ok(chunk) => tail.extend_from_slice(&chunk.data),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => panic!("Session ended early"),
+            }
+            let excess = tail.len().saturating_sub(64);
+            tail.drain(..excess);
+        }
+    })
+    .await
+    .expect("Timeout waiting for seq output");
+
+    manager.flush_scrollback().await;
+    let retention = ScrollbackRetention {
+        max_session_bytes: Some(SESSION_CAP),
+        ..Default::default()
+    };
+    retention.apply(&pool).await.unwrap();
+
+    // The start of the session is gone, and the resume says so
+    let resumed = manager.attach_from(session_id, user.id, Some(0)).await.unwrap();
+    assert_eq!(resumed.truncated_from, Some(0));
+    assert!(resumed.scrollback_offset > 0);
+    assert!(String::from_utf8_lossy(&resumed.scrollback).contains("\n80000\r\n"));
+
+    // Resuming from output that is still available is not truncated
+    let end = resumed.live_offset();
+    let resumed = manager.attach_from(session_id, user.id, Some(end - 10)).await.unwrap();
+    assert_eq!(resumed.truncated_from, None);
+    assert_eq!(resumed.scrollback_offset, end - 10);
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_get_scrollback_ranges() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let session = Session::create(&pool, user.id, connection.id).await.unwrap();
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "scrollback-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other.id, "scrollback-test", &other_key).await.unwrap();
+
+    // Three 10000-byte chunks: "a" at 0, "b" at 10000, "c" at 20000
+    for (i, byte) in [b'a', b'b', b'c'].into_iter().enumerate() {
+        ScrollbackChunk::append_at(&pool, session.id, i as u64 * 10000, &[byte; 10000])
+            .await
+            .unwrap();
+    }
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50055".parse().unwrap();
+    let server_pool = pool.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(server_pool, manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = SessionsClient::connect("http://[::1]:50055").await.unwrap();
+
+    // Whole history, in order, with the stored size on the first page
+    let pages = get_scrollback(&mut client, &raw_key, session.id, None).await.unwrap();
+    assert_eq!(pages.len(), 3);
+    let info = pages[0].info.expect("First page carries the totals");
+    assert_eq!((info.total_size, info.start_offset, info.end_offset), (30000, 0, 30000));
+    assert!(pages[1..].iter().all(|page| page.info.is_none()));
+    let all: Vec<u8> = pages.iter().flat_map(|page| page.data.clone()).collect();
+    assert_eq!(all, ScrollbackChunk::get_all(&pool, session.id).await.unwrap());
+
+    // Byte range slices the chunks at its edges
+    let range = get_scrollback_request::Range::Bytes(ByteRange {
+        start: 5000,
+        end: 25000,
+    });
+    let pages = get_scrollback(&mut client, &raw_key, session.id, Some(range)).await.unwrap();
+    let spans: Vec<_> = pages.iter().map(|page| (page.offset, page.data.len())).collect();
+    assert_eq!(spans, vec![(5000, 5000), (10000, 10000), (20000, 5000)]);
+    assert_eq!(pages[2].data[0], b'c');
+
+    // Time range selects chunks by when they were stored
+    sqlx::query(
+        "UPDATE scrollback_chunks SET created_at = NOW() - INTERVAL '2 days' \
+         WHERE session_id = $1 AND start_offset = 0",
+    )
+    .bind(session.id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let since = (chrono::Utc::now() - chrono::Duration::days(1)).to_rfc3339();
+    let range = get_scrollback_request::Range::Time(TimeRange {
+        start: since,
+        end: String::new(),
+    });
+    let pages = get_scrollback(&mut client, &raw_key, session.id, Some(range)).await.unwrap();
+    let offsets: Vec<_> = pages.iter().map(|page| page.offset).collect();
+    assert_eq!(offsets, vec![10000, 20000]);
+
+    // An empty range still reports the totals
+    let range = get_scrollback_request::Range::Bytes(ByteRange {
+        start: 40000,
+        end: 0,
+    });
+    let pages = get_scrollback(&mut client, &raw_key, session.id, Some(range)).await.unwrap();
+    assert_eq!(pages.len(), 1);
+    assert!(pages[0].data.is_empty());
+    assert_eq!(pages[0].info.as_ref().unwrap().end_offset, 30000);
+
+    // Other users cannot read it
+    let status = get_scrollback(&mut client, &other_key, session.id, None).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    ScrollbackChunk::delete_for_session(&pool, session.id).await.unwrap();
+    Session::close(&pool, session.id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_output_lost_before_storage_is_reported() {
+    // Once persistence falls too far behind, the oldest unstored bytes are dropped and
+    // storage carries on after them
+    let log = OutputLog::new(16);
+    log.append(&vec![b'x'; 17 * 1024 * 1024]);
+    assert_eq!(log.end_offset(), 17 * 1024 * 1024);
+    assert_eq!(log.unpersisted(8).unwrap().offset, 1024 * 1024);
+
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let session = Session::create(&pool, user.id, connection.id).await.unwrap();
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "scrollback-test", &raw_key).await.unwrap();
+
+    // Output in [10000, 20000) never made it to the database
+    ScrollbackChunk::append_at(&pool, session.id, 0, &[b'a'; 10000]).await.unwrap();
+    ScrollbackChunk::append_at(&pool, session.id, 20000, &[b'c'; 10000]).await.unwrap();
+
+    // Reads never splice across the gap
+    let (start, data) = ScrollbackChunk::read_range(&pool, session.id, 0, u64::MAX).await.unwrap();
+    assert_eq!((start, data), (20000, vec![b'c'; 10000]));
+    let (start, data) = ScrollbackChunk::read_range(&pool, session.id, 5000, 15000).await.unwrap();
+    assert_eq!((start, data), (5000, vec![b'a'; 5000]));
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50071".parse().unwrap();
+    let server_pool = pool.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(server_pool, manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut client = SessionsClient::connect("http://[::1]:50071").await.unwrap();
+
+    // Pages say how much is missing before them
+    let pages = get_scrollback(&mut client, &raw_key, session.id, None).await.unwrap();
+    let spans: Vec<_> = pages.iter().map(|page| (page.offset, page.lost)).collect();
+    assert_eq!(spans, vec![(0, 0), (20000, 10000)]);
+
+    let range = get_scrollback_request::Range::Bytes(ByteRange {
+        start: 15000,
+        end: 0,
+    });
+    let pages = get_scrollback(&mut client, &raw_key, session.id, Some(range)).await.unwrap();
+    let spans: Vec<_> = pages.iter().map(|page| (page.offset, page.lost)).collect();
+    assert_eq!(spans, vec![(20000, 5000)]);
+
+    ScrollbackChunk::delete_for_session(&pool, session.id).await.unwrap();
+    Session::close(&pool, session.id).await.unwrap();
+}
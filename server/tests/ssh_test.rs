@@ -1,5 +1,15 @@
-use hive_server::ssh::SshSession;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hive_server::db::{create_pool, run_migrations, KnownHost, User};
+use hive_server::ssh::{parse_known_hosts, HostKeyPolicy, HostKeyVerifier, SshSession};
+use hive_server::HiveError;
+use hmac::{Hmac, Mac};
+use russh::keys::key::KeyPair;
+use russh::keys::PublicKeyBase64;
+use sha1::Sha1;
+use sqlx::PgPool;
 use tokio::time::Duration;
+use uuid::Uuid;
 
 // SSH test container settings from docker-compose.yml
 const SSH_HOST: &str = "localhost";
@@ -7,8 +17,29 @@ const SSH_PORT: u16 = 2222;
 const SSH_USER: &str = "testuser";
 const SSH_PASS: &str = "testpass";
 
+async fn setup_db() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+async fn create_test_user(pool: &PgPool) -> User {
+    let username = format!("sshtest_{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    User::create(pool, &username).await.unwrap()
+}
+
+async fn test_verifier(policy: HostKeyPolicy) -> (PgPool, User, HostKeyVerifier) {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let verifier = HostKeyVerifier::new(pool.clone(), user.id, None, SSH_HOST, SSH_PORT, policy);
+    (pool, user, verifier)
+}
+
 #[tokio::test]
 async fn test_ssh_connection() {
+    let (_pool, _user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
+
     // Connect to SSH test container
     let session = SshSession::connect(
         SSH_HOST,
@@ -17,6 +48,7 @@ async fn test_ssh_connection() {
         SSH_PASS,
         80,
         24,
+        verifier,
     )
     .await;
 
@@ -37,6 +69,7 @@ async fn test_ssh_connection() {
 
 #[tokio::test]
 async fn test_ssh_send_command() {
+    let (_pool, _user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
     let session = match SshSession::connect(
         SSH_HOST,
         SSH_PORT,
@@ -44,6 +77,7 @@ async fn test_ssh_send_command() {
         SSH_PASS,
         80,
         24,
+        verifier,
     )
     .await {
         Ok(s) => s,
@@ -65,6 +99,7 @@ async fn test_ssh_send_command() {
 
 #[tokio::test]
 async fn test_ssh_resize() {
+    let (_pool, _user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
     let session = match SshSession::connect(
         SSH_HOST,
         SSH_PORT,
@@ -72,6 +107,7 @@ async fn test_ssh_resize() {
         SSH_PASS,
         80,
         24,
+        verifier,
     )
     .await {
         Ok(s) => s,
@@ -90,6 +126,7 @@ async fn test_ssh_resize() {
 
 #[tokio::test]
 async fn test_ssh_auth_failure() {
+    let (_pool, _user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
     let result = SshSession::connect(
         SSH_HOST,
         SSH_PORT,
@@ -97,6 +134,7 @@ async fn test_ssh_auth_failure() {
         "wrongpassword",
         80,
         24,
+        verifier,
     )
     .await;
 
@@ -112,3 +150,127 @@ async fn test_ssh_auth_failure() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_host_key_trust_on_first_use() {
+    let (pool, user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
+
+    let session = match SshSession::connect(
+        SSH_HOST, SSH_PORT, SSH_USER, SSH_PASS, 80, 24, verifier.clone(),
+    )
+    .await
+    {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Skipping test - SSH container not available: {}", e);
+            return;
+        }
+    };
+    session.close().await.expect("Failed to close session");
+
+    // First connection recorded the key
+    let known = KnownHost::find_for_host(&pool, user.id, None, SSH_HOST, SSH_PORT as i32)
+        .await
+        .unwrap();
+    assert_eq!(known.len(), 1);
+    assert!(known[0].fingerprint.starts_with("SHA256:"));
+
+    // Second connection is verified against it
+    let session = SshSession::connect(SSH_HOST, SSH_PORT, SSH_USER, SSH_PASS, 80, 24, verifier)
+        .await
+        .expect("Known host key should be accepted");
+    session.close().await.expect("Failed to close session");
+}
+
+#[tokio::test]
+async fn test_host_key_mismatch() {
+    let (pool, user, verifier) = test_verifier(HostKeyPolicy::TrustOnFirstUse).await;
+
+    // Pin a key the server does not have
+    let other = KeyPair::generate_ed25519().clone_public_key().unwrap();
+    KnownHost::create(
+        &pool,
+        user.id,
+        None,
+        SSH_HOST,
+        SSH_PORT as i32,
+        "ssh-ed25519",
+        &other.public_key_base64(),
+        &format!("SHA256:{}", other.fingerprint()),
+    )
+    .await
+    .unwrap();
+
+    let result = SshSession::connect(SSH_HOST, SSH_PORT, SSH_USER, SSH_PASS, 80, 24, verifier).await;
+
+    match result {
+        Ok(_) => panic!("Changed host key must be rejected"),
+        Err(HiveError::HostKeyMismatch { expected, actual, .. }) => {
+            assert_eq!(expected, format!("SHA256:{}", other.fingerprint()));
+            assert_ne!(expected, actual);
+        }
+        Err(e) => println!("Skipping test - SSH container not available: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn test_host_key_strict_rejects_unknown() {
+    let (pool, user, verifier) = test_verifier(HostKeyPolicy::Strict).await;
+
+    let result = SshSession::connect(SSH_HOST, SSH_PORT, SSH_USER, SSH_PASS, 80, 24, verifier).await;
+
+    match result {
+        Ok(_) => panic!("Unknown host key must be rejected in strict mode"),
+        Err(HiveError::HostKeyUnknown { fingerprint, .. }) => {
+            assert!(fingerprint.starts_with("SHA256:"));
+        }
+        Err(e) => println!("Skipping test - SSH container not available: {}", e),
+    }
+
+    // Nothing is learned in strict mode
+    let known = KnownHost::find_for_host(&pool, user.id, None, SSH_HOST, SSH_PORT as i32)
+        .await
+        .unwrap();
+    assert!(known.is_empty());
+}
+
+#[test]
+fn test_parse_known_hosts() {
+    let key = KeyPair::generate_ed25519().clone_public_key().unwrap();
+    let key_b64 = key.public_key_base64();
+
+    // Hashed entry for [example.com]:2200, as written by `ssh-keygen -H`
+    let salt = b"01234567890123456789";
+    let mut mac = Hmac::<Sha1>::new_from_slice(salt).unwrap();
+    mac.update(b"[example.com]:2200");
+    let hashed = format!(
+        "|1|{}|{}",
+        BASE64.encode(salt),
+        BASE64.encode(mac.finalize().into_bytes())
+    );
+
+    let content = format!(
+        "# comment\n\
+         plain.example,[alt.example]:2222 ssh-ed25519 {key} laptop\n\
+         {hashed} ssh-ed25519 {key}\n\
+         @cert-authority *.example ssh-ed25519 {key}\n\
+         broken-line\n",
+        key = key_b64,
+        hashed = hashed,
+    );
+
+    let (lines, skipped) = parse_known_hosts(&content);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(skipped, 2);
+
+    assert_eq!(
+        lines[0].plain_hosts(),
+        vec![("plain.example".to_string(), 22), ("alt.example".to_string(), 2222)]
+    );
+    assert!(lines[0].matches("alt.example", 2222));
+    assert!(!lines[0].matches("alt.example", 22));
+
+    assert!(lines[1].plain_hosts().is_empty());
+    assert!(lines[1].matches("example.com", 2200));
+    assert!(!lines[1].matches("example.com", 22));
+}
@@ -2,8 +2,32 @@ use sqlx::PgPool;
 use tokio::time::Duration;
 use uuid::Uuid;
 
-use hive_server::db::{create_pool, run_migrations, Connection, ScrollbackChunk, Session, User};
-use hive_server::terminal::SessionManager;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+
+use hive_server::api::{ApiKeyAuthLayer, SessionsService};
+use hive_server::crypto::MasterKey;
+use hive_server::db::{
+    create_pool, run_migrations, ApiKey, Connection, ConnectionEnv, ConnectionPtyMode,
+    ConnectionVariable, ScrollbackChunk, Session, SshKey, Upload, User,
+};
+use hive_server::proto::sessions_client::SessionsClient;
+use hive_server::proto::sessions_server::SessionsServer;
+use hive_server::proto::terminal_client::TerminalClient;
+use hive_server::proto::terminal_server::TerminalServer;
+use hive_server::proto::{
+    exec_output, terminal_input, terminal_output, BroadcastRequest, CloseSessionRequest,
+    CreateSessionGroupRequest, CreateSessionRequest, DeleteSessionGroupRequest, Empty, ExecExit,
+    ExecRequest, FileUpload, Resize, ResumeSessionRequest, SuspendSessionRequest, TerminalInput,
+    UpdateSessionGroupRequest,
+};
+use hive_server::terminal::{
+    expand_variables, LinkState, ReaperPolicy, ReconnectPolicy, SessionEnd, SessionManager,
+    SessionOptions, TerminalService,
+};
 
 async fn setup_db() -> PgPool {
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -33,6 +57,14 @@ async fn create_test_connection(pool: &PgPool, user_id: Uuid) -> Connection {
     .unwrap()
 }
 
+fn with_api_key<T>(message: T, api_key: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", api_key).parse().unwrap());
+    request
+}
+
 #[tokio::test]
 async fn test_session_manager_create_session() {
     let pool = setup_db().await;
@@ -43,7 +75,7 @@ async fn test_session_manager_create_session() {
 
     // Create a session
     let result = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await;
 
     assert!(result.is_ok(), "Failed to create session: {:?}", result);
@@ -87,7 +119,7 @@ async fn test_session_manager_send_command() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, mut output_rx) = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -135,7 +167,7 @@ async fn test_session_manager_multi_client_attach() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, mut output_rx1) = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -196,7 +228,7 @@ async fn test_session_manager_resize() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, _output_rx) = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -222,7 +254,7 @@ async fn test_session_manager_auth_check() {
 
     // User1 creates session
     let (session_id, _output_rx) = manager
-        .create_session(user1.id, connection.id, 80, 24, "testpass")
+        .create_session(user1.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -243,7 +275,7 @@ async fn test_session_manager_invalid_password() {
 
     // Try to create session with wrong password
     let result = manager
-        .create_session(user.id, connection.id, 80, 24, "wrongpassword")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "wrongpassword")
         .await;
 
     assert!(result.is_err(), "Should fail with wrong password");
@@ -260,7 +292,7 @@ async fn test_session_manager_connection_not_owned() {
 
     // User2 tries to create session with User1's connection
     let result = manager
-        .create_session(user2.id, connection.id, 80, 24, "testpass")
+        .create_session(user2.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await;
 
     assert!(result.is_err(), "User2 should not be able to use User1's connection");
@@ -277,7 +309,7 @@ async fn test_scrollback_storage() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, mut output_rx) = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -376,6 +408,83 @@ async fn test_scrollback_large_data() {
     Session::close(&pool, session.id).await.unwrap();
 }
 
+#[tokio::test]
+async fn test_scrollback_append_at_offset() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+    let session = Session::create(&pool, user.id, connection.id).await.unwrap();
+
+    ScrollbackChunk::append_at(&pool, session.id, 0, b"first ").await.unwrap();
+    ScrollbackChunk::append_at(&pool, session.id, 6, b"second").await.unwrap();
+    assert_eq!(ScrollbackChunk::end_offset(&pool, session.id).await.unwrap(), 12);
+
+    // Offsets inside a chunk slice it; earlier chunks are skipped
+    let from_offset = ScrollbackChunk::get_from_offset(&pool, session.id, 9).await.unwrap();
+    assert_eq!(from_offset, b"ond");
+
+    // Plain appends continue after the last chunk
+    ScrollbackChunk::append(&pool, session.id, b"!").await.unwrap();
+    let all_data = ScrollbackChunk::get_all(&pool, session.id).await.unwrap();
+    assert_eq!(all_data, b"first second!");
+
+    ScrollbackChunk::delete_for_session(&pool, session.id).await.unwrap();
+    Session::close(&pool, session.id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_scrollback_writer_batches_and_flushes_on_close() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let session = manager.get_session(session_id).await.unwrap();
+    {
+        let session = session.lock().await;
+        session.send(b"seq 1 20000\n").await.unwrap();
+    }
+
+    // Collect the whole burst as clients see it
+    let mut stream = Vec::new();
+    let mut packets = 0;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !String::from_utf8_lossy(&stream).contains("\n20000\r\n") {
+            let chunk = output_rx.recv().await.unwrap();
+            stream.extend_from_slice(&chunk.data);
+            packets += 1;
+        }
+    })
+    .await
+    .expect("Timeout waiting for seq output");
+
+    // Closing right away still persists everything
+    manager.close_session(session_id).await.unwrap();
+
+    let persisted = ScrollbackChunk::get_all(&pool, session_id).await.unwrap();
+    assert!(persisted.starts_with(&stream), "Persisted scrollback is missing output");
+
+    // Many SSH packets were written as a few large chunks
+    let rows: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM scrollback_chunks WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert!(
+        (rows as usize) < packets,
+        "Expected batched writes, got {} rows for {} packets",
+        rows,
+        packets
+    );
+}
+
 #[tokio::test]
 async fn test_session_recovery() {
     let pool = setup_db().await;
@@ -385,7 +494,7 @@ async fn test_session_recovery() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, mut output_rx1) = manager
-        .create_session(user.id, connection.id, 80, 24, "testpass")
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -427,6 +536,65 @@ async fn test_session_recovery() {
     manager.close_session(session_id).await.unwrap();
 }
 
+#[tokio::test]
+async fn test_attach_from_offset() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let session = manager.get_session(session_id).await.unwrap();
+    {
+        let session = session.lock().await;
+        session.send(b"echo FIRST_MARKER; echo SECOND_MARKER\n").await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    // Live chunks carry contiguous absolute offsets from the start of the session
+    let mut stream = Vec::new();
+    while let Ok(chunk) = output_rx.try_recv() {
+        assert_eq!(chunk.offset, stream.len() as u64, "Output offsets must be contiguous");
+        stream.extend_from_slice(&chunk.data);
+    }
+    let text = String::from_utf8_lossy(&stream);
+    assert!(text.contains("SECOND_MARKER"), "Expected command output, got: {}", text);
+
+    // Resume halfway through: exactly the remaining bytes are replayed
+    let resume_at = stream.len() as u64 / 2;
+    let resumed = manager
+        .attach_from(session_id, user.id, Some(resume_at))
+        .await
+        .unwrap();
+    assert_eq!(resumed.scrollback_offset, resume_at);
+    assert_eq!(resumed.scrollback, stream[resume_at as usize..]);
+    assert_eq!(resumed.live_offset(), stream.len() as u64);
+
+    // Live output continues where the replay stopped
+    let mut receiver = resumed.receiver;
+    {
+        let session = session.lock().await;
+        session.send(b"echo THIRD_MARKER\n").await.unwrap();
+    }
+    let chunk = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("Timeout waiting for live output")
+        .unwrap();
+    assert_eq!(chunk.offset, stream.len() as u64);
+
+    // Without an offset only live output is delivered
+    let live_only = manager.attach_from(session_id, user.id, None).await.unwrap();
+    assert!(live_only.scrollback.is_empty());
+    assert!(live_only.scrollback_offset >= chunk.end());
+
+    manager.close_session(session_id).await.unwrap();
+}
+
 #[tokio::test]
 async fn test_scrollback_auth_check() {
     let pool = setup_db().await;
@@ -437,7 +605,7 @@ async fn test_scrollback_auth_check() {
     let manager = SessionManager::new(pool.clone());
 
     let (session_id, _output_rx) = manager
-        .create_session(user1.id, connection.id, 80, 24, "testpass")
+        .create_session(user1.id, connection.id, SessionOptions::new(80, 24), "testpass")
         .await
         .unwrap();
 
@@ -453,3 +621,1892 @@ async fn test_scrollback_auth_check() {
 
     manager.close_session(session_id).await.unwrap();
 }
+
+#[tokio::test]
+async fn test_session_manager_end_session_notifies() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let mut end_rx = {
+        let session = manager.get_session(session_id).await.unwrap();
+        let session = session.lock().await;
+        session.subscribe_end()
+    };
+    assert!(end_rx.borrow().is_none());
+
+    manager
+        .end_session(session_id, SessionEnd::new("maintenance"))
+        .await
+        .unwrap();
+
+    // Attached clients learn why the session ended
+    tokio::time::timeout(Duration::from_secs(5), end_rx.changed())
+        .await
+        .expect("Timeout waiting for session end")
+        .unwrap();
+    assert_eq!(end_rx.borrow().as_ref().unwrap().reason, "maintenance");
+
+    // SSH connection is torn down, so the output stream ends
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Err(tokio::sync::broadcast::error::RecvError::Closed) = output_rx.recv().await {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "SSH output was not closed");
+
+    assert!(manager.get_session(session_id).await.is_none());
+    let db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "closed");
+}
+
+#[tokio::test]
+async fn test_sessions_close_notifies_attached_clients() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "close-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50054".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(
+                server_pool,
+                server_manager.clone(),
+            )))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut sessions = SessionsClient::connect("http://[::1]:50054").await.unwrap();
+    let session = sessions
+        .create(with_api_key(
+            CreateSessionRequest {
+                connection_id: connection.id.to_string(),
+                password: "testpass".into(),
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Create should succeed")
+        .into_inner();
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50054").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    input_tx
+        .send(TerminalInput {
+            session_id: session.id.clone(),
+            payload: None,
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    sessions
+        .close(with_api_key(
+            CloseSessionRequest {
+                id: session.id.clone(),
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Close should succeed");
+
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = output.next().await {
+            if let Some(terminal_output::Payload::Closed(closed)) = message.payload {
+                return Some(closed);
+            }
+        }
+        None
+    })
+    .await
+    .expect("Timeout waiting for SessionClosed")
+    .expect("Stream ended without SessionClosed");
+
+    assert_eq!(closed.session_id, session.id);
+    assert_eq!(closed.reason, "Session closed by user");
+
+    // The live session is gone, not just the database row
+    let session_id = Uuid::parse_str(&session.id).unwrap();
+    assert!(manager.get_session(session_id).await.is_none());
+    let db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "closed");
+    drop(input_tx);
+}
+
+#[tokio::test]
+async fn test_remote_shell_exit_is_recorded() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let mut end_rx = {
+        let session = manager.get_session(session_id).await.unwrap();
+        let session = session.lock().await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        session.send(b"exit 3\n").await.unwrap();
+        session.subscribe_end()
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), end_rx.changed())
+        .await
+        .expect("Timeout waiting for shell exit")
+        .unwrap();
+
+    let end = end_rx.borrow().clone().unwrap();
+    assert_eq!(end.exit_code, Some(3));
+    assert_eq!(end.exit_signal, None);
+    assert_eq!(end.reason, "Shell exited with status 3");
+
+    // Manager entry is removed without anyone calling close
+    assert!(manager.get_session(session_id).await.is_none());
+
+    let mut db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    for _ in 0..20 {
+        if db_session.status == "closed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    }
+    assert_eq!(db_session.status, "closed");
+    assert_eq!(db_session.exit_code, Some(3));
+    assert_eq!(db_session.exit_signal, None);
+}
+
+#[tokio::test]
+async fn test_user_close_does_not_record_exit() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    manager.close_session(session_id).await.unwrap();
+
+    // Give the monitor a chance to observe the channel close
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "closed");
+    assert_eq!(db_session.exit_code, None);
+}
+
+#[tokio::test]
+async fn test_upload_file_over_sftp() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    // The test SSH server shares this machine's filesystem
+    let upload_dir = tempfile::TempDir::new().unwrap();
+    let manager = SessionManager::new(pool.clone())
+        .with_upload_dir(upload_dir.path().join("nested").to_str().unwrap());
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let first = manager
+        .upload_file(session_id, user.id, "../screen shot.png", b"first")
+        .await
+        .unwrap();
+    let second = manager
+        .upload_file(session_id, user.id, "screen shot.png", b"second")
+        .await
+        .unwrap();
+
+    // Names are reduced to one component and never overwrite each other
+    let dir = upload_dir.path().join("nested").canonicalize().unwrap();
+    assert_eq!(first.filename, "screen shot.png");
+    assert_eq!(first.path, dir.join("screen shot.png").to_str().unwrap());
+    assert_eq!(second.path, dir.join("screen shot-1.png").to_str().unwrap());
+    assert_eq!(std::fs::read(&first.path).unwrap(), b"first");
+    assert_eq!(std::fs::read(&second.path).unwrap(), b"second");
+
+    let uploads = Upload::list_for_session(&pool, session_id).await.unwrap();
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[1].size_bytes, 6);
+
+    // SFTP traffic stays out of the terminal, and the shell keeps working
+    while output_rx.try_recv().is_ok() {}
+    {
+        let session = manager.get_session(session_id).await.unwrap();
+        let session = session.lock().await;
+        session.send(b"echo AFTER_UPLOAD\n").await.unwrap();
+    }
+    let mut output = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !String::from_utf8_lossy(&output).contains("AFTER_UPLOAD\r\n") {
+            output.extend_from_slice(&output_rx.recv().await.unwrap());
+        }
+    })
+    .await
+    .expect("Shell stopped responding after upload");
+
+    // Only the owner may upload
+    let result = manager.upload_file(session_id, other.id, "x.txt", b"x").await;
+    assert!(result.is_err(), "Other users must not upload into the session");
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_attach_file_upload_pastes_path() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "upload-test", &raw_key).await.unwrap();
+
+    let upload_dir = tempfile::TempDir::new().unwrap();
+    let manager = Arc::new(
+        SessionManager::new(pool.clone()).with_upload_dir(upload_dir.path().to_str().unwrap()),
+    );
+    let addr: SocketAddr = "[::1]:50056".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50056").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: None,
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: Some(terminal_input::Payload::File(FileUpload {
+                filename: "it's.log".into(),
+                data: b"log line".to_vec(),
+                paste_path: true,
+            })),
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+
+    // The upload is acknowledged and its quoted path typed into the shell
+    let mut uploaded = None;
+    let mut echoed = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = output.next().await {
+            match message.payload {
+                Some(terminal_output::Payload::File(file)) => uploaded = Some(file),
+                Some(terminal_output::Payload::Data(data)) => echoed.extend_from_slice(&data),
+                _ => {}
+            }
+            if uploaded.is_some() && String::from_utf8_lossy(&echoed).contains("s.log'") {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Timeout waiting for upload");
+
+    let uploaded = uploaded.expect("FileUploaded was not sent");
+    assert_eq!(uploaded.filename, "it's.log");
+    assert_eq!(std::fs::read(&uploaded.path).unwrap(), b"log line");
+    assert!(String::from_utf8_lossy(&echoed).contains("'\\''s.log'"));
+
+    manager.close_session(session_id).await.unwrap();
+    drop(input_tx);
+}
+
+#[tokio::test]
+async fn test_attach_rejects_oversized_resize() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "resize-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50073".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50073").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    let input = |payload| TerminalInput {
+        session_id: session_id.to_string(),
+        payload: Some(payload),
+        last_seen_offset: None,
+        snapshot: false,
+    };
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: None,
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    let huge = Resize { cols: 65535, rows: 65535 };
+    input_tx.send(input(terminal_input::Payload::Resize(huge))).await.unwrap();
+    input_tx.send(input(terminal_input::Payload::Data(b"stty size\n".to_vec()))).await.unwrap();
+
+    // The resize is refused and the terminal keeps its size
+    let mut refused = None;
+    let mut echoed = String::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = output.next().await {
+            match message.payload {
+                Some(terminal_output::Payload::Error(error)) => refused = Some(error),
+                Some(terminal_output::Payload::Data(data)) => {
+                    echoed.push_str(&String::from_utf8_lossy(&data))
+                }
+                _ => {}
+            }
+            if refused.is_some() && echoed.contains("24 80") {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Timeout waiting for the terminal size");
+    assert_eq!(refused.expect("Resize was not refused").code, "INVALID_RESIZE");
+
+    manager.close_session(session_id).await.unwrap();
+    drop(input_tx);
+}
+
+/// Collect an Exec stream into stdout, stderr and the final exit frame
+async fn collect_exec(
+    stream: &mut tonic::Streaming<hive_server::proto::ExecOutput>,
+) -> (Vec<u8>, Vec<u8>, Option<ExecExit>) {
+    let (mut stdout, mut stderr, mut exit) = (Vec::new(), Vec::new(), None);
+    while let Some(message) = stream.next().await {
+        match message.unwrap().payload {
+            Some(exec_output::Payload::Stdout(data)) => stdout.extend_from_slice(&data),
+            Some(exec_output::Payload::Stderr(data)) => stderr.extend_from_slice(&data),
+            Some(exec_output::Payload::Exit(e)) => exit = Some(e),
+            None => {}
+        }
+    }
+    (stdout, stderr, exit)
+}
+
+#[tokio::test]
+async fn test_exec_separates_streams_and_reports_exit() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other_user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "exec-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other_user.id, "exec-test", &other_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50061".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50061").await.unwrap();
+    let exec_request = |command: &str, timeout_seconds: u32| ExecRequest {
+        connection_id: connection.id.to_string(),
+        command: command.to_string(),
+        password: "testpass".to_string(),
+        timeout_seconds,
+    };
+
+    // stdout and stderr arrive separately, followed by the exit status
+    let mut stream = terminal
+        .exec(with_api_key(exec_request("echo out; echo err >&2; exit 3", 0), &raw_key))
+        .await
+        .expect("Exec should start")
+        .into_inner();
+    let (stdout, stderr, exit) =
+        tokio::time::timeout(Duration::from_secs(10), collect_exec(&mut stream))
+            .await
+            .expect("Timeout waiting for exec");
+    assert_eq!(stdout, b"out\n");
+    assert_eq!(stderr, b"err\n");
+    let exit = exit.expect("Exit frame was not sent");
+    assert_eq!(exit.exit_code, Some(3));
+    assert!(!exit.timed_out);
+
+    // A command running past its timeout is terminated
+    let started = std::time::Instant::now();
+    let mut stream = terminal
+        .exec(with_api_key(exec_request("echo started; sleep 30", 1), &raw_key))
+        .await
+        .unwrap()
+        .into_inner();
+    let (stdout, _, exit) =
+        tokio::time::timeout(Duration::from_secs(10), collect_exec(&mut stream))
+            .await
+            .expect("Timed out command should end the stream");
+    assert_eq!(stdout, b"started\n");
+    assert!(exit.expect("Exit frame was not sent").timed_out);
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    // Dropping the stream cancels the command
+    let marker = tempfile::TempDir::new().unwrap();
+    let marker_path = marker.path().join("finished");
+    let command = format!("echo started; sleep 2 && touch {}", marker_path.display());
+    let mut stream = terminal
+        .exec(with_api_key(exec_request(&command, 0), &raw_key))
+        .await
+        .unwrap()
+        .into_inner();
+    stream.next().await.unwrap().unwrap();
+    drop(stream);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert!(!marker_path.exists(), "Cancelled command kept running");
+
+    // Connections belong to their owner
+    let err = terminal
+        .exec(with_api_key(exec_request("true", 0), &other_key))
+        .await
+        .expect_err("Other user should not run commands");
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+}
+
+/// Wait until `marker` shows up in a session's output
+async fn wait_for_output(
+    output_rx: &mut tokio::sync::broadcast::Receiver<hive_server::terminal::OutputChunk>,
+    marker: &str,
+) -> bool {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let mut output = String::new();
+    while std::time::Instant::now() < deadline {
+        match tokio::time::timeout(Duration::from_millis(100), output_rx.recv()).await {
+            Ok(Ok(chunk)) => {
+                output.push_str(&String::from_utf8_lossy(&chunk));
+                if output.contains(marker) {
+                    return true;
+                }
+            }
+            Ok(Err(_)) => return false,
+            Err(_) => continue,
+        }
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_broadcast_to_session_group() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let other_user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "broadcast-test", &raw_key).await.unwrap();
+    let other_key = ApiKey::generate_key();
+    ApiKey::create(&pool, other_user.id, "broadcast-test", &other_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let (first, mut first_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let (second, mut second_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let (third, mut third_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    let addr: SocketAddr = "[::1]:50064".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(server_pool, server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut sessions = SessionsClient::connect("http://[::1]:50064").await.unwrap();
+    let group = sessions
+        .create_group(with_api_key(
+            CreateSessionGroupRequest {
+                name: "web".into(),
+                session_ids: vec![first.to_string(), second.to_string(), first.to_string()],
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("CreateGroup should succeed")
+        .into_inner();
+    assert_eq!(group.session_ids, vec![first.to_string(), second.to_string()]);
+
+    // Input reaches every member and nothing else
+    let response = sessions
+        .broadcast(with_api_key(
+            BroadcastRequest {
+                group_id: group.id.clone(),
+                data: b"echo BCAST_$((6 * 7))\n".to_vec(),
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Broadcast should succeed")
+        .into_inner();
+    assert!(response.results.iter().all(|r| r.ok && r.error.is_empty()));
+    assert!(wait_for_output(&mut first_rx, "BCAST_42").await);
+    assert!(wait_for_output(&mut second_rx, "BCAST_42").await);
+    while let Ok(chunk) = third_rx.try_recv() {
+        assert!(!String::from_utf8_lossy(&chunk).contains("BCAST"));
+    }
+
+    // Members change at runtime
+    let group = sessions
+        .update_group(with_api_key(
+            UpdateSessionGroupRequest {
+                id: group.id.clone(),
+                add_session_ids: vec![third.to_string()],
+                remove_session_ids: vec![first.to_string()],
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("UpdateGroup should succeed")
+        .into_inner();
+    assert_eq!(group.session_ids, vec![second.to_string(), third.to_string()]);
+
+    // A member that ends leaves the group
+    manager.close_session(second).await.unwrap();
+    let groups = sessions
+        .list_groups(with_api_key(Empty {}, &raw_key))
+        .await
+        .unwrap()
+        .into_inner()
+        .groups;
+    assert_eq!(groups[0].session_ids, vec![third.to_string()]);
+
+    // A member refusing input is reported without stopping the others
+    sessions
+        .update_group(with_api_key(
+            UpdateSessionGroupRequest {
+                id: group.id.clone(),
+                add_session_ids: vec![first.to_string()],
+                remove_session_ids: vec![],
+            },
+            &raw_key,
+        ))
+        .await
+        .unwrap();
+    manager.suspend_session(third).await.unwrap();
+    let response = sessions
+        .broadcast(with_api_key(
+            BroadcastRequest {
+                group_id: group.id.clone(),
+                data: b"echo AGAIN_$((6 * 7))\n".to_vec(),
+            },
+            &raw_key,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.results.len(), 2);
+    assert_eq!(response.results[0].session_id, third.to_string());
+    assert!(!response.results[0].ok);
+    assert!(!response.results[0].error.is_empty());
+    assert!(response.results[1].ok);
+    assert!(wait_for_output(&mut first_rx, "AGAIN_42").await);
+
+    // Groups belong to their owner, and so do the sessions added to them
+    let err = sessions
+        .broadcast(with_api_key(
+            BroadcastRequest {
+                group_id: group.id.clone(),
+                data: b"id\n".to_vec(),
+            },
+            &other_key,
+        ))
+        .await
+        .expect_err("Other user should not broadcast to the group");
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+    let err = sessions
+        .create_group(with_api_key(
+            CreateSessionGroupRequest {
+                name: "stolen".into(),
+                session_ids: vec![third.to_string()],
+            },
+            &other_key,
+        ))
+        .await
+        .expect_err("Other user should not group these sessions");
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+    let groups = sessions
+        .list_groups(with_api_key(Empty {}, &other_key))
+        .await
+        .unwrap()
+        .into_inner()
+        .groups;
+    assert!(groups.is_empty());
+
+    sessions
+        .delete_group(with_api_key(DeleteSessionGroupRequest { id: group.id.clone() }, &raw_key))
+        .await
+        .expect("DeleteGroup should succeed");
+    let groups = sessions
+        .list_groups(with_api_key(Empty {}, &raw_key))
+        .await
+        .unwrap()
+        .into_inner()
+        .groups;
+    assert!(groups.is_empty());
+
+    manager.close_session(first).await.unwrap();
+    manager.close_session(third).await.unwrap();
+}
+
+/// Connection whose startup command runs in `mode`, with `name=world` as a variable
+async fn create_startup_connection(
+    pool: &PgPool,
+    user_id: Uuid,
+    command: &str,
+    mode: &str,
+    prompt: Option<&str>,
+) -> Connection {
+    let connection = create_test_connection(pool, user_id).await;
+    Connection::update(
+        pool,
+        connection.id,
+        &connection.name,
+        "localhost",
+        2222,
+        "testuser",
+        None,
+        Some(command),
+    )
+    .await
+    .unwrap();
+    ConnectionVariable::replace_for_connection(
+        pool,
+        connection.id,
+        &[("name".to_string(), "world".to_string())],
+    )
+    .await
+    .unwrap();
+    Connection::set_startup(pool, connection.id, mode, prompt).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn test_expand_variables() {
+    let variables: std::collections::HashMap<String, String> =
+        [("host".to_string(), "web1".to_string()), ("dir".to_string(), "/srv".to_string())]
+            .into_iter()
+            .collect();
+
+    assert_eq!(expand_variables("cd ${dir} && ssh ${host}", &variables), "cd /srv && ssh web1");
+    // Unknown names and unterminated references are left for the shell
+    assert_eq!(expand_variables("echo ${HOME} ${dir", &variables), "echo ${HOME} ${dir");
+    assert_eq!(expand_variables("${dir}${dir}", &variables), "/srv/srv");
+    assert_eq!(expand_variables("no variables", &variables), "no variables");
+}
+
+#[tokio::test]
+async fn test_startup_command_typed_with_variables() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    // Multi-line scripts run line by line
+    let connection = create_startup_connection(
+        &pool,
+        user.id,
+        "GREETING=hello\necho TYPED_${GREETING}_${name}_$((20 + 22))",
+        "typed",
+        None,
+    )
+    .await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    assert!(wait_for_output(&mut output_rx, "TYPED_hello_world_42").await);
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_startup_command_after_prompt() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_startup_connection(
+        &pool,
+        user.id,
+        "echo PROMPTED_${name}_$((6 * 7))",
+        "prompt",
+        Some(r"[$#] "),
+    )
+    .await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    assert!(wait_for_output(&mut output_rx, "PROMPTED_world_42").await);
+    manager.close_session(session_id).await.unwrap();
+
+    // A prompt that never appears means the command is not typed
+    let connection = create_startup_connection(
+        &pool,
+        user.id,
+        "echo NEVER_$((6 * 7))",
+        "prompt",
+        Some("no-such-prompt> "),
+    )
+    .await;
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(1);
+    while std::time::Instant::now() < deadline {
+        if let Ok(Ok(chunk)) =
+            tokio::time::timeout(Duration::from_millis(100), output_rx.recv()).await
+        {
+            assert!(!String::from_utf8_lossy(&chunk).contains("NEVER"));
+        }
+    }
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_startup_command_exec_replaces_shell() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_startup_connection(
+        &pool,
+        user.id,
+        "echo EXEC_${name}_$((6 * 7)); sleep 0.5; exit 3",
+        "exec",
+        None,
+    )
+    .await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+
+    assert!(wait_for_output(&mut output_rx, "EXEC_world_42").await);
+
+    // The command is the session: its exit ends it
+    let mut db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    for _ in 0..40 {
+        if db_session.status == "closed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    }
+    assert_eq!(db_session.status, "closed");
+    assert_eq!(db_session.exit_code, Some(3));
+    assert!(manager.get_session(session_id).await.is_none());
+}
+
+#[tokio::test]
+async fn test_session_uses_connection_terminal_settings() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    Connection::set_term_type(&pool, connection.id, Some("vt100")).await.unwrap();
+    ConnectionEnv::replace_for_connection(
+        &pool,
+        connection.id,
+        &[("HIVE_GREETING".to_string(), "hello".to_string())],
+    )
+    .await
+    .unwrap();
+    ConnectionPtyMode::replace_for_connection(
+        &pool,
+        connection.id,
+        &[("VERASE".to_string(), 127), ("IUTF8".to_string(), 1)],
+    )
+    .await
+    .unwrap();
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    manager
+        .send_input(session_id, user.id, b"echo SETTINGS_${TERM}_${HIVE_GREETING}\n")
+        .await
+        .unwrap();
+    assert!(wait_for_output(&mut output_rx, "SETTINGS_vt100_hello").await);
+    manager.close_session(session_id).await.unwrap();
+
+    // Commands get the environment without a PTY
+    let mut process = manager
+        .exec(user.id, connection.id, "testpass", "echo EXEC_${HIVE_GREETING}")
+        .await
+        .unwrap();
+    let mut stdout = Vec::new();
+    while let Some(event) = process.next().await {
+        if let hive_server::terminal::ExecEvent::Stdout(data) = event {
+            stdout.extend_from_slice(&data);
+        }
+    }
+    process.close().await;
+    assert_eq!(String::from_utf8_lossy(&stdout), "EXEC_hello\n");
+}
+
+#[tokio::test]
+async fn test_sessions_create_with_terminal_options() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "create-options-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50066".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(server_pool, server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut sessions = SessionsClient::connect("http://[::1]:50066").await.unwrap();
+    let session = sessions
+        .create(with_api_key(
+            CreateSessionRequest {
+                connection_id: connection.id.to_string(),
+                password: "testpass".into(),
+                cols: 132,
+                rows: 40,
+                pixel_width: 1320,
+                pixel_height: 800,
+                term_type: "vt220".into(),
+                name: "deploy".into(),
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Create should succeed")
+        .into_inner();
+    assert_eq!(session.name, "deploy");
+    assert_eq!((session.cols, session.rows), (132, 40));
+    assert_eq!((session.pixel_width, session.pixel_height), (1320, 800));
+    assert_eq!(session.term_type, "vt220");
+
+    // The shell starts at the requested size and TERM
+    let session_id = Uuid::parse_str(&session.id).unwrap();
+    let mut output_rx = {
+        let session = manager.get_session(session_id).await.unwrap();
+        let session = session.lock().await;
+        session.subscribe()
+    };
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    manager
+        .send_input(session_id, user.id, b"echo SIZE_$(stty size | tr ' ' x)_${TERM}\n")
+        .await
+        .unwrap();
+    assert!(wait_for_output(&mut output_rx, "SIZE_40x132_vt220").await);
+
+    let stored = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(stored.name.as_deref(), Some("deploy"));
+    assert_eq!((stored.cols, stored.rows), (132, 40));
+    assert_eq!(stored.term_type.as_deref(), Some("vt220"));
+    manager.close_session(session_id).await.unwrap();
+
+    // Without a size the session falls back to 80x24 and the connection's TERM
+    let session = sessions
+        .create(with_api_key(
+            CreateSessionRequest {
+                connection_id: connection.id.to_string(),
+                password: "testpass".into(),
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect("Create should succeed")
+        .into_inner();
+    assert_eq!((session.cols, session.rows), (80, 24));
+    assert_eq!(session.term_type, "xterm-256color");
+    assert_eq!(session.name, "");
+    manager.close_session(Uuid::parse_str(&session.id).unwrap()).await.unwrap();
+
+    let status = sessions
+        .create(with_api_key(
+            CreateSessionRequest {
+                connection_id: connection.id.to_string(),
+                password: "testpass".into(),
+                cols: 100_000,
+                ..Default::default()
+            },
+            &raw_key,
+        ))
+        .await
+        .expect_err("Oversized terminals are rejected");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+/// Forwards TCP connections to the test SSH server, and can drop them to simulate a network
+/// failure
+struct FlakyProxy {
+    port: u16,
+    connections: Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    listener: tokio::task::JoinHandle<()>,
+}
+
+impl FlakyProxy {
+    async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections: Arc<std::sync::Mutex<Vec<_>>> = Arc::default();
+
+        let tracked = connections.clone();
+        let listener = tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let forward = tokio::spawn(async move {
+                    if let Ok(mut outbound) = tokio::net::TcpStream::connect("localhost:2222").await
+                    {
+                        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    }
+                });
+                tracked.lock().unwrap().push(forward);
+            }
+        });
+
+        Self {
+            port,
+            connections,
+            listener,
+        }
+    }
+
+    /// Drop every forwarded connection; new ones are still accepted
+    fn cut(&self) {
+        for forward in self.connections.lock().unwrap().drain(..) {
+            forward.abort();
+        }
+    }
+
+    /// Drop every connection and refuse new ones
+    fn stop(&self) {
+        self.listener.abort();
+        self.cut();
+    }
+}
+
+fn fast_reconnect(max_attempts: u32) -> ReconnectPolicy {
+    ReconnectPolicy {
+        max_attempts,
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(400),
+    }
+}
+
+#[test]
+fn test_reconnect_policy_backoff() {
+    let policy = ReconnectPolicy {
+        max_attempts: 10,
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+    };
+    let delays: Vec<_> = (1..=6).map(|attempt| policy.delay(attempt).as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+    assert_eq!(policy.delay(100), Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn test_dropped_connection_reconnects_same_session() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let proxy = FlakyProxy::start().await;
+    let connection = Connection::create(
+        &pool, user.id, "flaky", "127.0.0.1", proxy.port as i32, "testuser", None, None,
+    )
+    .await
+    .unwrap();
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "reconnect-test", &raw_key).await.unwrap();
+
+    let manager = SessionManager::new(pool.clone()).with_reconnect_policy(fast_reconnect(5));
+    let manager = Arc::new(manager);
+    let addr: SocketAddr = "[::1]:50067".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    manager.send_input(session_id, user.id, b"echo before_$((40+2))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "before_42").await);
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50067").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: None,
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    proxy.cut();
+
+    // Attached clients hear about the reconnect, then output carries on at the same offsets.
+    // Input typed in between is refused without ending the client's input stream.
+    let mut refused = None;
+    let (reconnecting, reconnected) = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut reconnecting = None;
+        while let Some(Ok(message)) = output.next().await {
+            match message.payload {
+                Some(terminal_output::Payload::Reconnecting(frame)) => {
+                    if reconnecting.is_none() {
+                        input_tx
+                            .send(TerminalInput {
+                                session_id: session_id.to_string(),
+                                payload: Some(terminal_input::Payload::Data(b"x".to_vec())),
+                                last_seen_offset: None,
+                                snapshot: false,
+                            })
+                            .await
+                            .unwrap();
+                    }
+                    reconnecting.get_or_insert(frame);
+                }
+                Some(terminal_output::Payload::Error(error)) => refused = Some(error),
+                Some(terminal_output::Payload::Reconnected(frame)) => {
+                    return (reconnecting, Some(frame));
+                }
+                Some(terminal_output::Payload::Closed(closed)) => {
+                    panic!("Session closed instead of reconnecting: {}", closed.reason)
+                }
+                _ => {}
+            }
+        }
+        (reconnecting, None)
+    })
+    .await
+    .expect("Timeout waiting for reconnect");
+    let reconnecting = reconnecting.expect("No reconnecting frame");
+    assert_eq!(reconnecting.attempt, 1);
+    assert_eq!(reconnecting.retry_in_ms, 100);
+    assert!(reconnected.expect("No reconnected frame").attempts >= 1);
+    assert_eq!(refused.expect("Input was not refused").code, "SESSION_RECONNECTING");
+
+    let offset_before = output_rx.try_recv().map(|chunk| chunk.end()).unwrap_or(0);
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: Some(terminal_input::Payload::Data(b"echo after_$((40+2))\n".to_vec())),
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let resumed = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut seen = String::new();
+        while let Some(Ok(message)) = output.next().await {
+            if let Some(terminal_output::Payload::Data(data)) = message.payload {
+                assert!(message.offset >= offset_before);
+                seen.push_str(&String::from_utf8_lossy(&data));
+                if seen.contains("after_42") {
+                    return true;
+                }
+            }
+        }
+        false
+    })
+    .await
+    .expect("Timeout waiting for output after reconnect");
+    assert!(resumed, "No output from the reconnected shell");
+
+    let db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "active");
+    manager.close_session(session_id).await.unwrap();
+    drop(input_tx);
+}
+
+/// Drop a session's connection once and count how often its startup command ran, judging by
+/// the lines it appends to a file on the test server
+async fn startup_runs_across_reconnect(pool: &PgPool, mode: &str, persistence: &str) -> usize {
+    let user = create_test_user(pool).await;
+    let proxy = FlakyProxy::start().await;
+    let runs_file = format!("/tmp/hive-startup-{}", Uuid::new_v4());
+    // The test server hands a dropped connection to the shell as end of input rather than a
+    // hangup, which would end the shell in tmux too; ignoring it keeps the shell alive as a
+    // hangup would
+    let command = match mode {
+        "exec" => format!("echo run >> {}; exec bash -o ignoreeof", runs_file),
+        _ => format!("set -o ignoreeof; echo run >> {}", runs_file),
+    };
+    let connection = Connection::create(
+        pool, user.id, "flaky", "127.0.0.1", proxy.port as i32, "testuser", None,
+        Some(&command),
+    )
+    .await
+    .unwrap();
+    Connection::set_startup(pool, connection.id, mode, None).await.unwrap();
+    Connection::set_persistence(pool, connection.id, persistence).await.unwrap();
+    let runs = || {
+        std::fs::read_to_string(&runs_file).map(|runs| runs.lines().count()).unwrap_or(0)
+    };
+
+    let manager = SessionManager::new(pool.clone()).with_reconnect_policy(fast_reconnect(5));
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    manager.send_input(session_id, user.id, b"echo before_$((40+2))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "before_42").await, "Shell never started");
+    assert_eq!(runs(), 1, "Startup command did not run once at creation");
+
+    let session = manager.get_session(session_id).await.unwrap();
+    let mut link = session.lock().await.subscribe_link();
+    drop(session);
+    proxy.cut();
+    link.changed().await.unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        link.wait_for(|state| matches!(state, LinkState::Connected)),
+    )
+    .await
+    .expect("Session never reconnected")
+    .unwrap();
+
+    // A typed startup command goes in ahead of this, so it has run once the echo shows up
+    manager.send_input(session_id, user.id, b"echo after_$((40+2))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "after_42").await, "Shell did not come back");
+    let runs = runs();
+
+    manager.close_session(session_id).await.unwrap();
+    std::fs::remove_file(&runs_file).ok();
+    runs
+}
+
+#[tokio::test]
+async fn test_reconnect_runs_startup_only_in_a_fresh_shell() {
+    let pool = setup_db().await;
+
+    // Without a multiplexer the old shell is gone, so every mode runs its command again
+    assert_eq!(startup_runs_across_reconnect(&pool, "typed", "none").await, 2);
+    assert_eq!(startup_runs_across_reconnect(&pool, "exec", "none").await, 2);
+    // Reattaching to tmux brings back the shell the command already ran in
+    assert_eq!(startup_runs_across_reconnect(&pool, "typed", "tmux").await, 1);
+    assert_eq!(startup_runs_across_reconnect(&pool, "exec", "tmux").await, 1);
+}
+
+#[tokio::test]
+async fn test_reconnect_gives_up_and_ends_session() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let proxy = FlakyProxy::start().await;
+    let connection = Connection::create(
+        &pool, user.id, "gone", "127.0.0.1", proxy.port as i32, "testuser", None, None,
+    )
+    .await
+    .unwrap();
+
+    let manager = SessionManager::new(pool.clone()).with_reconnect_policy(fast_reconnect(2));
+    let (session_id, _output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let session = manager.get_session(session_id).await.unwrap();
+    let (mut link, mut ended) = {
+        let session = session.lock().await;
+        (session.subscribe_link(), session.subscribe_end())
+    };
+    drop(session);
+
+    proxy.stop();
+
+    link.changed().await.unwrap();
+    assert!(matches!(*link.borrow(), LinkState::Reconnecting { attempt: 1, .. }));
+    // Input is refused rather than written to the dead connection
+    assert!(manager.send_input(session_id, user.id, b"ls\n").await.is_err());
+
+    tokio::time::timeout(Duration::from_secs(5), ended.changed())
+        .await
+        .expect("Session never gave up")
+        .unwrap();
+    assert_eq!(ended.borrow().as_ref().unwrap().reason, "SSH connection lost");
+    assert!(manager.get_session(session_id).await.is_none());
+
+    let mut db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    for _ in 0..20 {
+        if db_session.status == "closed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    }
+    assert_eq!(db_session.status, "closed");
+}
+
+#[tokio::test]
+async fn test_session_activity_is_recorded() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let created = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+
+    manager.send_input(session_id, user.id, b"echo active_$((40+2))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "active_42").await);
+
+    let mut db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    for _ in 0..20 {
+        if db_session.last_activity > created.last_activity {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    }
+    assert!(db_session.last_activity > created.last_activity);
+
+    let session = manager.get_session(session_id).await.unwrap();
+    assert!(session.lock().await.idle_for() < Duration::from_secs(5));
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reaper_closes_unattended_then_idle_sessions() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (attached_id, _) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let (unattended_id, _) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let attached = manager.attach_from(attached_id, user.id, None).await.unwrap();
+    let mut unattended_end = manager
+        .get_session(unattended_id)
+        .await
+        .unwrap()
+        .lock()
+        .await
+        .subscribe_end();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Only sessions without a client count as unattended
+    let policy = ReaperPolicy {
+        close_unattended_after: Some(Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let summary = policy.apply(&manager).await;
+    assert_eq!((summary.unattended, summary.idle), (1, 0));
+    assert!(manager.get_session(unattended_id).await.is_none());
+    assert!(manager.get_session(attached_id).await.is_some());
+    unattended_end.changed().await.unwrap();
+    let reason = unattended_end.borrow().as_ref().unwrap().reason.clone();
+    assert!(reason.starts_with("No client attached for"), "{}", reason);
+    let db_session = Session::find_by_id(&pool, unattended_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "closed");
+
+    // Attached sessions still close once idle
+    let policy = ReaperPolicy {
+        close_idle_after: Some(Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let summary = policy.apply(&manager).await;
+    assert_eq!((summary.unattended, summary.idle), (0, 1));
+    assert!(manager.get_session(attached_id).await.is_none());
+    let end = attached.ended.borrow().clone().unwrap();
+    assert!(end.reason.starts_with("Idle for"), "{}", end.reason);
+}
+
+#[tokio::test]
+async fn test_suspend_keeps_connection_until_resumed() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "suspend-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50068".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool.clone()))
+            .add_service(SessionsServer::new(SessionsService::new(
+                server_pool,
+                server_manager.clone(),
+            )))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    manager
+        .send_input(session_id, user.id, b"(sleep 2; echo later_$((6*7))) &\n")
+        .await
+        .unwrap();
+
+    // A client stays attached throughout
+    let mut terminal = TerminalClient::connect("http://[::1]:50068").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    let input = |data: &[u8]| TerminalInput {
+        session_id: session_id.to_string(),
+        payload: Some(terminal_input::Payload::Data(data.to_vec())),
+        last_seen_offset: None,
+        snapshot: false,
+    };
+    input_tx
+        .send(TerminalInput { payload: None, ..input(b"") })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    let mut sessions = SessionsClient::connect("http://[::1]:50068").await.unwrap();
+    let suspend = || SuspendSessionRequest { id: session_id.to_string() };
+    let suspended = sessions
+        .suspend(with_api_key(suspend(), &raw_key))
+        .await
+        .expect("Suspend should succeed")
+        .into_inner();
+    assert_eq!(suspended.status, "suspended");
+
+    // The connection stays up and output is still captured, but the session is out of use
+    assert!(manager.get_session(session_id).await.is_some());
+    assert!(manager.send_input(session_id, user.id, b"echo no\n").await.is_err());
+    assert!(manager.attach_from(session_id, user.id, None).await.is_err());
+    let active = Session::list_active_for_user(&pool, user.id).await.unwrap();
+    assert!(active.iter().all(|session| session.id != session_id));
+    assert!(wait_for_output(&mut output_rx, "later_42").await);
+    input_tx.send(input(b"echo typed\n")).await.unwrap();
+    let refused = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = output.next().await {
+            if let Some(terminal_output::Payload::Error(error)) = message.payload {
+                return Some(error);
+            }
+        }
+        None
+    })
+    .await
+    .expect("Timeout waiting for refused input")
+    .expect("Stream ended");
+    assert_eq!(refused.code, "SESSION_SUSPENDED");
+
+    // Suspended sessions do not count as idle
+    let policy = ReaperPolicy {
+        close_idle_after: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    assert_eq!(policy.apply(&manager).await.total(), 0);
+    assert!(manager.get_session(session_id).await.is_some());
+
+    let status = sessions
+        .suspend(with_api_key(suspend(), &raw_key))
+        .await
+        .expect_err("Suspending twice is rejected");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    let resume = || ResumeSessionRequest { id: session_id.to_string(), password: String::new() };
+    let resumed = sessions
+        .resume(with_api_key(resume(), &raw_key))
+        .await
+        .expect("Resume should succeed")
+        .into_inner();
+    assert_eq!(resumed.status, "active");
+    manager.send_input(session_id, user.id, b"echo back_$((1+1))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "back_2").await);
+
+    // The attached client can type again without reattaching
+    input_tx.send(input(b"echo client_$((2+2))\n")).await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "client_4").await);
+
+    let status = sessions
+        .resume(with_api_key(resume(), &raw_key))
+        .await
+        .expect_err("Only suspended sessions resume");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    manager.close_session(session_id).await.unwrap();
+    let status = sessions
+        .suspend(with_api_key(suspend(), &raw_key))
+        .await
+        .expect_err("Closed sessions cannot be suspended");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    drop(input_tx);
+}
+
+// Authorized for testuser on the SSH test container (see docker-compose.yml)
+const TEST_PRIVATE_KEY: &str = include_str!("fixtures/test_ed25519");
+const TEST_PUBLIC_KEY: &str = include_str!("fixtures/test_ed25519.pub");
+
+#[tokio::test]
+async fn test_suspend_detaches_multiplexer() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = MasterKey::parse(&hex::encode([7u8; 32])).unwrap();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    let key = SshKey::create(&pool, user.id, "test", &sealed, TEST_PUBLIC_KEY.trim())
+        .await
+        .unwrap();
+    let connection = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+    Connection::set_persistence(&pool, connection.id, "tmux").await.unwrap();
+
+    let manager = SessionManager::new(pool.clone()).with_master_key(master_key);
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "")
+        .await
+        .unwrap();
+    manager.send_input(session_id, user.id, b"HIVE_MARK=kept\n").await.unwrap();
+    manager.send_input(session_id, user.id, b"echo set_$HIVE_MARK\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "set_kept").await, "Shell never ran");
+
+    // The SSH connection is dropped while tmux keeps the shell
+    let suspended = manager.suspend_session(session_id).await.unwrap();
+    assert_eq!(suspended.status, "suspended");
+    assert!(manager.get_session(session_id).await.is_none());
+
+    let resumed = manager.resume_session(session_id, "").await.unwrap();
+    assert_eq!(resumed.status, "active");
+    let mut attached = manager.attach_from(session_id, user.id, None).await.unwrap();
+    manager.send_input(session_id, user.id, b"echo again_$HIVE_MARK\n").await.unwrap();
+    assert!(
+        wait_for_output(&mut attached.receiver, "again_kept").await,
+        "Resumed session did not land in the same shell"
+    );
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_suspended_multiplexer_session_stays_in_its_groups() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let master_key = MasterKey::parse(&hex::encode([7u8; 32])).unwrap();
+
+    let sealed = master_key.seal(TEST_PRIVATE_KEY.as_bytes()).unwrap();
+    let key = SshKey::create(&pool, user.id, "test", &sealed, TEST_PUBLIC_KEY.trim())
+        .await
+        .unwrap();
+    let connection = Connection::create(
+        &pool, user.id, "keyed", "localhost", 2222, "testuser", Some(key.id), None,
+    )
+    .await
+    .unwrap();
+    Connection::set_persistence(&pool, connection.id, "tmux").await.unwrap();
+
+    let manager = SessionManager::new(pool.clone()).with_master_key(master_key);
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "")
+        .await
+        .unwrap();
+    manager.send_input(session_id, user.id, b"echo ready_$((40+2))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "ready_42").await, "Shell never ran");
+    let group = manager.create_group(user.id, "kept", &[session_id]).await.unwrap();
+
+    // Detaching drops the connection, which the session's monitor notices before the resume
+    manager.suspend_session(session_id).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    manager.resume_session(session_id, "").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert!(manager.get_session(session_id).await.is_some(), "Resumed session was dropped");
+    let groups = manager.list_groups(user.id).await;
+    assert_eq!(groups[0].session_ids, vec![session_id]);
+
+    let mut attached = manager.attach_from(session_id, user.id, None).await.unwrap();
+    let results = manager.broadcast(group.id, user.id, b"echo back_$((40+2))\n").await.unwrap();
+    assert!(results[0].result.is_ok());
+    assert!(wait_for_output(&mut attached.receiver, "back_42").await);
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_attach_with_snapshot_of_alternate_screen() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let manager = SessionManager::new(pool.clone());
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(100, 30), "testpass")
+        .await
+        .unwrap();
+
+    // Set a title and draw on the alternate screen, like a full-screen program
+    manager
+        .send_input(
+            session_id,
+            user.id,
+            b"printf '\\033]2;hive_title\\007\\033[?1049h\\033[2J\\033[5;10HALT_%s' $((3*3))\n",
+        )
+        .await
+        .unwrap();
+    assert!(wait_for_output(&mut output_rx, "ALT_9").await);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut attached = manager.attach_with_snapshot(session_id, user.id).await.unwrap();
+    let snapshot = attached.snapshot.clone().expect("Snapshot requested");
+    assert_eq!((snapshot.cols, snapshot.rows), (100, 30));
+    assert!(snapshot.alternate_screen);
+    assert_eq!(snapshot.title, "hive_title");
+    assert!(String::from_utf8_lossy(&snapshot.contents).contains("ALT_9"));
+    assert!(attached.scrollback.is_empty());
+    assert_eq!(attached.live_offset(), snapshot.offset);
+
+    // Live output continues right after the snapshot
+    manager
+        .send_input(session_id, user.id, b"printf '\\033[?1049l'; echo main_$((1+1))\n")
+        .await
+        .unwrap();
+    let chunk = attached.receiver.recv().await.unwrap();
+    assert_eq!(chunk.offset, snapshot.offset);
+    assert!(wait_for_output(&mut attached.receiver, "main_2").await);
+
+    // Resizing the terminal resizes the emulated screen
+    manager.get_session(session_id).await.unwrap().lock().await.resize(120, 40).await.unwrap();
+    let snapshot = manager.attach_with_snapshot(session_id, user.id).await.unwrap().snapshot;
+    let snapshot = snapshot.unwrap();
+    assert_eq!((snapshot.cols, snapshot.rows), (120, 40));
+    assert!(!snapshot.alternate_screen);
+
+    manager.close_session(session_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_terminal_attach_sends_snapshot() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "snapshot-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50069".parse().unwrap();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    tokio::spawn(async move {
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, mut output_rx) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    manager.send_input(session_id, user.id, b"echo drawn_$((6*7))\n").await.unwrap();
+    assert!(wait_for_output(&mut output_rx, "drawn_42").await);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut terminal = TerminalClient::connect("http://[::1]:50069").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: None,
+            last_seen_offset: Some(0),
+            snapshot: true,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+
+    // The snapshot replaces scrollback, and live data picks up at its offset
+    let first = output.next().await.unwrap().unwrap();
+    let Some(terminal_output::Payload::Snapshot(snapshot)) = first.payload else {
+        panic!("Expected a snapshot first, got {:?}", first.payload);
+    };
+    assert_eq!((snapshot.cols, snapshot.rows), (80, 24));
+    assert!(String::from_utf8_lossy(&snapshot.contents).contains("drawn_42"));
+    assert!(!snapshot.alternate_screen);
+    assert!(snapshot.cursor_visible);
+
+    manager.send_input(session_id, user.id, b"echo live_$((2+3))\n").await.unwrap();
+    let live = output.next().await.unwrap().unwrap();
+    assert!(matches!(live.payload, Some(terminal_output::Payload::Data(_))));
+    assert_eq!(live.offset, first.offset);
+
+    manager.close_session(session_id).await.unwrap();
+    drop(input_tx);
+}
+
+#[tokio::test]
+async fn test_shutdown_disconnects_sessions_with_client_attached() {
+    let pool = setup_db().await;
+    let user = create_test_user(&pool).await;
+    let connection = create_test_connection(&pool, user.id).await;
+
+    let raw_key = ApiKey::generate_key();
+    ApiKey::create(&pool, user.id, "shutdown-test", &raw_key).await.unwrap();
+
+    let manager = Arc::new(SessionManager::new(pool.clone()));
+    let addr: SocketAddr = "[::1]:50070".parse().unwrap();
+    let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+    let (flushed_tx, flushed_rx) = tokio::sync::oneshot::channel();
+    let server_pool = pool.clone();
+    let server_manager = manager.clone();
+    let server = tokio::spawn(async move {
+        let shutdown = async {
+            server_manager.disconnect_on(async { signal_rx.await.unwrap() }).await;
+            flushed_tx.send(()).unwrap();
+        };
+        Server::builder()
+            .layer(ApiKeyAuthLayer::new(server_pool))
+            .add_service(TerminalServer::new(TerminalService::new(server_manager.clone())))
+            .serve_with_shutdown(addr, shutdown)
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (session_id, _) = manager
+        .create_session(user.id, connection.id, SessionOptions::new(80, 24), "testpass")
+        .await
+        .unwrap();
+    let mut terminal = TerminalClient::connect("http://[::1]:50070").await.unwrap();
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(8);
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: None,
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let mut output = terminal
+        .attach(with_api_key(
+            tokio_stream::wrappers::ReceiverStream::new(input_rx),
+            &raw_key,
+        ))
+        .await
+        .expect("Attach should succeed")
+        .into_inner();
+    input_tx
+        .send(TerminalInput {
+            session_id: session_id.to_string(),
+            payload: Some(terminal_input::Payload::Data(b"echo flushed_$((6*7))\n".to_vec())),
+            last_seen_offset: None,
+            snapshot: false,
+        })
+        .await
+        .unwrap();
+    let seen = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut seen = String::new();
+        while !seen.contains("flushed_42") {
+            let message = output.next().await.unwrap().unwrap();
+            if let Some(terminal_output::Payload::Data(data)) = message.payload {
+                seen.push_str(&String::from_utf8_lossy(&data));
+            }
+        }
+    });
+    seen.await.expect("Command never ran");
+
+    // The client stays attached, yet output is written as soon as shutdown starts
+    signal_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), flushed_rx)
+        .await
+        .expect("Shutdown never flushed")
+        .unwrap();
+    let stored = ScrollbackChunk::get_all(&pool, session_id).await.unwrap();
+    assert!(String::from_utf8_lossy(&stored).contains("flushed_42"));
+
+    // The attached stream ends, so serving returns, and the row is left for the next start
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("Server kept waiting for the attached client")
+        .unwrap();
+    assert!(manager.get_session(session_id).await.is_none());
+    let db_session = Session::find_by_id(&pool, session_id).await.unwrap().unwrap();
+    assert_eq!(db_session.status, "active");
+
+    drop(input_tx);
+    Session::close(&pool, session_id).await.unwrap();
+}
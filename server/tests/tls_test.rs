@@ -0,0 +1,209 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair,
+};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tonic::transport::{Certificate as TlsCertificate, Channel, ClientTlsConfig, Identity, Server};
+
+use hive_server::api::AuthService;
+use hive_server::db::{create_pool, run_migrations};
+use hive_server::proto::auth_client::AuthClient;
+use hive_server::proto::auth_server::AuthServer;
+use hive_server::proto::ApiKeyRequest;
+use hive_server::tls::{ReloadingTlsAcceptor, TlsOptions};
+
+struct TestCa {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl TestCa {
+    fn new(name: &str) -> Self {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = params.self_signed(&key).unwrap();
+        Self { cert, key }
+    }
+
+    fn pem(&self) -> String {
+        self.cert.pem()
+    }
+
+    /// Issue a leaf certificate, returning (cert PEM, key PEM)
+    fn issue(&self, name: &str, purpose: ExtendedKeyUsagePurpose) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![purpose];
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn issue_server(&self) -> (String, String) {
+        self.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth)
+    }
+
+    fn issue_client(&self, device: &str) -> (String, String) {
+        self.issue(device, ExtendedKeyUsagePurpose::ClientAuth)
+    }
+}
+
+fn write_server_cert(dir: &Path, ca: &TestCa) {
+    let (cert, key) = ca.issue_server();
+    std::fs::write(dir.join("server.pem"), cert).unwrap();
+    std::fs::write(dir.join("server.key"), key).unwrap();
+}
+
+fn tls_options(dir: &Path) -> TlsOptions {
+    TlsOptions::new(dir.join("server.pem"), dir.join("server.key"))
+}
+
+async fn start_server(acceptor: ReloadingTlsAcceptor) -> SocketAddr {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = create_pool(&database_url).await.unwrap();
+    run_migrations(&pool).await.unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(AuthServer::new(AuthService::new(pool)))
+            .serve_with_incoming(acceptor.incoming(listener))
+            .await
+            .unwrap();
+    });
+
+    addr
+}
+
+/// Make one Auth call over TLS, trusting `ca_pem` and optionally presenting a client identity
+async fn call(addr: SocketAddr, ca_pem: &str, identity: Option<&(String, String)>) -> Result<(), String> {
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(TlsCertificate::from_pem(ca_pem))
+        .domain_name("localhost");
+    if let Some((cert, key)) = identity {
+        tls = tls.identity(Identity::from_pem(cert, key));
+    }
+
+    let channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .map_err(|e| e.to_string())?
+        .connect_timeout(Duration::from_secs(5))
+        .connect()
+        .await
+        .map_err(|e| format!("connect: {:?}", e))?;
+
+    let response = AuthClient::new(channel)
+        .validate_api_key(ApiKeyRequest {
+            api_key: "hive_not_a_real_key".into(),
+        })
+        .await
+        .map_err(|e| format!("call: {}", e))?;
+
+    assert!(!response.into_inner().valid);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_server() {
+    let dir = TempDir::new().unwrap();
+    let ca = TestCa::new("Hive Test CA");
+    write_server_cert(dir.path(), &ca);
+
+    let acceptor = ReloadingTlsAcceptor::new(tls_options(dir.path())).unwrap();
+    let addr = start_server(acceptor).await;
+
+    // Client trusting the issuing CA succeeds
+    call(addr, &ca.pem(), None).await.expect("TLS call should succeed");
+
+    // Client trusting a different CA rejects the server
+    let other = TestCa::new("Other CA");
+    assert!(call(addr, &other.pem(), None).await.is_err());
+
+    // Plaintext clients are not served
+    let plaintext = async {
+        let mut client = AuthClient::connect(format!("http://{}", addr)).await.ok()?;
+        client
+            .validate_api_key(ApiKeyRequest {
+                api_key: "hive_not_a_real_key".into(),
+            })
+            .await
+            .ok()
+    };
+    let plaintext = tokio::time::timeout(Duration::from_secs(5), plaintext).await;
+    assert!(!matches!(plaintext, Ok(Some(_))), "Plaintext call must fail");
+}
+
+#[tokio::test]
+async fn test_mtls_requires_client_certificate() {
+    let dir = TempDir::new().unwrap();
+    let server_ca = TestCa::new("Hive Server CA");
+    let device_ca = TestCa::new("Hive Device CA");
+    write_server_cert(dir.path(), &server_ca);
+    std::fs::write(dir.path().join("devices.pem"), device_ca.pem()).unwrap();
+
+    let options = tls_options(dir.path()).with_client_ca(dir.path().join("devices.pem"));
+    let acceptor = ReloadingTlsAcceptor::new(options).unwrap();
+    let addr = start_server(acceptor).await;
+
+    // Pinned device certificate is accepted
+    let device = device_ca.issue_client("laptop");
+    call(addr, &server_ca.pem(), Some(&device))
+        .await
+        .expect("Device certificate should be accepted");
+
+    // No client certificate
+    assert!(call(addr, &server_ca.pem(), None).await.is_err());
+
+    // Client certificate from an unknown CA
+    let rogue = TestCa::new("Rogue CA").issue_client("laptop");
+    assert!(call(addr, &server_ca.pem(), Some(&rogue)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tls_certificate_hot_reload() {
+    let dir = TempDir::new().unwrap();
+    let old_ca = TestCa::new("Old CA");
+    write_server_cert(dir.path(), &old_ca);
+
+    let options = tls_options(dir.path()).with_reload_interval(Duration::from_millis(100));
+    let acceptor = ReloadingTlsAcceptor::new(options).unwrap();
+    acceptor.spawn_reloader();
+    let addr = start_server(acceptor.clone()).await;
+
+    call(addr, &old_ca.pem(), None).await.expect("Initial certificate should be served");
+
+    // Unchanged files are not reloaded
+    assert!(!acceptor.reload().unwrap());
+
+    // Rotate the certificate on disk; the reloader picks it up without a restart
+    let new_ca = TestCa::new("New CA");
+    write_server_cert(dir.path(), &new_ca);
+
+    let mut rotated = false;
+    for _ in 0..50 {
+        if call(addr, &new_ca.pem(), None).await.is_ok() {
+            rotated = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(rotated, "Renewed certificate was not picked up");
+    assert!(call(addr, &old_ca.pem(), None).await.is_err());
+
+    // A broken renewal keeps the last good certificate
+    std::fs::write(dir.path().join("server.pem"), "not a certificate").unwrap();
+    assert!(acceptor.reload().is_err());
+    call(addr, &new_ca.pem(), None)
+        .await
+        .expect("Last good certificate should still be served");
+}